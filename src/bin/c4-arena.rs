@@ -0,0 +1,396 @@
+use discord_c4::connect4::board::{
+    Board, BoardSize, GameResult, GameStatus, Player, Rules, Variant,
+};
+use discord_c4::connect4::engine::{Engine, EngineError, MonteCarlo};
+use discord_c4::connect4::external_engine::ExternalEngine;
+use discord_c4::connect4::monte_carlo_ai;
+use discord_c4::connect4::solver::Solver;
+
+use rand::seq::SliceRandom;
+
+use std::env;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+const USAGE: &str = "usage: c4-arena [--games N] [--opening-moves N] [--threads N]
+                [--size WIDTHxHEIGHT] [--connect N] [--variant NAME] ENGINE ENGINE
+
+engines:
+  level:N              the bot at level N
+  mc:ROLLOUTS[:EXP]    monte carlo search with the given rollouts and
+                       exploration constant
+  solver:DEPTH         alpha-beta search looking DEPTH moves ahead
+  random               a random legal move
+  external:COMMAND     an external engine, started with the given command,
+                       only for connect four on the standard 7x6 board
+
+variants:
+  standard, popout, fiveinarow, cylinder, each with its usual size and line length
+  unless --size or --connect say otherwise";
+
+// z-score of a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+// An engine along with the description it was given on the command line
+struct Contestant {
+    name: String,
+    engine: Box<dyn Engine>,
+}
+
+struct RandomMover;
+
+impl Engine for RandomMover {
+    fn best_move(&self, board: &Board, _moves: &[u8]) -> Result<u8, EngineError> {
+        random_move(board)
+    }
+
+    fn evaluate(&self, _board: &Board, _moves: &[u8]) -> Result<f64, EngineError> {
+        Ok(0.5)
+    }
+}
+
+fn parse_engine(s: &str) -> Result<Contestant, String> {
+    if let Some(command_line) = s.strip_prefix("external:") {
+        return match ExternalEngine::from_command_line(command_line) {
+            Some(engine) => Ok(Contestant {
+                name: s.to_string(),
+                engine: Box::new(engine),
+            }),
+            None => Err(format!("invalid engine {}", s)),
+        };
+    }
+
+    let parts: Vec<&str> = s.split(':').collect();
+    let engine: Option<Box<dyn Engine>> = match parts.as_slice() {
+        ["random"] => Some(Box::new(RandomMover)),
+        ["level", level] => match u8::from_str(level) {
+            Ok(n) if (monte_carlo_ai::MIN_LEVEL..=monte_carlo_ai::MAX_LEVEL).contains(&n) => {
+                Some(Box::new(MonteCarlo::at_level(n)))
+            }
+            _ => None,
+        },
+        ["mc", rollouts] => match u32::from_str(rollouts) {
+            Ok(rollouts) if rollouts > 1 => Some(Box::new(MonteCarlo {
+                rollouts,
+                exploration: monte_carlo_ai::DEFAULT_EXPLORATION,
+            })),
+            _ => None,
+        },
+        ["mc", rollouts, exploration] => {
+            match (u32::from_str(rollouts), f64::from_str(exploration)) {
+                (Ok(rollouts), Ok(exploration)) if rollouts > 1 && exploration >= 0.0 => {
+                    Some(Box::new(MonteCarlo {
+                        rollouts,
+                        exploration,
+                    }))
+                }
+                _ => None,
+            }
+        }
+        ["solver", depth] => match u8::from_str(depth) {
+            Ok(depth) if depth > 0 => Some(Box::new(Solver { depth })),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    match engine {
+        Some(engine) => Ok(Contestant {
+            name: s.to_string(),
+            engine,
+        }),
+        None => Err(format!("invalid engine {}", s)),
+    }
+}
+
+struct Options {
+    games: usize,
+    opening_moves: usize,
+    threads: usize,
+    rules: Rules,
+    contestants: [Contestant; 2],
+}
+
+// Result of a game for the first engine
+enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+// Results from the point of view of the first engine
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Tally {
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn score(&self) -> f64 {
+        (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.games())
+    }
+
+    // Normal approximation of the 95% confidence interval of the score, taking
+    // each game as an independent sample
+    fn score_interval(&self) -> (f64, f64) {
+        let n = f64::from(self.games());
+        let s = self.score();
+        let variance = (f64::from(self.wins) * (1.0 - s).powi(2)
+            + f64::from(self.draws) * (0.5 - s).powi(2)
+            + f64::from(self.losses) * s.powi(2))
+            / n;
+        let margin = Z_95 * (variance / n).sqrt();
+        ((s - margin).max(0.0), (s + margin).min(1.0))
+    }
+}
+
+fn elo_difference(score: f64) -> f64 {
+    // adding zero turns the -0 of even scores into 0
+    -400.0 * (1.0 / score - 1.0).log10() + 0.0
+}
+
+fn show_elo(elo: f64) -> String {
+    if elo.is_finite() {
+        format!("{:+.0}", elo)
+    } else if elo > 0.0 {
+        "+inf".to_string()
+    } else {
+        "-inf".to_string()
+    }
+}
+
+fn random_move(board: &Board) -> Result<u8, EngineError> {
+    board
+        .legal_moves()
+        .choose(&mut rand::thread_rng())
+        .copied()
+        .ok_or(EngineError::GameOver)
+}
+
+fn random_opening(rules: Rules, opening_moves: usize) -> Vec<u8> {
+    // openings that already decide the game are thrown away
+    loop {
+        let mut board = Board::empty_board_with_rules(rules);
+        let mut opening = vec![];
+        while opening.len() < opening_moves {
+            match random_move(&board) {
+                Ok(m) => {
+                    board.play_move(m);
+                    opening.push(m);
+                }
+                Err(_) => break,
+            }
+        }
+        if let GameStatus::Turn(_) = board.game_status() {
+            return opening;
+        }
+    }
+}
+
+// Plays a game from the given opening, with the first engine as red if
+// first_is_red
+fn play_game(
+    contestants: &[Contestant; 2],
+    rules: Rules,
+    opening: &[u8],
+    first_is_red: bool,
+) -> Outcome {
+    let mut board = Board::empty_board_with_rules(rules);
+    let mut moves = opening.to_vec();
+    for &m in opening {
+        board.play_move(m);
+    }
+
+    loop {
+        match board.game_status() {
+            GameStatus::GameOver(GameResult::Tie) => return Outcome::Draw,
+            GameStatus::GameOver(GameResult::Winner(player)) => {
+                if (player == Player::Red) == first_is_red {
+                    return Outcome::Win;
+                } else {
+                    return Outcome::Loss;
+                }
+            }
+            GameStatus::Turn(player) => {
+                let first_to_move = (player == Player::Red) == first_is_red;
+                let contestant = &contestants[if first_to_move { 0 } else { 1 }];
+                match contestant.engine.best_move(&board, &moves) {
+                    Ok(m) => {
+                        board.play_move(m);
+                        moves.push(m);
+                    }
+                    // an engine that can't move forfeits the game
+                    Err(_) => {
+                        eprintln!("\n{} failed to move and loses", contestant.name);
+                        return if first_to_move {
+                            Outcome::Loss
+                        } else {
+                            Outcome::Win
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_count(flag: &str, value: Option<String>) -> Result<usize, String> {
+    value
+        .and_then(|v| usize::from_str(&v).ok())
+        .ok_or(format!("{} needs a number", flag))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut games = 100;
+    let mut opening_moves = 2;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let (mut size, mut line_length, mut variant) = (None, None, Variant::Standard);
+    let mut contestants = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => games = parse_count(&arg, args.next())?,
+            "--opening-moves" => opening_moves = parse_count(&arg, args.next())?,
+            "--threads" => threads = parse_count(&arg, args.next())?.max(1),
+            "--size" => {
+                size = Some(
+                    args.next()
+                        .and_then(|v| BoardSize::from_str(&v).ok())
+                        .ok_or("--size needs a size like 9x7")?,
+                )
+            }
+            "--variant" => {
+                variant = args
+                    .next()
+                    .and_then(|v| Variant::from_str(&v).ok())
+                    .ok_or("--variant needs one of the variants below")?
+            }
+            "--connect" => {
+                line_length = Some(
+                    args.next()
+                        .and_then(|v| u8::from_str(&v).ok())
+                        .ok_or("--connect needs a number")?,
+                )
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown argument {}", arg)),
+            _ => contestants.push(parse_engine(&arg)?),
+        }
+    }
+
+    let usual = variant.usual_rules();
+    let rules = Rules::new(
+        size.unwrap_or(usual.size),
+        line_length.unwrap_or(usual.line_length),
+    )
+    .ok_or(format!(
+        "lines must have {} to {} pieces and fit on the board",
+        Rules::MIN_LINE_LENGTH,
+        Rules::MAX_LINE_LENGTH
+    ))?;
+    let rules = Rules { variant, ..rules };
+    match <[Contestant; 2]>::try_from(contestants) {
+        Ok(contestants) => Ok(Options {
+            games,
+            opening_moves,
+            threads,
+            rules,
+            contestants,
+        }),
+        Err(_) => Err("exactly two engines are needed".to_string()),
+    }
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    // Games are played in pairs from the same opening, once with each engine
+    // as red, so that neither gets the better half of the openings
+    let openings: Vec<Vec<u8>> = (0..options.games.div_ceil(2))
+        .map(|_| random_opening(options.rules, options.opening_moves))
+        .collect();
+    let next_game = AtomicUsize::new(0);
+    let (results_tx, results_rx) = mpsc::channel();
+
+    let mut tally = Tally::default();
+    thread::scope(|scope| {
+        for _ in 0..options.threads {
+            let results_tx = results_tx.clone();
+            let (next_game, openings, contestants) = (&next_game, &openings, &options.contestants);
+            scope.spawn(move || loop {
+                let game = next_game.fetch_add(1, Ordering::SeqCst);
+                if game >= options.games {
+                    break;
+                }
+                let result = play_game(
+                    contestants,
+                    options.rules,
+                    &openings[game / 2],
+                    game % 2 == 0,
+                );
+                results_tx
+                    .send(result)
+                    .expect("the results are no longer read");
+            });
+        }
+        drop(results_tx);
+
+        for result in results_rx {
+            match result {
+                Outcome::Win => tally.wins += 1,
+                Outcome::Draw => tally.draws += 1,
+                Outcome::Loss => tally.losses += 1,
+            }
+            eprint!(
+                "\r{}/{} games, +{} ={} -{}",
+                tally.games(),
+                options.games,
+                tally.wins,
+                tally.draws,
+                tally.losses
+            );
+        }
+        eprintln!();
+    });
+
+    if tally.games() == 0 {
+        return;
+    }
+    let (low, high) = tally.score_interval();
+    println!(
+        "{} vs {}, {} games of {}",
+        options.contestants[0].name,
+        options.contestants[1].name,
+        tally.games(),
+        options.rules
+    );
+    println!(
+        "wins {}, draws {}, losses {}",
+        tally.wins, tally.draws, tally.losses
+    );
+    println!(
+        "score {:.3} (95% confidence: {:.3} to {:.3})",
+        tally.score(),
+        low,
+        high
+    );
+    println!(
+        "elo difference {} (95% confidence: {} to {})",
+        show_elo(elo_difference(tally.score())),
+        show_elo(elo_difference(low)),
+        show_elo(elo_difference(high))
+    );
+}
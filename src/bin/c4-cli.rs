@@ -0,0 +1,403 @@
+use discord_c4::connect4::board;
+use discord_c4::connect4::board::{
+    Board, BoardSize, GameResult, GameStatus, Glyphs, Player, Rules, Variant,
+};
+use discord_c4::connect4::monte_carlo_ai;
+
+use std::env;
+use std::io;
+use std::io::{BufRead, Write};
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "usage: c4-cli [--level N] [--first | --second] [--size WIDTHxHEIGHT]
+              [--connect N] [--variant standard|popout|fiveinarow|cylinder|fog]
+              [--blockers N] [--handicap N] [--no-color]";
+
+const HELP: &str = "commands:
+  1-9        drop a piece in that column
+  pop N      pop your piece out of the bottom of column N, in popout games
+  undo       take back your last move
+  hint       ask the bot what it would play
+  level N    play against a bot of level N from now on
+  new        start a new game
+  quit       leave";
+
+struct Options {
+    ai_level: u8,
+    human: Player,
+    rules: Rules,
+    blockers: u32,
+    handicap: u32,
+    color: bool,
+}
+
+enum Command {
+    Play(u8),
+    Undo,
+    Hint,
+    Level(u8),
+    New,
+    Help,
+    Quit,
+}
+
+struct Game {
+    board: Board,
+    move_history: Vec<u8>,
+    human: Player,
+}
+
+impl Game {
+    // Each game gets its own blockers, in random columns, and the handicap
+    // pieces are the human's
+    fn new(human: Player, rules: Rules, blockers: u32, handicap: u32) -> Game {
+        let mut board = Board::empty_board_with_rules(rules);
+        board.add_random_blockers(blockers, &mut rand::thread_rng());
+        board.add_handicap(human, handicap);
+        Game {
+            board,
+            move_history: vec![],
+            human,
+        }
+    }
+
+    fn play(&mut self, column: u8) {
+        self.board.play_move(column);
+        self.move_history.push(column);
+    }
+
+    // Takes back moves up to and including the human's last one. The board is
+    // replayed from the start, since finished games can't be undone move by
+    // move.
+    fn take_back(&mut self) -> bool {
+        let start = self.board.starting_position();
+        let human_parity = if start.game_status() == GameStatus::Turn(self.human) {
+            0
+        } else {
+            1
+        };
+        let last_human_move = match (0..self.move_history.len())
+            .rev()
+            .find(|i| i % 2 == human_parity)
+        {
+            None => return false,
+            Some(i) => i,
+        };
+
+        self.move_history.truncate(last_human_move);
+        self.board = start;
+        for &column in self.move_history.iter() {
+            self.board.play_move(column);
+        }
+        true
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        ai_level: 5,
+        human: Player::Red,
+        rules: Rules::STANDARD,
+        blockers: 0,
+        handicap: 0,
+        color: env::var_os("NO_COLOR").is_none(),
+    };
+    let (mut size, mut line_length, mut variant) = (None, None, Variant::Standard);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--first" => options.human = Player::Red,
+            "--second" => options.human = Player::Blue,
+            "--no-color" => options.color = false,
+            "--variant" => {
+                let value = args.next().ok_or("--variant needs a value")?;
+                variant = Variant::from_str(&value)
+                    .map_err(|_| format!("there is no variant called {}", value))?;
+            }
+            "--size" => {
+                let value = args.next().ok_or("--size needs a value")?;
+                size = Some(BoardSize::from_str(&value).map_err(|_| {
+                    format!(
+                        "the size must be like 7x6, with {} to {} columns and {} to {} rows",
+                        BoardSize::MIN_SIDE,
+                        BoardSize::MAX_WIDTH,
+                        BoardSize::MIN_SIDE,
+                        BoardSize::MAX_HEIGHT
+                    )
+                })?);
+            }
+            "--connect" => {
+                let value = args.next().ok_or("--connect needs a value")?;
+                line_length = Some(u8::from_str(&value).map_err(|_| "--connect needs a number")?);
+            }
+            "--blockers" => {
+                let value = args.next().ok_or("--blockers needs a value")?;
+                options.blockers = u32::from_str(&value)
+                    .ok()
+                    .filter(|&count| count <= Board::MAX_BLOCKERS)
+                    .ok_or(format!(
+                        "--blockers needs a number up to {}",
+                        Board::MAX_BLOCKERS
+                    ))?;
+            }
+            "--handicap" => {
+                let value = args.next().ok_or("--handicap needs a value")?;
+                options.handicap = u32::from_str(&value)
+                    .ok()
+                    .filter(|&count| count <= Board::MAX_HANDICAP)
+                    .ok_or(format!(
+                        "--handicap needs a number up to {}",
+                        Board::MAX_HANDICAP
+                    ))?;
+            }
+            "--level" => {
+                let level = args.next().ok_or("--level needs a value")?;
+                options.ai_level = parse_level(&level)?;
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    // the variant's usual board and line unless others are given
+    let usual = variant.usual_rules();
+    let rules = Rules::new(
+        size.unwrap_or(usual.size),
+        line_length.unwrap_or(usual.line_length),
+    )
+    .ok_or(format!(
+        "lines must have {} to {} pieces and fit on the board",
+        Rules::MIN_LINE_LENGTH,
+        Rules::MAX_LINE_LENGTH
+    ))?;
+    options.rules = Rules { variant, ..rules };
+    Ok(options)
+}
+
+fn parse_level(level: &str) -> Result<u8, String> {
+    match u8::from_str(level) {
+        Ok(n) if (monte_carlo_ai::MIN_LEVEL..=monte_carlo_ai::MAX_LEVEL).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "the level must be a number from {} to {}",
+            monte_carlo_ai::MIN_LEVEL,
+            monte_carlo_ai::MAX_LEVEL
+        )),
+    }
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [column] if column.chars().all(|c| c.is_ascii_digit()) => match u8::from_str(column) {
+            Ok(n) if (1..=BoardSize::MAX_WIDTH).contains(&n) => Ok(Command::Play(n - 1)),
+            _ => Err("there is no such column".to_string()),
+        },
+        ["p", column] | ["pop", column] => match u8::from_str(column) {
+            Ok(n) if (1..=BoardSize::MAX_WIDTH).contains(&n) => {
+                Ok(Command::Play(board::pop_move(n - 1)))
+            }
+            _ => Err("there is no such column".to_string()),
+        },
+        ["u"] | ["undo"] => Ok(Command::Undo),
+        ["h"] | ["hint"] => Ok(Command::Hint),
+        ["l", level] | ["level", level] => Ok(Command::Level(parse_level(level)?)),
+        ["n"] | ["new"] => Ok(Command::New),
+        ["?"] | ["help"] => Ok(Command::Help),
+        ["q"] | ["quit"] | ["exit"] => Ok(Command::Quit),
+        _ => Err("unknown command, type help to see them all".to_string()),
+    }
+}
+
+fn show_board(board: &Board, viewer: Player, color: bool) {
+    // the winning line is drawn with diamonds, or lowercase letters, rows
+    // that go around the cylinder end with arrows, and the fog hides the
+    // slots the player can't see
+    let wrap = match board.rules().variant {
+        Variant::Cylinder if color => " ↔",
+        Variant::Cylinder => " <>",
+        _ => "",
+    };
+    let glyphs = if color {
+        Glyphs {
+            red: "\x1b[31m●\x1b[0m",
+            blue: "\x1b[34m●\x1b[0m",
+            green: "\x1b[32m●\x1b[0m",
+            yellow: "\x1b[33m●\x1b[0m",
+            empty: "\x1b[2m·\x1b[0m",
+            blocked: "■",
+            hidden: "\x1b[2m░\x1b[0m",
+            winning_red: "\x1b[1;31m◆\x1b[0m",
+            winning_blue: "\x1b[1;34m◆\x1b[0m",
+            winning_green: "\x1b[1;32m◆\x1b[0m",
+            winning_yellow: "\x1b[1;33m◆\x1b[0m",
+        }
+    } else {
+        Glyphs {
+            red: "X",
+            blue: "O",
+            green: "G",
+            yellow: "Y",
+            empty: ".",
+            blocked: "#",
+            hidden: "?",
+            winning_red: "x",
+            winning_blue: "o",
+            winning_green: "g",
+            winning_yellow: "y",
+        }
+    };
+
+    print!(
+        "{}",
+        board.display_with_winning_line(Some(viewer), &glyphs, " ", " ", wrap, "\n")
+    );
+    let labels: Vec<String> = (1..=board.size().width).map(|n| n.to_string()).collect();
+    println!(" {}", labels.join(" "));
+}
+
+// The verb and the column number of a move, as in play 4 or pop 4
+fn describe_move(m: u8) -> (&'static str, u8) {
+    let verb = if board::is_pop(m) { "pop" } else { "play" };
+    (verb, board::move_column(m) + 1)
+}
+
+fn player_name(player: Player, human: Player) -> &'static str {
+    if player == human {
+        "You"
+    } else {
+        "Connect4Bot"
+    }
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut ai_level = options.ai_level;
+    let mut game = Game::new(
+        options.human,
+        options.rules,
+        options.blockers,
+        options.handicap,
+    );
+
+    println!("Connect Four against Connect4Bot level {}.", ai_level);
+    if options.rules != Rules::STANDARD {
+        println!("Playing {}.", options.rules);
+    }
+    println!("{}", HELP);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        if let GameStatus::Turn(player) = game.board.game_status() {
+            if player != game.human {
+                let m = monte_carlo_ai::ai_move(
+                    &game.board,
+                    monte_carlo_ai::rollouts_at_level(ai_level),
+                )
+                .expect("AI failure :(");
+                let (verb, column) = describe_move(m);
+                println!("\nConnect4Bot {}s {}", verb, column);
+                game.play(m);
+            }
+        }
+
+        show_board(&game.board, game.human, options.color);
+        match game.board.game_status() {
+            GameStatus::GameOver(GameResult::Winner(player)) => {
+                println!(
+                    "{} won! Type new to play again.",
+                    player_name(player, game.human)
+                )
+            }
+            GameStatus::GameOver(GameResult::Tie) => {
+                println!("It's a tie. Type new to play again.")
+            }
+            GameStatus::Turn(_) => {}
+        }
+
+        print!("> ");
+        io::stdout()
+            .flush()
+            .expect("failed to write to the terminal");
+        let line = match lines.next() {
+            None => break,
+            Some(line) => line.expect("failed to read from the terminal"),
+        };
+
+        match parse_command(&line) {
+            Err(message) => println!("{}", message),
+            Ok(Command::Play(m)) => match game.board.game_status() {
+                GameStatus::GameOver(_) => println!("The game is over, type new to play again."),
+                GameStatus::Turn(_) if board::move_column(m) >= game.board.size().width => {
+                    println!("The board only has {} columns.", game.board.size().width)
+                }
+                GameStatus::Turn(_)
+                    if board::is_pop(m) && game.board.rules().variant != Variant::PopOut =>
+                {
+                    println!("Pieces can only be popped in games started with --variant popout.")
+                }
+                GameStatus::Turn(_) if board::is_pop(m) && !game.board.is_move_legal(m) => {
+                    println!(
+                        "Column {} doesn't have one of your pieces at the bottom.",
+                        board::move_column(m) + 1
+                    )
+                }
+                GameStatus::Turn(_) if !game.board.is_move_legal(m) => {
+                    println!("Column {} is full.", m + 1)
+                }
+                GameStatus::Turn(_) => game.play(m),
+            },
+            Ok(Command::Undo) => {
+                if !game.take_back() {
+                    println!("There is nothing to take back.");
+                }
+            }
+            Ok(Command::Hint) => {
+                let rollouts = monte_carlo_ai::rollouts_at_level(ai_level);
+                match (
+                    monte_carlo_ai::ai_move(&game.board, rollouts),
+                    monte_carlo_ai::evaluate(&game.board, rollouts),
+                ) {
+                    (Ok(m), Ok(score)) => {
+                        let (verb, column) = describe_move(m);
+                        println!(
+                            "Connect4Bot would {} {}, expecting to score {:.2} for you.",
+                            verb, column, score
+                        )
+                    }
+                    _ => println!("The game is over, there's nothing left to play."),
+                }
+            }
+            Ok(Command::Level(level)) => {
+                ai_level = level;
+                println!("Connect4Bot now plays at level {}.", ai_level);
+            }
+            Ok(Command::New) => {
+                // swap colors so both sides get to start
+                game = Game::new(
+                    game.human.other(),
+                    game.board.rules(),
+                    options.blockers,
+                    options.handicap,
+                );
+                println!(
+                    "New game, you play {}.",
+                    if game.board.game_status() == GameStatus::Turn(game.human) {
+                        "first"
+                    } else {
+                        "second"
+                    }
+                );
+            }
+            Ok(Command::Help) => println!("{}", HELP),
+            Ok(Command::Quit) => break,
+        }
+    }
+}
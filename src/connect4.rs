@@ -1,3 +1,7 @@
 pub mod board;
+pub mod clock;
+pub mod engine;
+pub mod external_engine;
 pub mod monte_carlo_ai;
 pub mod persistency;
+pub mod solver;
@@ -1,11 +1,12 @@
+#include <stdlib.h>
 #include "board.h"
 #include "montecarlo.h"
 
 
-uint8_t __c_montecarlo_c4_ai(uint64_t red_pieces, uint64_t blue_pieces, uint32_t tree_size) {
+static GameState *import_state(uint64_t red_pieces, uint64_t blue_pieces) {
     GameState *imported_state;
     if (game_state_new(&imported_state) != BOARD_OK) 
-        return 7;
+        return NULL;
     
     uint64_t i = 1;
     for (int e = 0; e < 42; e++) {
@@ -23,8 +24,17 @@ uint8_t __c_montecarlo_c4_ai(uint64_t red_pieces, uint64_t blue_pieces, uint32_t
         imported_state->game_status = YELLOW_TO_PLAY;
     else
         imported_state->game_status = BLUE_TO_PLAY;
+
+    return imported_state;
+}
+
+uint8_t __c_montecarlo_c4_ai_with_exploration(uint64_t red_pieces, uint64_t blue_pieces,
+        uint32_t tree_size, double exp_constant) {
+    GameState *imported_state = import_state(red_pieces, blue_pieces);
+    if (imported_state == NULL)
+        return 7;
         
-    int ai_move = monte_carlo_best_move(imported_state, tree_size);
+    int ai_move = monte_carlo_search(imported_state, tree_size, exp_constant, NULL);
     
     game_state_delete(imported_state);
     
@@ -34,3 +44,24 @@ uint8_t __c_montecarlo_c4_ai(uint64_t red_pieces, uint64_t blue_pieces, uint32_t
         return 7;
 }
 
+uint8_t __c_montecarlo_c4_ai(uint64_t red_pieces, uint64_t blue_pieces, uint32_t tree_size) {
+    return __c_montecarlo_c4_ai_with_exploration(red_pieces, blue_pieces, tree_size, EXP_CONSTANT);
+}
+
+double __c_montecarlo_c4_evaluate_with_exploration(uint64_t red_pieces, uint64_t blue_pieces,
+        uint32_t tree_size, double exp_constant) {
+    GameState *imported_state = import_state(red_pieces, blue_pieces);
+    if (imported_state == NULL)
+        return -1.0;
+
+    double score = -1.0;
+    monte_carlo_search(imported_state, tree_size, exp_constant, &score);
+
+    game_state_delete(imported_state);
+
+    return score;
+}
+
+double __c_montecarlo_c4_evaluate(uint64_t red_pieces, uint64_t blue_pieces, uint32_t tree_size) {
+    return __c_montecarlo_c4_evaluate_with_exploration(red_pieces, blue_pieces, tree_size, EXP_CONSTANT);
+}
@@ -68,11 +68,11 @@ int coin_toss() {
         return rand()%2;
 }
 
-float compute_score(float w, float n, float t) {
-        return (w/n) + EXP_CONSTANT*sqrt(log(t)/n);
+float compute_score(float w, float n, float t, float exp_constant) {
+        return (w/n) + exp_constant*sqrt(log(t)/n);
 }
 
-int select_child(MCNode *node) {
+int select_child(MCNode *node, double exp_constant) {
         float highest_score = -1.0, current_score;
         Options o;
         int j = 1;
@@ -84,7 +84,8 @@ int select_child(MCNode *node) {
                                 current_score = 20.0;
                         else
                                 current_score = compute_score((float)node->w[i],
-                                                (float) node->n[i], (float) node->t);
+                                                (float) node->n[i], (float) node->t,
+                                                (float) exp_constant);
                         if (current_score - highest_score > -0.0001 &&
                                         highest_score - current_score > -0.0001) {
                                 o.n += 1;
@@ -103,10 +104,19 @@ int select_child(MCNode *node) {
 }
 
 int monte_carlo_best_move(GameState *gs, uint32_t tree_size) {
+        return monte_carlo_best_move_with_score(gs, tree_size, NULL);
+}
+
+int monte_carlo_best_move_with_score(GameState *gs, uint32_t tree_size, double *score) {
+        return monte_carlo_search(gs, tree_size, EXP_CONSTANT, score);
+}
+
+int monte_carlo_search(GameState *gs, uint32_t tree_size, double exp_constant, double *score) {
         // assert(gs != NULL);
         MCTree *tree = malloc(sizeof *tree);
         if (tree == NULL)
                 return -1;
+        tree->exp_constant = exp_constant;
         tree->nodes = malloc(tree_size * sizeof(MCNode));
         if (tree->nodes == NULL)
                 return -1;
@@ -128,6 +138,8 @@ int monte_carlo_best_move(GameState *gs, uint32_t tree_size) {
                 }
         }
         // printf("Confidence: %f\n\n", ((float)(tree->nodes[0]).w[best_move])/((float)(tree->nodes[0]).n[best_move]));
+        if (score != NULL && best_move != -1 && (tree->nodes[0]).n[best_move] > 0)
+                *score = ((double)(tree->nodes[0]).w[best_move])/((double)(tree->nodes[0]).n[best_move]);
         delete_int_stack(tree->path);
         free(tree->nodes);
         free(tree);
@@ -143,7 +155,7 @@ void monte_carlo_round(MCTree *tree, GameState *gs) {
 
         /* STEP 1: Selection */
         while(in_selection) {
-                choice = select_child(&(tree->nodes[current_node]));
+                choice = select_child(&(tree->nodes[current_node]), tree->exp_constant);
                 //printf("Selection returned %d\n", choice);
                 if (choice == -1) // No children nodes
                         in_selection = 0;
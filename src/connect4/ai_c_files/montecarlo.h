@@ -25,6 +25,7 @@ typedef struct {
         MCNode * nodes;
         int length;
         IntStack *path;
+        double exp_constant;
 } MCTree;
 
 // If game is unfinished, picks a move at random
@@ -45,7 +46,14 @@ void monte_carlo_round(MCTree *tree, GameState *gs);
 
 // This is the droid you're looking for
 int monte_carlo_best_move(GameState *gs, uint32_t);
-int select_child(MCNode * node);
+
+// Same as above, also storing in score the fraction of rollouts through the
+// chosen move that were won by the player to move (if score is not NULL)
+int monte_carlo_best_move_with_score(GameState *gs, uint32_t, double *score);
+
+// Same as above, with the given exploration constant instead of EXP_CONSTANT
+int monte_carlo_search(GameState *gs, uint32_t, double exp_constant, double *score);
+int select_child(MCNode * node, double exp_constant);
 
 // Create a new node reflecting the moves available
 // in the given game state
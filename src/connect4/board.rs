@@ -1,42 +1,388 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
 use std::cmp;
+use std::fmt;
+use std::str::FromStr;
 use std::string::String;
 
-mod magic_numbers;
+mod notation;
+
+pub use notation::{moves_to_string, GridError, MovesError, PositionError};
+
+// Directions a line can run in, as steps of (columns, rows)
+const DIRECTIONS: [(i8, i8); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+// Moves are named by their column, counting from 0 on the left. Popping a piece
+// out of the bottom of a column, which only PopOut games allow, is named by the
+// column plus POP.
+pub const POP: u8 = 16;
+
+pub fn pop_move(column: u8) -> u8 {
+    POP + column
+}
+
+pub fn is_pop(m: u8) -> bool {
+    m >= POP
+}
+
+pub fn move_column(m: u8) -> u8 {
+    if is_pop(m) {
+        m - POP
+    } else {
+        m
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct BoardSize {
+    pub width: u8,
+    pub height: u8,
+}
+
+impl BoardSize {
+    pub const STANDARD: BoardSize = BoardSize {
+        width: 7,
+        height: 6,
+    };
+
+    // Columns are named by a single digit, and every slot needs a bit of a
+    // u128 with three to spare for the turn, game over and out indicators
+    pub const MIN_SIDE: u8 = 4;
+    pub const MAX_WIDTH: u8 = 9;
+    pub const MAX_HEIGHT: u8 = 8;
+
+    pub fn new(width: u8, height: u8) -> Option<BoardSize> {
+        if (BoardSize::MIN_SIDE..=BoardSize::MAX_WIDTH).contains(&width)
+            && (BoardSize::MIN_SIDE..=BoardSize::MAX_HEIGHT).contains(&height)
+        {
+            Some(BoardSize { width, height })
+        } else {
+            None
+        }
+    }
+
+    fn slot_count(&self) -> u32 {
+        u32::from(self.width) * u32::from(self.height)
+    }
+
+    fn slot(&self, x: u8, y: u8) -> u128 {
+        1u128 << (u32::from(x) * u32::from(self.height) + u32::from(y))
+    }
+
+    fn column(&self, x: u8) -> u128 {
+        self.slot(x, 0) * ((1u128 << self.height) - 1)
+    }
+
+    fn top_row(&self) -> u128 {
+        self.bottom_row() << (self.height - 1)
+    }
+
+    // Dividing every slot by a whole column leaves one bit per column
+    fn bottom_row(&self) -> u128 {
+        self.cells() / ((1u128 << self.height) - 1)
+    }
+
+    // The slot each column's next piece lands on, for the columns with room.
+    // The pieces of a column sit on each other from the bottom, so adding its
+    // bottom slot carries into the first empty one. The top row is left out
+    // of the sum so that a full column doesn't carry into the next.
+    fn drop_slots(&self, occupied: u128) -> u128 {
+        ((occupied & !self.top_row()) + self.bottom_row()) & !occupied & self.cells()
+    }
+
+    // The bits that stand for slots of the board
+    fn cells(&self) -> u128 {
+        (1u128 << self.slot_count()) - 1
+    }
+
+    fn turn_indicator(&self) -> u128 {
+        1u128 << self.slot_count()
+    }
+
+    fn game_over_indicator(&self) -> u128 {
+        1u128 << (self.slot_count() + 1)
+    }
+
+    fn out_indicator(&self) -> u128 {
+        1u128 << (self.slot_count() + 2)
+    }
+}
+
+impl fmt::Display for BoardSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+// Sizes are written as width by height, like 8x7
+impl FromStr for BoardSize {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<BoardSize, ()> {
+        let (width, height) = s.split_once('x').ok_or(())?;
+        let width = u8::from_str(width).map_err(|_| ())?;
+        let height = u8::from_str(height).map_err(|_| ())?;
+        BoardSize::new(width, height).ok_or(())
+    }
+}
+
+// Ways of playing that change more than the board and the line
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Variant {
+    Standard,
+    // Players may pop their own pieces out of the bottom row instead of
+    // dropping one
+    PopOut,
+    // The outer columns start filled with pieces of alternating colors
+    FiveInARow,
+    // The board is rolled into a cylinder, so lines can run off one side and
+    // carry on from the other
+    Cylinder,
+    // Players only see their own pieces and the slots next to them, until
+    // the game is over
+    FogOfWar,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 5] = [
+        Variant::Standard,
+        Variant::PopOut,
+        Variant::FiveInARow,
+        Variant::Cylinder,
+        Variant::FogOfWar,
+    ];
+
+    // The word the variant is asked for with, as in `--variant popout`
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::PopOut => "popout",
+            Variant::FiveInARow => "fiveinarow",
+            Variant::Cylinder => "cylinder",
+            Variant::FogOfWar => "fog",
+        }
+    }
+
+    // How the variant is played unless the size or line length are given
+    pub fn usual_rules(self) -> Rules {
+        match self {
+            Variant::Standard | Variant::PopOut | Variant::Cylinder | Variant::FogOfWar => Rules {
+                variant: self,
+                ..Rules::STANDARD
+            },
+            Variant::FiveInARow => Rules {
+                size: BoardSize {
+                    width: 9,
+                    height: 6,
+                },
+                line_length: 5,
+                variant: self,
+                players: 2,
+            },
+        }
+    }
+
+    // Pops and starting pieces are made for two, the other variants can be
+    // played by more
+    pub fn allows_groups(self) -> bool {
+        match self {
+            Variant::Standard | Variant::Cylinder | Variant::FogOfWar => true,
+            Variant::PopOut | Variant::FiveInARow => false,
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::ALL
+            .iter()
+            .copied()
+            .find(|variant| variant.name() == s)
+            .ok_or(())
+    }
+}
+
+// What a game is played on, and what it takes to win it
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Rules {
+    pub size: BoardSize,
+    // How many pieces in a row win the game
+    pub line_length: u8,
+    pub variant: Variant,
+    // How many players take turns, each with pieces of their own
+    pub players: u8,
+}
+
+impl Rules {
+    pub const STANDARD: Rules = Rules {
+        size: BoardSize::STANDARD,
+        line_length: 4,
+        variant: Variant::Standard,
+        players: 2,
+    };
+
+    pub const MIN_LINE_LENGTH: u8 = 3;
+    pub const MAX_LINE_LENGTH: u8 = 6;
+    pub const MAX_PLAYERS: u8 = 4;
+
+    // The line has to fit on the board, at least across or upwards
+    pub fn new(size: BoardSize, line_length: u8) -> Option<Rules> {
+        if (Rules::MIN_LINE_LENGTH..=Rules::MAX_LINE_LENGTH).contains(&line_length)
+            && line_length <= cmp::max(size.width, size.height)
+        {
+            Some(Rules {
+                size,
+                line_length,
+                variant: Variant::Standard,
+                players: 2,
+            })
+        } else {
+            None
+        }
+    }
+
+    // More players need more room: three play on 9x7, and four on 9x8
+    pub fn usual_for_players(players: u8) -> Rules {
+        let height = if players > 3 { 8 } else { 7 };
+        Rules {
+            size: BoardSize { width: 9, height },
+            players,
+            ..Rules::STANDARD
+        }
+    }
+
+    // The same game for the given number of players, if its variant can be
+    // played by that many
+    pub fn with_players(self, players: u8) -> Option<Rules> {
+        if players == 2
+            || (3..=Rules::MAX_PLAYERS).contains(&players) && self.variant.allows_groups()
+        {
+            Some(Rules { players, ..self })
+        } else {
+            None
+        }
+    }
+
+    // The red and blue pieces on the board before the first move
+    pub fn starting_pieces(&self) -> (u128, u128) {
+        match self.variant {
+            Variant::Standard | Variant::PopOut | Variant::Cylinder | Variant::FogOfWar => (0, 0),
+            Variant::FiveInARow => {
+                let (size, right) = (self.size, self.size.width - 1);
+                (0..size.height).fold((0, 0), |(red, blue), y| {
+                    let (left, right) = (size.slot(0, y), size.slot(right, y));
+                    if y % 2 == 0 {
+                        (red | left, blue | right)
+                    } else {
+                        (red | right, blue | left)
+                    }
+                })
+            }
+        }
+    }
 
-const COLUMNS: [u64; 7] = [
-    0x1u64,
-    0x40u64,
-    0x1000u64,
-    0x40000u64,
-    0x1000000u64,
-    0x40000000u64,
-    0x1000000000u64,
-];
+    // Lines only go around the cylinder if they can't come back to a column
+    // they have already been through
+    fn wraps(&self) -> bool {
+        self.variant == Variant::Cylinder && self.line_length <= self.size.width
+    }
 
-const TOP_ROW: u64 = 0x20u64
-    | 0x800u64
-    | 0x20000u64
-    | 0x800000u64
-    | 0x20000000u64
-    | 0x800000000u64
-    | 0x20000000000u64;
+    // The pieces on the board, with the columns a line can go around to
+    // copied again past the last one. Lines around the cylinder then end on
+    // the copies, and are found like any other.
+    fn extend(&self, pieces: u128) -> u128 {
+        let pieces = pieces & self.size.cells();
+        if self.wraps() {
+            let reach = u32::from(self.line_length - 1) * u32::from(self.size.height);
+            pieces | (pieces & ((1u128 << reach) - 1)) << self.size.slot_count()
+        } else {
+            pieces
+        }
+    }
 
-const TURN_INDICATOR: u64 = 0x40000000000u64;
-const GAME_OVER_INDICATOR: u64 = 0x80000000000u64;
+    // For each direction, how many bits apart the slots of a line are, and
+    // the slots a line going that way can start from
+    fn line_shifts(&self) -> impl Iterator<Item = (u32, u128)> {
+        let rules = *self;
+        let (width, height) = (u32::from(self.size.width), u32::from(self.size.height));
+        let reach = u32::from(self.line_length - 1);
+        DIRECTIONS.iter().map(move |&(dx, dy)| {
+            let shift = (i32::from(dx) * height as i32 + i32::from(dy)) as u32;
+            let (low, high) = match dy {
+                1 => (0, height.saturating_sub(reach)),
+                -1 => (reach, height),
+                _ => (0, height),
+            };
+            let columns = if dx == 0 || rules.wraps() {
+                width
+            } else {
+                width.saturating_sub(reach)
+            };
+            let column_starts = if low < high {
+                (1u128 << high) - (1u128 << low)
+            } else {
+                0
+            };
+            let first_columns = (1u128 << (columns * height)) - 1;
+            (
+                shift,
+                column_starts * (rules.size.bottom_row() & first_columns),
+            )
+        })
+    }
+}
 
-const BOARD_HEIGHT: u8 = 6;
-const BOARD_WIDTH: u8 = 7;
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connect {} on {}", self.line_length, self.size)?;
+        match self.variant {
+            Variant::Standard => Ok(()),
+            Variant::PopOut => write!(f, " with pops"),
+            Variant::FiveInARow => write!(f, " with filled side columns"),
+            Variant::Cylinder => write!(f, " with the sides joined"),
+            Variant::FogOfWar => write!(f, " in the fog"),
+        }?;
+        match self.players {
+            2 => Ok(()),
+            players => write!(f, " for {} players", players),
+        }
+    }
+}
 
+// Players move in this order. Green and yellow only play in games of more
+// than two players.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Player {
     Red,
     Blue,
+    Green,
+    Yellow,
+}
+
+impl Player {
+    pub const ALL: [Player; 4] = [Player::Red, Player::Blue, Player::Green, Player::Yellow];
+
+    // The opponent in a two player game
+    pub fn other(&self) -> Player {
+        match self {
+            Player::Red => Player::Blue,
+            Player::Blue => Player::Red,
+            Player::Green | Player::Yellow => {
+                unreachable!("only red and blue play two player games")
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Slot {
     Empty,
     Piece(Player),
+    // Taken by a neutral piece that belongs to neither player
+    Blocked,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -51,193 +397,903 @@ pub enum GameStatus {
     GameOver(GameResult),
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UndoError {
+    GameOver,
+    // The move given isn't the last one played, or is one the game started with
+    NotLastMove,
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UndoError::GameOver => write!(f, "finished games can't be undone"),
+            UndoError::NotLastMove => write!(f, "that isn't the last move played"),
+        }
+    }
+}
+
+// The strings each kind of slot is drawn with
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Glyphs<'a> {
+    pub red: &'a str,
+    pub blue: &'a str,
+    pub green: &'a str,
+    pub yellow: &'a str,
+    pub empty: &'a str,
+    pub blocked: &'a str,
+    // Slots the viewer can't see, in fog of war games
+    pub hidden: &'a str,
+    // The pieces of the winning line
+    pub winning_red: &'a str,
+    pub winning_blue: &'a str,
+    pub winning_green: &'a str,
+    pub winning_yellow: &'a str,
+}
+
+impl Glyphs<'_> {
+    fn longest(&self) -> usize {
+        [
+            self.red,
+            self.blue,
+            self.green,
+            self.yellow,
+            self.empty,
+            self.blocked,
+            self.hidden,
+            self.winning_red,
+            self.winning_blue,
+            self.winning_green,
+            self.winning_yellow,
+        ]
+        .iter()
+        .map(|s| s.len())
+        .max()
+        .unwrap_or(0)
+    }
+}
+
 /*
-  The board is represented as 2 u64, one indicating the position of the red pieces
+  The board is represented as 2 u128, one indicating the position of the red pieces
   and one indicating the positions of the blue pieces. A piece being present is indicated with a
   1 in the corresponding position (and its absence with a 0).
 
-  The positions are assigned, LSB first, from the bottom left of the board, and going upwards.
+  The positions are assigned, LSB first, from the bottom left of the board, and going upwards,
+  one column after the other.
+
+  The bit right after the last slot (bit 42 on the standard 7x6 board) indicates the turn. This
+  bit is set to 1 on the pieces of the player whose turn it is. i.e. it is set in red_pieces if
+  (and only if) it's red's turn, and similarly for blue_pieces and blue.
+
+  The bit after that (bit 43 on the standard board) indicates game over. The bit is set on the
+  pieces of the winning player, or in both in the case of a tie.
 
-  The bit at position 42 indicates the turn. This bit is set to 1 on the pieces of the player
-  whose turn it is. i.e. bit 42 of red_pieces is set if (and only if) it's red's turn, and
-  similarly for blue_pieces and blue.
+  Standard boards fit in the lower 64 bits, where they are laid out as they always have been.
 
-  The bit at position 43 indicates game over. The bit is set on the pieces of the winning player,
-  or in both in the case of a tie.
+  The rules of the game aren't part of the bits, so they are kept alongside. Variants that start
+  with pieces on the board have them in the bits like any other.
+
+  Games can also start with neutral blockers at the bottom of some columns. They are in a third
+  u128, laid out like the other two but without the turn and game over bits, and stay where
+  they are for the whole game.
+
+  A game can also start from a position set up for it, with some moves already played or with
+  handicap pieces given to the weaker player. Those pieces are in the red and blue bits like
+  the rest, and a fourth u128 marks the slots they were set up in, which can't be taken back.
+  The player with fewer of them moves first, red when it's even. PopOut games aren't set up,
+  since pops would move the pieces out of their slots.
+
+  In PopOut games the pieces don't tell how many moves were played, so the turn can only be
+  known from its bit. A pop can also give both players a line at once, in which case the player
+  who popped wins.
+
+  Games of three or four players keep the pieces of green and yellow in two more u128, laid out
+  the same way, and the turn goes around the table in the order of Player. A player who resigns
+  or runs out of time drops out: the bit after the game over one (bit 74 on a 9x8 board) is set
+  on their pieces, their turns are skipped, and the others play on until one of them wins, the
+  board fills up, or only one is left, who wins. A tie sets the game over bit on the players still
+  in the game. Group games have no starting or set up pieces.
 */
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Board {
-    red_pieces: u64,
-    blue_pieces: u64,
+    rules: Rules,
+    red_pieces: u128,
+    blue_pieces: u128,
+    green_pieces: u128,
+    yellow_pieces: u128,
+    blocked: u128,
+    preset: u128,
 }
 
 impl Board {
-    pub fn serialize(&self) -> (u64, u64) {
+    // Games can't start with more blockers than this
+    pub const MAX_BLOCKERS: u32 = 8;
+    // Nor with more handicap pieces than this, which can't make a line
+    pub const MAX_HANDICAP: u32 = 3;
+
+    pub fn serialize(&self) -> (u128, u128) {
         (self.red_pieces, self.blue_pieces)
     }
 
-    pub fn unserialize(repr: (u64, u64)) -> Board {
+    pub fn unserialize(rules: Rules, repr: (u128, u128)) -> Board {
+        Board::unserialize_with_blockers(rules, 0, repr)
+    }
+
+    pub fn unserialize_with_blockers(rules: Rules, blocked: u128, repr: (u128, u128)) -> Board {
+        Board::unserialize_with_setup(rules, blocked, 0, repr)
+    }
+
+    // Takes the blockers and the slots of the pieces set up before the first move
+    pub fn unserialize_with_setup(
+        rules: Rules,
+        blocked: u128,
+        preset: u128,
+        repr: (u128, u128),
+    ) -> Board {
         let (red_pieces, blue_pieces) = repr;
         Board {
+            rules,
             red_pieces,
             blue_pieces,
+            green_pieces: 0,
+            yellow_pieces: 0,
+            blocked,
+            preset,
         }
     }
 
-    fn drop_piece(&mut self, column: u8, piece: Player) -> Result<(), ()> {
-        assert!(column < BOARD_WIDTH);
+    // The pieces of green and yellow, which only play in group games
+    pub fn serialize_group(&self) -> (u128, u128) {
+        (self.green_pieces, self.yellow_pieces)
+    }
 
-        let mut row = COLUMNS[column as usize];
-        let limit = row << BOARD_HEIGHT;
-        let occupied = self.red_pieces | self.blue_pieces;
-        while (row & occupied) != 0 && row != limit {
-            row = row << 1;
+    pub fn unserialize_group(
+        rules: Rules,
+        blocked: u128,
+        repr: (u128, u128),
+        group: (u128, u128),
+    ) -> Board {
+        let (green_pieces, yellow_pieces) = group;
+        Board {
+            green_pieces,
+            yellow_pieces,
+            ..Board::unserialize_with_blockers(rules, blocked, repr)
         }
+    }
 
-        if row < limit {
-            match piece {
-                Player::Red => self.red_pieces = self.red_pieces | row,
-                Player::Blue => self.blue_pieces = self.blue_pieces | row,
-            };
-            Ok(())
-        } else {
-            Err(())
+    // Like unserialize, but refuses boards that no game could have left behind
+    pub fn checked_unserialize(rules: Rules, repr: (u128, u128)) -> Result<Board, PositionError> {
+        Board::checked_unserialize_with_blockers(rules, 0, repr)
+    }
+
+    pub fn checked_unserialize_with_blockers(
+        rules: Rules,
+        blocked: u128,
+        repr: (u128, u128),
+    ) -> Result<Board, PositionError> {
+        Board::checked_unserialize_with_setup(rules, blocked, 0, repr)
+    }
+
+    pub fn checked_unserialize_with_setup(
+        rules: Rules,
+        blocked: u128,
+        preset: u128,
+        repr: (u128, u128),
+    ) -> Result<Board, PositionError> {
+        let (red_pieces, blue_pieces) = repr;
+        let size = rules.size;
+        let (cells, turn_indicator, game_over_indicator) = (
+            size.cells(),
+            size.turn_indicator(),
+            size.game_over_indicator(),
+        );
+        if (red_pieces | blue_pieces) & !(cells | turn_indicator | game_over_indicator) != 0
+            || (blocked | preset) & !cells != 0
+            || rules.variant == Variant::PopOut && preset != 0
+        {
+            return Err(PositionError::StrayBits);
+        }
+        if red_pieces & blue_pieces & cells != 0
+            || (red_pieces | blue_pieces | preset) & blocked != 0
+        {
+            return Err(PositionError::OverlappingPieces);
         }
+        notation::check_blockers(size, blocked)?;
+        if (red_pieces ^ blue_pieces) & turn_indicator == 0 {
+            return Err(PositionError::InvalidTurnIndicator);
+        }
+
+        let board = Board::unserialize_with_setup(rules, blocked, preset, repr);
+        if rules.variant == Variant::PopOut {
+            return notation::check_popped_position(&board).map(|_| board);
+        }
+        // the board as play_move would have left it after the same pieces
+        let played = notation::position_from_pieces(
+            rules,
+            blocked,
+            preset,
+            red_pieces & cells,
+            blue_pieces & cells,
+        )?;
+        let game_over_bits = (
+            red_pieces & game_over_indicator != 0,
+            blue_pieces & game_over_indicator != 0,
+        );
+
+        match played.game_status() {
+            // games can also end by resignation or agreement, which leave the
+            // turn with the player to move
+            GameStatus::Turn(player) if board.active_player() != player => {
+                Err(PositionError::WrongTurn)
+            }
+            GameStatus::Turn(_) => Ok(board),
+            GameStatus::GameOver(_) if game_over_bits == (false, false) => {
+                Err(PositionError::UnmarkedGameOver)
+            }
+            GameStatus::GameOver(GameResult::Winner(_)) if game_over_bits == (true, true) => {
+                Err(PositionError::TieWithWinner)
+            }
+            GameStatus::GameOver(_) if board.game_status() != played.game_status() => {
+                Err(PositionError::WrongResult)
+            }
+            GameStatus::GameOver(_) if board != played => Err(PositionError::WrongTurn),
+            GameStatus::GameOver(_) => Ok(board),
+        }
+    }
+
+    // Like checked_unserialize_with_blockers, for games of more than two
+    // players
+    pub fn checked_unserialize_group(
+        rules: Rules,
+        blocked: u128,
+        repr: (u128, u128),
+        group: (u128, u128),
+    ) -> Result<Board, PositionError> {
+        let board = Board::unserialize_group(rules, blocked, repr, group);
+        let size = rules.size;
+        let indicators = size.turn_indicator() | size.game_over_indicator() | size.out_indicator();
+        let seats: Vec<u128> = board.players().map(|p| board.pieces_of(p)).collect();
+        let seated = seats.iter().fold(0, |seated, pieces| seated | pieces);
+        let unseated = Player::ALL[seats.len()..]
+            .iter()
+            .fold(0, |unseated, &p| unseated | board.pieces_of(p));
+        if seated & !(size.cells() | indicators) != 0
+            || unseated != 0
+            || blocked & !size.cells() != 0
+        {
+            return Err(PositionError::StrayBits);
+        }
+        let cells: u32 = seats
+            .iter()
+            .map(|pieces| (pieces & size.cells()).count_ones())
+            .sum();
+        if cells != (seated & size.cells()).count_ones() || seated & blocked != 0 {
+            return Err(PositionError::OverlappingPieces);
+        }
+        notation::check_blockers(size, blocked)?;
+        notation::check_group_position(&board).map(|_| board)
+    }
+
+    pub fn size(&self) -> BoardSize {
+        self.rules.size
+    }
+
+    pub fn line_length(&self) -> u8 {
+        self.rules.line_length
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn blocked(&self) -> u128 {
+        self.blocked
+    }
+
+    // The slots of the pieces the game was set up with, besides those the
+    // variant starts with
+    pub fn preset(&self) -> u128 {
+        self.preset
+    }
+
+    // Puts a blocker in the column, on top of any already there, and tells if
+    // it fit. Only boards where no move has been played yet take blockers, and
+    // no column is filled up with them.
+    pub fn add_blocker(&mut self, column: u8) -> bool {
+        match self.blocker_slot(column) {
+            Some(slot) => {
+                self.blocked |= slot;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Adds up to the given number of blockers, each in a random column that
+    // still takes one
+    pub fn add_random_blockers(&mut self, count: u32, rng: &mut impl Rng) {
+        for _ in 0..count {
+            let columns: Vec<u8> = (0..self.rules.size.width)
+                .filter(|&column| self.blocker_slot(column).is_some())
+                .collect();
+            match columns.choose(rng) {
+                Some(&column) => self.blocked |= self.blocker_slot(column).unwrap(),
+                None => break,
+            }
+        }
+    }
+
+    // Gives the player up to the given number of pieces at the bottom of the
+    // middle columns, leaving a column between each two so that they make no
+    // line, and the first move to the other player. Only boards where no move
+    // has been played yet take them, once their blockers are in, and PopOut
+    // and group boards don't.
+    pub fn add_handicap(&mut self, player: Player, count: u32) {
+        if !self.is_fresh() || self.rules.variant == Variant::PopOut || self.rules.players > 2 {
+            return;
+        }
+        let size = self.rules.size;
+        let middle = (size.width / 2) as i8;
+        let columns = [middle, middle - 2, middle + 2]
+            .into_iter()
+            .filter(|&column| column >= 0 && column < size.width as i8)
+            .take(cmp::min(count, Board::MAX_HANDICAP) as usize);
+        for column in columns {
+            if let Ok(row) = self.drop_piece(column as u8, player) {
+                self.preset |= size.slot(column as u8, row);
+            }
+        }
+        if self.preset != 0 && player == Player::Red {
+            self.pass_turn();
+        }
+    }
+
+    // No move has been played and no piece set up, though there may be blockers
+    fn is_fresh(&self) -> bool {
+        let cells = self.rules.size.cells();
+        (self.red_pieces & cells, self.blue_pieces & cells) == self.rules.starting_pieces()
+            && (self.green_pieces | self.yellow_pieces) & cells == 0
+            && self.preset == 0
+            && self.game_status() == GameStatus::Turn(Player::Red)
+    }
+
+    fn blocker_slot(&self, column: u8) -> Option<u128> {
+        let size = self.rules.size;
+        if column >= size.width
+            || !self.is_fresh()
+            || self.blocked.count_ones() >= Board::MAX_BLOCKERS
+        {
+            return None;
+        }
+        let occupied = self.occupied();
+        let free: Vec<u8> = (0..size.height)
+            .filter(|&y| occupied & size.slot(column, y) == 0)
+            .collect();
+        // blockers stay under every piece, so starting pieces keep them out
+        match free.as_slice() {
+            [y, _, ..] if *y == 0 || self.blocked & size.slot(column, y - 1) != 0 => {
+                Some(size.slot(column, *y))
+            }
+            _ => None,
+        }
+    }
+
+    // The slots taken by any player or by blockers
+    fn occupied(&self) -> u128 {
+        self.pieces() | self.blocked
+    }
+
+    // The slots taken by any player
+    fn pieces(&self) -> u128 {
+        (self.red_pieces | self.blue_pieces | self.green_pieces | self.yellow_pieces)
+            & self.rules.size.cells()
+    }
+
+    // Drops a piece and gives the row it landed on
+    fn drop_piece(&mut self, column: u8, piece: Player) -> Result<u8, ()> {
+        let size = self.rules.size;
+        assert!(column < size.width);
+
+        let slot = size.drop_slots(self.occupied()) & size.column(column);
+        if slot == 0 {
+            return Err(());
+        }
+        *self.pieces_of_mut(piece) |= slot;
+        Ok((slot.trailing_zeros() - u32::from(column) * u32::from(size.height)) as u8)
+    }
+
+    // Takes the bottom piece out of the column, and lets the ones above it fall
+    fn pop_piece(&mut self, column: u8) {
+        let size = self.rules.size;
+        let (column_pieces, bottom) = (size.column(column), size.slot(column, 0));
+        let fall = |pieces: u128| pieces & !column_pieces | (pieces & column_pieces & !bottom) >> 1;
+        self.red_pieces = fall(self.red_pieces);
+        self.blue_pieces = fall(self.blue_pieces);
     }
 
-    pub fn play_move(&mut self, column: u8) {
+    pub fn play_move(&mut self, m: u8) {
         if let GameStatus::Turn(player) = self.game_status() {
-            match self.drop_piece(column, player) {
-                Ok(_) => self.update_game_status(),
-                Err(_) => {}
+            if is_pop(m) {
+                if self.is_move_legal(m) {
+                    self.pop_piece(move_column(m));
+                    self.update_game_status_after_pop();
+                }
+                return;
+            }
+            if self.drop_piece(m, player).is_ok() {
+                self.update_game_status();
+            }
+        }
+    }
+
+    // Takes back the last move, which must have been the given one. Finished
+    // games can't be undone, nor can the moves of players who dropped out.
+    pub fn undo_move(&mut self, m: u8) -> Result<(), UndoError> {
+        let column = move_column(m);
+        assert!(column < self.rules.size.width);
+
+        let player_to_move = match self.game_status() {
+            GameStatus::Turn(player) => player,
+            GameStatus::GameOver(_) => return Err(UndoError::GameOver),
+        };
+        if is_pop(m) {
+            // only two play PopOut, so the player who moved last moves next
+            return self.undo_pop(column, self.next_player(player_to_move));
+        }
+
+        // the highest bit of the column is the last piece dropped in it, and
+        // whoever it belongs to moved last
+        let column_pieces = self.pieces() & self.rules.size.column(column);
+        let top = match column_pieces {
+            0 => 0,
+            pieces => 1u128 << (127 - pieces.leading_zeros()),
+        };
+        let last_player = match self.players().find(|&p| self.pieces_of(p) & top != 0) {
+            Some(player) if player != player_to_move && self.is_in_play(player) => player,
+            _ => return Err(UndoError::NotLastMove),
+        };
+
+        let (red_start, blue_start) =
+            setup_pieces(self.rules, self.preset, self.red_pieces, self.blue_pieces);
+        if (red_start | blue_start) & top != 0 {
+            Err(UndoError::NotLastMove)
+        } else {
+            *self.pieces_of_mut(last_player) &= !top;
+            self.give_turn(last_player);
+            Ok(())
+        }
+    }
+
+    // Puts the popped piece back under the column. The pop left room at the top.
+    fn undo_pop(&mut self, column: u8, last_player: Player) -> Result<(), UndoError> {
+        if self.rules.variant != Variant::PopOut || self.is_column_full(column) {
+            return Err(UndoError::NotLastMove);
+        }
+        let size = self.rules.size;
+        let column_pieces = size.column(column);
+        let rise = |pieces: u128| pieces & !column_pieces | (pieces & column_pieces) << 1;
+        self.red_pieces = rise(self.red_pieces);
+        self.blue_pieces = rise(self.blue_pieces);
+        *self.pieces_of_mut(last_player) |= size.slot(column, 0);
+        self.give_turn(last_player);
+        Ok(())
+    }
+
+    // Ends an unfinished game as a tie, as when all players agree to a draw
+    pub fn agree_draw(&mut self) {
+        if let GameStatus::Turn(_) = self.game_status() {
+            self.end_in_tie();
+        }
+    }
+
+    // The given player concedes an unfinished game. In a group game the others
+    // play on without them, until only one is left, who wins.
+    pub fn resign(&mut self, player: Player) {
+        if let GameStatus::Turn(player_to_move) = self.game_status() {
+            let others: Vec<Player> = self
+                .players()
+                .filter(|&p| p != player && self.is_in_play(p))
+                .collect();
+            match others.as_slice() {
+                [winner] => *self.pieces_of_mut(*winner) |= self.rules.size.game_over_indicator(),
+                _ => {
+                    *self.pieces_of_mut(player) |= self.rules.size.out_indicator();
+                    if player == player_to_move {
+                        self.pass_turn();
+                    }
+                }
             }
         }
     }
 
+    // Whether the player is still in the game, not having dropped out of it
+    pub fn is_in_play(&self, player: Player) -> bool {
+        self.pieces_of(player) & self.rules.size.out_indicator() == 0
+    }
+
+    // The players of the game, in the order they move
+    pub fn players(&self) -> impl Iterator<Item = Player> {
+        Player::ALL
+            .into_iter()
+            .take(usize::from(self.rules.players))
+    }
+
     pub fn empty_board() -> Board {
-        Board {
-            red_pieces: TURN_INDICATOR,
-            blue_pieces: 0,
+        Board::empty_board_with_rules(Rules::STANDARD)
+    }
+
+    // A board of the given size where four in a row wins
+    pub fn empty_board_of_size(size: BoardSize) -> Board {
+        Board::empty_board_with_rules(Rules {
+            size,
+            ..Rules::STANDARD
+        })
+    }
+
+    pub fn empty_board_with_rules(rules: Rules) -> Board {
+        let (red, blue) = rules.starting_pieces();
+        Board::unserialize(rules, (red | rules.size.turn_indicator(), blue))
+    }
+
+    // The board the game started from, blockers and set up pieces included
+    pub fn starting_position(&self) -> Board {
+        let mut board = Board {
+            blocked: self.blocked,
+            preset: self.preset,
+            ..Board::empty_board_with_rules(self.rules)
+        };
+        board.red_pieces |= self.red_pieces & self.preset;
+        board.blue_pieces |= self.blue_pieces & self.preset;
+        let (red_start, blue_start) =
+            setup_pieces(self.rules, self.preset, self.red_pieces, self.blue_pieces);
+        if first_player(red_start, blue_start) == Player::Blue {
+            board.pass_turn();
         }
+        board
     }
 
     pub fn slot_at(&self, x: u8, y: u8) -> Slot {
-        assert!(x < BOARD_WIDTH && y < BOARD_HEIGHT);
+        assert!(x < self.rules.size.width && y < self.rules.size.height);
 
-        let location = COLUMNS[x as usize] << y;
-        if (self.red_pieces & location) != 0 {
-            Slot::Piece(Player::Red)
-        } else if (self.blue_pieces & location) != 0 {
-            Slot::Piece(Player::Blue)
-        } else {
-            Slot::Empty
+        let location = self.rules.size.slot(x, y);
+        match Player::ALL
+            .into_iter()
+            .find(|&p| self.pieces_of(p) & location != 0)
+        {
+            Some(player) => Slot::Piece(player),
+            None if (self.blocked & location) != 0 => Slot::Blocked,
+            None => Slot::Empty,
         }
     }
 
+    // The game is won by the only player with the game over bit, and tied when
+    // several have it
     pub fn game_status(&self) -> GameStatus {
-        if self.red_pieces & self.blue_pieces & GAME_OVER_INDICATOR != 0 {
-            GameStatus::GameOver(GameResult::Tie)
-        } else if self.red_pieces & GAME_OVER_INDICATOR != 0 {
-            GameStatus::GameOver(GameResult::Winner(Player::Red))
-        } else if self.blue_pieces & GAME_OVER_INDICATOR != 0 {
-            GameStatus::GameOver(GameResult::Winner(Player::Blue))
-        } else {
-            GameStatus::Turn(self.active_player())
+        let game_over_indicator = self.rules.size.game_over_indicator();
+        let mut finished = self
+            .players()
+            .filter(|&p| self.pieces_of(p) & game_over_indicator != 0);
+        match (finished.next(), finished.next()) {
+            (None, _) => GameStatus::Turn(self.active_player()),
+            (Some(winner), None) => GameStatus::GameOver(GameResult::Winner(winner)),
+            (Some(_), Some(_)) => GameStatus::GameOver(GameResult::Tie),
         }
     }
 
     fn active_player(&self) -> Player {
-        if self.red_pieces & TURN_INDICATOR != 0 {
-            Player::Red
-        } else {
-            Player::Blue
+        let turn_indicator = self.rules.size.turn_indicator();
+        self.players()
+            .find(|&p| self.pieces_of(p) & turn_indicator != 0)
+            .unwrap_or(Player::Blue)
+    }
+
+    fn pieces_of(&self, player: Player) -> u128 {
+        match player {
+            Player::Red => self.red_pieces,
+            Player::Blue => self.blue_pieces,
+            Player::Green => self.green_pieces,
+            Player::Yellow => self.yellow_pieces,
         }
     }
 
+    fn pieces_of_mut(&mut self, player: Player) -> &mut u128 {
+        match player {
+            Player::Red => &mut self.red_pieces,
+            Player::Blue => &mut self.blue_pieces,
+            Player::Green => &mut self.green_pieces,
+            Player::Yellow => &mut self.yellow_pieces,
+        }
+    }
+
+    fn can_pop(&self, player: Player) -> bool {
+        self.rules.variant == Variant::PopOut
+            && self.pieces_of(player) & self.rules.size.bottom_row() != 0
+    }
+
     fn is_column_full(&self, column: u8) -> bool {
-        let top_position_in_column = COLUMNS[column as usize] << (BOARD_HEIGHT - 1);
-        top_position_in_column & (self.red_pieces | self.blue_pieces) != 0
+        let top_position_in_column = self.rules.size.slot(column, self.rules.size.height - 1);
+        top_position_in_column & self.occupied() != 0
     }
 
+    // Called after the active player dropped a piece. Only they can have
+    // made a line with it.
     fn update_game_status(&mut self) {
-        let pieces_to_check = // the positions to check for a possible connect 4 win
-            match self.active_player() {
-                Player::Red => self.red_pieces,
-                Player::Blue => self.blue_pieces
-            };
-        let four_in_line = magic_numbers::MAGIC_NUMBERS
-            .iter()
-            .any(|&x| x & pieces_to_check == x);
-        if four_in_line {
-            match self.active_player() {
-                Player::Red => self.red_pieces = self.red_pieces | GAME_OVER_INDICATOR,
-                Player::Blue => self.blue_pieces = self.blue_pieces | GAME_OVER_INDICATOR,
-            };
-        } else if TOP_ROW & (self.red_pieces | self.blue_pieces) == TOP_ROW {
-            self.red_pieces = self.red_pieces | GAME_OVER_INDICATOR;
-            self.blue_pieces = self.blue_pieces | GAME_OVER_INDICATOR;
+        let top_row = self.rules.size.top_row();
+        let player = self.active_player();
+        if has_line(self.rules, self.pieces_of(player)) {
+            *self.pieces_of_mut(player) |= self.rules.size.game_over_indicator();
+        } else if top_row & self.occupied() == top_row
+            // a full board still leaves pops, to a player with a piece at the bottom
+            && !self.can_pop(self.next_player(player))
+        {
+            self.end_in_tie();
         } else {
-            self.swap_turn();
+            self.pass_turn();
+        }
+    }
+
+    // Marks the game as a tie for the players still in it
+    fn end_in_tie(&mut self) {
+        let game_over_indicator = self.rules.size.game_over_indicator();
+        for player in self.players() {
+            if self.is_in_play(player) {
+                *self.pieces_of_mut(player) |= game_over_indicator;
+            }
+        }
+    }
+
+    // Called after the active player popped a piece out of a column. Every
+    // piece left in it has moved, so lines can appear for either player, and
+    // the player who popped wins if both have one.
+    fn update_game_status_after_pop(&mut self) {
+        let player = self.active_player();
+        let winner = [player, player.other()]
+            .iter()
+            .copied()
+            .find(|&p| has_line(self.rules, self.pieces_of(p)));
+        match winner {
+            Some(winner) => *self.pieces_of_mut(winner) |= self.rules.size.game_over_indicator(),
+            None => self.pass_turn(),
         }
     }
 
-    fn swap_turn(&mut self) {
-        self.red_pieces = self.red_pieces ^ TURN_INDICATOR;
-        self.blue_pieces = self.blue_pieces ^ TURN_INDICATOR;
+    // The player still in the game who moves after the given one
+    fn next_player(&self, player: Player) -> Player {
+        let players: Vec<Player> = self.players().collect();
+        let seat = players.iter().position(|&p| p == player).unwrap_or(0);
+        (1..=players.len())
+            .map(|step| players[(seat + step) % players.len()])
+            .find(|&p| self.is_in_play(p))
+            .unwrap_or(player)
+    }
+
+    fn pass_turn(&mut self) {
+        self.give_turn(self.next_player(self.active_player()));
     }
 
-    pub fn is_move_legal(&self, column: u8) -> bool {
-        if column >= BOARD_WIDTH {
+    fn give_turn(&mut self, player: Player) {
+        let turn_indicator = self.rules.size.turn_indicator();
+        for p in Player::ALL {
+            *self.pieces_of_mut(p) &= !turn_indicator;
+        }
+        *self.pieces_of_mut(player) |= turn_indicator;
+    }
+
+    pub fn is_move_legal(&self, m: u8) -> bool {
+        let column = move_column(m);
+        if column >= self.rules.size.width {
             false
         } else {
             match self.game_status() {
+                GameStatus::Turn(player) if is_pop(m) => {
+                    self.can_pop(player)
+                        && self.pieces_of(player) & self.rules.size.slot(column, 0) != 0
+                }
                 GameStatus::Turn(_) => !self.is_column_full(column),
                 _ => false,
             }
         }
     }
 
+    // Every move that can be played, drops first
+    pub fn legal_moves(&self) -> Vec<u8> {
+        let size = self.rules.size;
+        let player = match self.game_status() {
+            GameStatus::Turn(player) => player,
+            GameStatus::GameOver(_) => return vec![],
+        };
+        let drops = size.drop_slots(self.occupied());
+        let pops = if self.can_pop(player) {
+            self.pieces_of(player) & size.bottom_row()
+        } else {
+            0
+        };
+        let columns = |slots: u128| (0..size.width).filter(move |&x| slots & size.column(x) != 0);
+        columns(drops).chain(columns(pops).map(pop_move)).collect()
+    }
+
+    // Draws the board as the viewer sees it, with the slots hidden from them
+    // drawn as hidden. Nobody's view hides anything outside fog of war games.
+    // The winning line is drawn like the other pieces.
     pub fn display(
         &self,
-        red: &str,
-        blue: &str,
-        empty: &str,
+        viewer: Option<Player>,
+        glyphs: &Glyphs,
+        separator: &str,
+        line_start: &str,
+        line_end: &str,
+        line_separator: &str,
+    ) -> String {
+        let glyphs = Glyphs {
+            winning_red: glyphs.red,
+            winning_blue: glyphs.blue,
+            winning_green: glyphs.green,
+            winning_yellow: glyphs.yellow,
+            ..*glyphs
+        };
+        self.display_with_winning_line(
+            viewer,
+            &glyphs,
+            separator,
+            line_start,
+            line_end,
+            line_separator,
+        )
+    }
+
+    // Same as display, drawing the pieces of the winning line with their own
+    // glyphs
+    pub fn display_with_winning_line(
+        &self,
+        viewer: Option<Player>,
+        glyphs: &Glyphs,
         separator: &str,
         line_start: &str,
         line_end: &str,
         line_separator: &str,
     ) -> String {
-        let (bh, bw) = (BOARD_HEIGHT as usize, BOARD_WIDTH as usize);
-        let output_length = bh * bw * cmp::max(red.len(), cmp::max(blue.len(), empty.len()))
+        let (board_height, board_width) = (self.rules.size.height, self.rules.size.width);
+        let (bh, bw) = (board_height as usize, board_width as usize);
+        let longest = glyphs.longest();
+        let output_length = bh * bw * longest
             + bh * line_start.len()
             + bh * line_end.len()
             + bh * (bw - 1) * separator.len()
             + (bh + 1) * line_separator.len();
         let mut output_string = String::with_capacity(output_length);
 
-        for y in 0..BOARD_HEIGHT {
+        let winning_mask = self.winning_mask();
+        let visible = self.visible_to(viewer);
+        let slot_string = |x: u8, y: u8| {
+            let location = self.rules.size.slot(x, y);
+            if visible & location == 0 {
+                return glyphs.hidden;
+            }
+            let in_line = winning_mask & location != 0;
+            match (self.slot_at(x, y), in_line) {
+                (Slot::Piece(Player::Red), false) => glyphs.red,
+                (Slot::Piece(Player::Blue), false) => glyphs.blue,
+                (Slot::Piece(Player::Green), false) => glyphs.green,
+                (Slot::Piece(Player::Yellow), false) => glyphs.yellow,
+                (Slot::Piece(Player::Red), true) => glyphs.winning_red,
+                (Slot::Piece(Player::Blue), true) => glyphs.winning_blue,
+                (Slot::Piece(Player::Green), true) => glyphs.winning_green,
+                (Slot::Piece(Player::Yellow), true) => glyphs.winning_yellow,
+                (Slot::Empty, _) => glyphs.empty,
+                (Slot::Blocked, _) => glyphs.blocked,
+            }
+        };
+
+        for y in 0..board_height {
             output_string.push_str(line_separator);
             output_string.push_str(line_start);
-            for x in 0..(BOARD_WIDTH - 1) {
-                output_string.push_str(match self.slot_at(x, BOARD_HEIGHT - y - 1) {
-                    Slot::Piece(Player::Red) => red,
-                    Slot::Piece(Player::Blue) => blue,
-                    Slot::Empty => empty,
-                });
+            for x in 0..(board_width - 1) {
+                output_string.push_str(slot_string(x, board_height - y - 1));
                 output_string.push_str(separator);
             }
-            output_string.push_str(match self.slot_at(BOARD_WIDTH - 1, BOARD_HEIGHT - y - 1) {
-                Slot::Piece(Player::Red) => red,
-                Slot::Piece(Player::Blue) => blue,
-                Slot::Empty => empty,
-            });
+            output_string.push_str(slot_string(board_width - 1, board_height - y - 1));
             output_string.push_str(line_end);
         }
         output_string.push_str(line_separator);
 
         output_string
     }
+
+    // The slots, as (column, row) from the bottom left, of every winning line
+    // the winner made. Lines can be longer than needed, or cross each other, so
+    // there may be more slots than the line length. Games won by resignation or
+    // on time have no winning line.
+    pub fn winning_line(&self) -> Vec<(u8, u8)> {
+        let winning_mask = self.winning_mask();
+        let mut slots = vec![];
+        for x in 0..self.rules.size.width {
+            for y in 0..self.rules.size.height {
+                if winning_mask & self.rules.size.slot(x, y) != 0 {
+                    slots.push((x, y));
+                }
+            }
+        }
+        slots
+    }
+
+    // The slots the viewer can see. In fog of war games players see their own
+    // pieces, the slots next to them and the blockers, and somebody who isn't
+    // playing sees only the blockers. Everything shows once the game is over.
+    pub fn visible_to(&self, viewer: Option<Player>) -> u128 {
+        let size = self.rules.size;
+        if self.rules.variant != Variant::FogOfWar
+            || matches!(self.game_status(), GameStatus::GameOver(_))
+        {
+            return size.cells();
+        }
+        let own = match viewer {
+            None => 0,
+            Some(player) => self.pieces_of(player) & size.cells(),
+        };
+        let mut visible = self.blocked;
+        for x in 0..size.width {
+            for y in 0..size.height {
+                if own & size.slot(x, y) == 0 {
+                    continue;
+                }
+                for nx in x.saturating_sub(1)..=cmp::min(x + 1, size.width - 1) {
+                    for ny in y.saturating_sub(1)..=cmp::min(y + 1, size.height - 1) {
+                        visible |= size.slot(nx, ny);
+                    }
+                }
+            }
+        }
+        visible
+    }
+
+    fn winning_mask(&self) -> u128 {
+        let winner_pieces = match self.game_status() {
+            GameStatus::GameOver(GameResult::Winner(player)) => self.pieces_of(player),
+            _ => return 0,
+        };
+        line_slots(self.rules, winner_pieces)
+    }
+}
+
+// The red and blue pieces on the board before the first move
+fn setup_pieces(rules: Rules, preset: u128, red: u128, blue: u128) -> (u128, u128) {
+    let (red_start, blue_start) = rules.starting_pieces();
+    (red_start | red & preset, blue_start | blue & preset)
+}
+
+// Red moves first, unless the game was set up with more red pieces than blue
+fn first_player(red_start: u128, blue_start: u128) -> Player {
+    if red_start.count_ones() > blue_start.count_ones() {
+        Player::Blue
+    } else {
+        Player::Red
+    }
+}
+
+// Whether the pieces make a line. Each direction is checked on the whole
+// board at once, by and-ing the pieces with themselves shifted along it.
+fn has_line(rules: Rules, pieces: u128) -> bool {
+    let pieces = rules.extend(pieces);
+    rules
+        .line_shifts()
+        .any(|(shift, starts)| runs(rules, pieces, shift) & starts != 0)
+}
+
+// The slots followed by a line length of pieces, the given shift apart
+fn runs(rules: Rules, pieces: u128, shift: u32) -> u128 {
+    (1..u32::from(rules.line_length)).fold(pieces, |run, i| run & pieces >> (i * shift))
+}
+
+// Every slot that is part of a line of the pieces
+fn line_slots(rules: Rules, pieces: u128) -> u128 {
+    let pieces = rules.extend(pieces);
+    let slots = rules.line_shifts().fold(0, |slots, (shift, starts)| {
+        let starts = runs(rules, pieces, shift) & starts;
+        (0..u32::from(rules.line_length)).fold(slots, |slots, i| slots | starts << (i * shift))
+    });
+    // the lines around the cylinder end on the copies of the first columns
+    (slots | slots >> rules.size.slot_count()) & rules.size.cells()
 }
 
 #[cfg(test)]
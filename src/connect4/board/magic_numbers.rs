@@ -1,71 +0,0 @@
-pub const MAGIC_NUMBERS: [u64; 69] = [
-    15,
-    30,
-    60,
-    960,
-    1920,
-    3840,
-    61440,
-    122880,
-    245760,
-    3932160,
-    7864320,
-    15728640,
-    251658240,
-    503316480,
-    1006632960,
-    16106127360,
-    32212254720,
-    64424509440,
-    1030792151040,
-    2061584302080,
-    4123168604160,
-    266305,
-    532610,
-    1065220,
-    2130440,
-    4260880,
-    8521760,
-    17043520,
-    34087040,
-    68174080,
-    136348160,
-    272696320,
-    545392640,
-    1090785280,
-    2181570560,
-    4363141120,
-    8726282240,
-    17452564480,
-    34905128960,
-    69810257920,
-    139620515840,
-    279241031680,
-    558482063360,
-    1116964126720,
-    2233928253440,
-    2113665,
-    4227330,
-    8454660,
-    135274560,
-    270549120,
-    541098240,
-    8657571840,
-    17315143680,
-    34630287360,
-    554084597760,
-    1108169195520,
-    2216338391040,
-    270600,
-    541200,
-    1082400,
-    17318400,
-    34636800,
-    69273600,
-    1108377600,
-    2216755200,
-    4433510400,
-    70936166400,
-    141872332800,
-    283744665600,
-];
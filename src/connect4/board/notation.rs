@@ -0,0 +1,684 @@
+use super::*;
+
+use std::collections::HashSet;
+use std::fmt;
+
+/*
+  Positions can be written down in two ways.
+
+  The move sequence lists the columns played from the empty board, numbered from 1 on the left,
+  with nothing in between: "4453" is red in the middle, blue on top of it, then red and
+  blue next to each other. The empty board is the empty string. In PopOut games a p before the
+  column pops a piece out of it instead, as in "4453p4". Variants that start with pieces on the
+  board play the moves on top of those. Blockers aren't part of the sequence, which is read as
+  played on a board without them, and neither are the pieces a game was set up with: the
+  sequence of such a game starts from its set up position.
+
+  The grid lists the rows from top to bottom, separated by slashes, with r for a red piece, b
+  for a blue one, x for a blocker and . for an empty slot, followed by a space and the player to move (r or b),
+  or - if the game is over:
+
+    ......./......./......./......./...b.../..brr.. r
+
+  The size of the board is read from the grid, while move sequences are played on a board of
+  a size given separately. Neither says how long a winning line is, which is four unless
+  given otherwise, and grids are always read as standard games. Group boards are written with g
+  and y for the green and yellow pieces and players, but their grids can't be read back, nor their
+  moves found from their pieces, since players drop out of them.
+
+  Both only describe the pieces on the board, so a game that ended by resignation or agreed
+  draw reads back as the unfinished position it was left in.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MovesError {
+    // Positions count from 0
+    InvalidCharacter { position: usize, found: char },
+    ColumnFull { position: usize, column: u8 },
+    IllegalPop { position: usize, column: u8 },
+    GameAlreadyOver { position: usize },
+}
+
+impl fmt::Display for MovesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovesError::InvalidCharacter { position, found } => write!(
+                f,
+                "move {} is {:?}, which is not a column of the board",
+                position + 1,
+                found
+            ),
+            MovesError::ColumnFull { position, column } => {
+                write!(
+                    f,
+                    "move {} is in column {}, which is full",
+                    position + 1,
+                    column + 1
+                )
+            }
+            MovesError::IllegalPop { position, column } => write!(
+                f,
+                "move {} pops column {}, which doesn't have a piece of the player at the bottom",
+                position + 1,
+                column + 1
+            ),
+            MovesError::GameAlreadyOver { position } => {
+                write!(f, "move {} comes after the end of the game", position + 1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PositionError {
+    // A piece with an empty slot below it, in the given column
+    FloatingPiece { column: u8 },
+    // Red moves first, so it has as many pieces as blue or one more, not
+    // counting the pieces the game started with
+    ImpossiblePieceCount { red: u32, blue: u32 },
+    MissingStartingPieces,
+    BothConnected,
+    // Only the last player to move can have a winning line
+    WrongWinner(Player),
+    TooManyBlockers,
+    InvalidLineLength,
+    UnknownVariant,
+    // The variant can't be played by that many players, or they don't take
+    // their seats in order
+    InvalidPlayerCount,
+    // The rest only apply to serialized boards
+    InvalidSize,
+    StrayBits,
+    OverlappingPieces,
+    // Exactly one player has the turn bit
+    InvalidTurnIndicator,
+    WrongTurn,
+    // Somebody made a winning line or the board is full, but the game isn't over
+    UnmarkedGameOver,
+    TieWithWinner,
+    // The game over bits name a different result than the pieces
+    WrongResult,
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::FloatingPiece { column } => {
+                write!(f, "column {} has a piece over an empty slot", column + 1)
+            }
+            PositionError::ImpossiblePieceCount { red, blue } => write!(
+                f,
+                "{} red and {} blue pieces can't come from a game",
+                red, blue
+            ),
+            PositionError::MissingStartingPieces => {
+                write!(f, "the pieces the game starts with aren't all there")
+            }
+            PositionError::BothConnected => write!(f, "both players have a winning line"),
+            PositionError::WrongWinner(player) => write!(
+                f,
+                "{:?} has a winning line, but the other player moved last",
+                player
+            ),
+            PositionError::TooManyBlockers => write!(
+                f,
+                "games can't start with more than {} blockers",
+                Board::MAX_BLOCKERS
+            ),
+            PositionError::InvalidLineLength => write!(
+                f,
+                "winning lines must have {} to {} pieces and fit on the board",
+                Rules::MIN_LINE_LENGTH,
+                Rules::MAX_LINE_LENGTH
+            ),
+            PositionError::UnknownVariant => write!(f, "the game is of an unknown variant"),
+            PositionError::InvalidPlayerCount => {
+                write!(f, "the game can't be played by those players")
+            }
+            PositionError::InvalidSize => write!(f, "boards can't have that size"),
+            PositionError::StrayBits => write!(f, "bits are set outside of the board"),
+            PositionError::OverlappingPieces => write!(f, "two pieces share a slot"),
+            PositionError::InvalidTurnIndicator => {
+                write!(f, "the turn bit must be set for exactly one player")
+            }
+            PositionError::WrongTurn => write!(f, "the turn bit is on the wrong player"),
+            PositionError::UnmarkedGameOver => {
+                write!(f, "the game should be over but isn't marked so")
+            }
+            PositionError::TieWithWinner => {
+                write!(
+                    f,
+                    "the game is marked as a tie, but somebody has a winning line"
+                )
+            }
+            PositionError::WrongResult => {
+                write!(
+                    f,
+                    "the game is marked with a different result than the pieces give"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum GridError {
+    MissingTurn,
+    TrailingText(String),
+    WrongRowCount(usize),
+    // Rows count from 0 at the top
+    WrongRowLength {
+        row: usize,
+        length: usize,
+    },
+    InvalidCell {
+        row: usize,
+        column: usize,
+        found: char,
+    },
+    InvalidTurn(String),
+    // The turn the grid gives isn't the one its pieces call for
+    WrongTurn {
+        expected: char,
+        found: char,
+    },
+    Position(PositionError),
+}
+
+impl From<PositionError> for GridError {
+    fn from(e: PositionError) -> GridError {
+        GridError::Position(e)
+    }
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GridError::MissingTurn => write!(f, "the grid doesn't say who is to move"),
+            GridError::TrailingText(text) => write!(f, "unexpected {:?} after the turn", text),
+            GridError::WrongRowCount(rows) => write!(
+                f,
+                "the grid has {} rows, boards have {} to {}",
+                rows,
+                BoardSize::MIN_SIDE,
+                BoardSize::MAX_HEIGHT
+            ),
+            GridError::WrongRowLength { row, length } => write!(
+                f,
+                "row {} has {} slots, rows must be as long as the first and have {} to {}",
+                row + 1,
+                length,
+                BoardSize::MIN_SIDE,
+                BoardSize::MAX_WIDTH
+            ),
+            GridError::InvalidCell { row, column, found } => write!(
+                f,
+                "row {} has {:?} in column {}, slots are r, b or .",
+                row + 1,
+                found,
+                column + 1
+            ),
+            GridError::InvalidTurn(turn) => {
+                write!(f, "the turn is {:?}, it should be r, b or -", turn)
+            }
+            GridError::WrongTurn { expected, found } => write!(
+                f,
+                "the turn is {} but the pieces say it should be {}",
+                found, expected
+            ),
+            GridError::Position(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// The move sequence notation for the given moves
+pub fn moves_to_string(moves: &[u8]) -> String {
+    let mut s = String::with_capacity(moves.len());
+    for &m in moves {
+        if is_pop(m) {
+            s.push('p');
+        }
+        s.push((b'1' + move_column(m)) as char);
+    }
+    s
+}
+
+impl Board {
+    pub fn from_moves(moves: &str) -> Result<Board, MovesError> {
+        Board::from_moves_on(Rules::STANDARD, moves)
+    }
+
+    pub fn from_moves_on(rules: Rules, moves: &str) -> Result<Board, MovesError> {
+        let mut board = Board::empty_board_with_rules(rules);
+        board.play_moves(moves)?;
+        Ok(board)
+    }
+
+    // Sets up the board of a game that starts after the given moves, which
+    // can't be taken back. Like blockers, only boards where no move has been
+    // played take them, and PopOut and group boards don't.
+    pub fn play_opening(&mut self, moves: &str) -> Result<(), MovesError> {
+        if !self.is_fresh() || self.rules.variant == Variant::PopOut || self.rules.players > 2 {
+            return Ok(());
+        }
+        let cells = self.rules.size.cells();
+        let (red_start, blue_start) = self.rules.starting_pieces();
+        let mut board = self.clone();
+        board.play_moves(moves)?;
+        board.preset = (board.red_pieces & !red_start | board.blue_pieces & !blue_start) & cells;
+        *self = board;
+        Ok(())
+    }
+
+    fn play_moves(&mut self, moves: &str) -> Result<(), MovesError> {
+        let rules = self.rules;
+        let size = rules.size;
+        let mut chars = moves.chars();
+        let mut position = 0;
+        while let Some(c) = chars.next() {
+            let (pop, c) = match c {
+                'p' if rules.variant == Variant::PopOut => (true, chars.next().unwrap_or(c)),
+                _ => (false, c),
+            };
+            let column = match c {
+                '1'..='9' if c as u8 - b'1' < size.width => c as u8 - b'1',
+                _ => return Err(MovesError::InvalidCharacter { position, found: c }),
+            };
+            if let GameStatus::GameOver(_) = self.game_status() {
+                return Err(MovesError::GameAlreadyOver { position });
+            }
+            let m = if pop { pop_move(column) } else { column };
+            if !self.is_move_legal(m) && pop {
+                return Err(MovesError::IllegalPop { position, column });
+            }
+            if !self.is_move_legal(m) {
+                return Err(MovesError::ColumnFull { position, column });
+            }
+            self.play_move(m);
+            position += 1;
+        }
+        Ok(())
+    }
+
+    // A sequence of moves that leads to this position. Boards only keep their
+    // pieces, so when several orders lead to the same position any of them may
+    // come out. There is none for positions that can't come from a game. Pops
+    // leave no trace, so PopOut positions are only found if drops alone reach
+    // them, and group positions, which players may have dropped out of, aren't.
+    pub fn to_moves(&self) -> Option<String> {
+        if self.rules.players > 2 {
+            return None;
+        }
+        let cells = self.rules.size.cells();
+        let (red, blue) = (self.red_pieces & cells, self.blue_pieces & cells);
+        let drops_only = match self.rules.variant {
+            Variant::PopOut => Rules {
+                variant: Variant::Standard,
+                ..self.rules
+            },
+            _ => self.rules,
+        };
+        check_pieces(drops_only, self.blocked, self.preset, red, blue).ok()?;
+
+        let mut moves = vec![];
+        let mut dead_ends = HashSet::new();
+        let start = setup_pieces(drops_only, self.preset, red, blue);
+        if !find_moves(drops_only, start, red, blue, &mut moves, &mut dead_ends) {
+            return None;
+        }
+        moves.reverse();
+        let moves = moves_to_string(&moves);
+        if self.rules.variant == Variant::PopOut
+            && Board::from_moves_on(self.rules, &moves).as_ref() != Ok(self)
+        {
+            return None;
+        }
+        Some(moves)
+    }
+
+    pub fn from_grid(grid: &str) -> Result<Board, GridError> {
+        Board::from_grid_connecting(grid, Rules::STANDARD.line_length)
+    }
+
+    // Reads a grid where lines of the given length win
+    pub fn from_grid_connecting(grid: &str, line_length: u8) -> Result<Board, GridError> {
+        let mut fields = grid.split(' ');
+        let rows: Vec<&str> = fields.next().unwrap_or("").split('/').collect();
+        let turn = fields.next().ok_or(GridError::MissingTurn)?;
+        if let Some(rest) = fields.next() {
+            return Err(GridError::TrailingText(rest.to_string()));
+        }
+
+        let height = rows.len();
+        if !(BoardSize::MIN_SIDE as usize..=BoardSize::MAX_HEIGHT as usize).contains(&height) {
+            return Err(GridError::WrongRowCount(height));
+        }
+        // every row has as many slots as the first
+        let width = rows[0].chars().count();
+        if !(BoardSize::MIN_SIDE as usize..=BoardSize::MAX_WIDTH as usize).contains(&width) {
+            return Err(GridError::WrongRowLength {
+                row: 0,
+                length: width,
+            });
+        }
+        let size = BoardSize {
+            width: width as u8,
+            height: height as u8,
+        };
+        let rules = Rules::new(size, line_length).ok_or(PositionError::InvalidLineLength)?;
+        let (mut red, mut blue, mut blocked) = (0u128, 0u128, 0u128);
+        for (row, cells) in rows.iter().enumerate() {
+            let length = cells.chars().count();
+            if length != width {
+                return Err(GridError::WrongRowLength { row, length });
+            }
+            let y = size.height - row as u8 - 1;
+            for (column, cell) in cells.chars().enumerate() {
+                let location = size.slot(column as u8, y);
+                match cell {
+                    'r' => red |= location,
+                    'b' => blue |= location,
+                    'x' => blocked |= location,
+                    '.' => {}
+                    _ => {
+                        return Err(GridError::InvalidCell {
+                            row,
+                            column,
+                            found: cell,
+                        })
+                    }
+                }
+            }
+        }
+
+        let turn = match turn {
+            "r" | "b" | "-" => turn.chars().next().unwrap(),
+            _ => return Err(GridError::InvalidTurn(turn.to_string())),
+        };
+        check_blockers(size, blocked)?;
+        let board = position_from_pieces(rules, blocked, 0, red, blue)?;
+        let expected = turn_char(&board);
+        if turn != expected {
+            return Err(GridError::WrongTurn {
+                expected,
+                found: turn,
+            });
+        }
+        Ok(board)
+    }
+
+    pub fn to_grid(&self) -> String {
+        let rows: Vec<String> = (0..self.rules.size.height)
+            .rev()
+            .map(|y| {
+                (0..self.rules.size.width)
+                    .map(|x| match self.slot_at(x, y) {
+                        Slot::Piece(Player::Red) => 'r',
+                        Slot::Piece(Player::Blue) => 'b',
+                        Slot::Piece(Player::Green) => 'g',
+                        Slot::Piece(Player::Yellow) => 'y',
+                        Slot::Blocked => 'x',
+                        Slot::Empty => '.',
+                    })
+                    .collect()
+            })
+            .collect();
+        let cells = self.rules.size.cells();
+        let turn = match position_from_pieces(
+            self.rules(),
+            self.blocked,
+            self.preset,
+            self.red_pieces & cells,
+            self.blue_pieces & cells,
+        ) {
+            Ok(board) if self.rules.variant != Variant::PopOut && self.rules.players == 2 => {
+                turn_char(&board)
+            }
+            _ => turn_char(self),
+        };
+        format!("{} {}", rows.join("/"), turn)
+    }
+}
+
+fn turn_char(board: &Board) -> char {
+    match board.game_status() {
+        GameStatus::Turn(Player::Red) => 'r',
+        GameStatus::Turn(Player::Blue) => 'b',
+        GameStatus::Turn(Player::Green) => 'g',
+        GameStatus::Turn(Player::Yellow) => 'y',
+        GameStatus::GameOver(_) => '-',
+    }
+}
+
+// Takes pieces with the indicator bits already cleared
+fn count(pieces: u128) -> u32 {
+    pieces.count_ones()
+}
+
+// The pieces of a column fill it from the bottom, with no gaps
+fn check_columns(size: BoardSize, occupied: u128) -> Result<(), PositionError> {
+    for column in 0..size.width {
+        let bottom = size.slot(column, 0);
+        let column_pieces = occupied & size.column(column);
+        if column_pieces & (column_pieces + bottom) != 0 {
+            return Err(PositionError::FloatingPiece { column });
+        }
+    }
+    Ok(())
+}
+
+// Blockers sit at the bottom of their columns, where the game started them
+pub(super) fn check_blockers(size: BoardSize, blocked: u128) -> Result<(), PositionError> {
+    check_columns(size, blocked)?;
+    if blocked.count_ones() > Board::MAX_BLOCKERS {
+        return Err(PositionError::TooManyBlockers);
+    }
+    Ok(())
+}
+
+// The player who moved last, after the given number of moves by each since
+// the start
+fn last_player(first_player: Player, red_count: u32, blue_count: u32) -> Player {
+    if red_count == blue_count {
+        first_player.other()
+    } else {
+        first_player
+    }
+}
+
+// Checks that the pieces could be on the board after some game without pops,
+// and gives the result if it is over
+fn check_pieces(
+    rules: Rules,
+    blocked: u128,
+    preset: u128,
+    red: u128,
+    blue: u128,
+) -> Result<Option<GameResult>, PositionError> {
+    let size = rules.size;
+    let occupied = red | blue | blocked;
+    check_columns(size, occupied)?;
+
+    let (red_start, blue_start) = setup_pieces(rules, preset, red, blue);
+    let (rules_red, rules_blue) = rules.starting_pieces();
+    if red & rules_red != rules_red || blue & rules_blue != rules_blue || preset & !occupied != 0 {
+        return Err(PositionError::MissingStartingPieces);
+    }
+    // set up pieces are under the ones played on top of them
+    check_columns(size, blocked | red_start | blue_start)?;
+    let first_player = first_player(red_start, blue_start);
+    let (red_count, blue_count) = (count(red & !red_start), count(blue & !blue_start));
+    let (first_count, second_count) = if first_player == Player::Red {
+        (red_count, blue_count)
+    } else {
+        (blue_count, red_count)
+    };
+    if first_count != second_count && first_count != second_count + 1 {
+        return Err(PositionError::ImpossiblePieceCount {
+            red: red_count,
+            blue: blue_count,
+        });
+    }
+    let last_player = last_player(first_player, red_count, blue_count);
+
+    let top_row = size.top_row();
+    match (has_line(rules, red), has_line(rules, blue)) {
+        (true, true) => Err(PositionError::BothConnected),
+        (true, false) if last_player != Player::Red => Err(PositionError::WrongWinner(Player::Red)),
+        (false, true) if last_player != Player::Blue => {
+            Err(PositionError::WrongWinner(Player::Blue))
+        }
+        (true, false) => Ok(Some(GameResult::Winner(Player::Red))),
+        (false, true) => Ok(Some(GameResult::Winner(Player::Blue))),
+        (false, false) if occupied & top_row == top_row => Ok(Some(GameResult::Tie)),
+        (false, false) => Ok(None),
+    }
+}
+
+// PopOut positions can't be replayed from their pieces, which pops take away in
+// any order, so only what holds for all of them is checked
+pub(super) fn check_popped_position(board: &Board) -> Result<(), PositionError> {
+    let cells = board.rules.size.cells();
+    let (red, blue) = (board.red_pieces & cells, board.blue_pieces & cells);
+    check_columns(board.rules.size, red | blue | board.blocked)?;
+
+    let connected = has_line(board.rules, red) || has_line(board.rules, blue);
+    match board.game_status() {
+        GameStatus::Turn(_) if connected => Err(PositionError::UnmarkedGameOver),
+        GameStatus::GameOver(GameResult::Tie) if connected => Err(PositionError::TieWithWinner),
+        _ => Ok(()),
+    }
+}
+
+// Group positions can't be replayed either, since players drop out of them at
+// any time, so only what holds for all of them is checked
+pub(super) fn check_group_position(board: &Board) -> Result<(), PositionError> {
+    let size = board.rules.size;
+    check_columns(size, board.occupied())?;
+
+    let turn_indicator = size.turn_indicator();
+    let players: Vec<Player> = board.players().collect();
+    if players
+        .iter()
+        .filter(|&&p| board.pieces_of(p) & turn_indicator != 0)
+        .count()
+        != 1
+    {
+        return Err(PositionError::InvalidTurnIndicator);
+    }
+    let connected: Vec<Player> = players
+        .iter()
+        .copied()
+        .filter(|&p| has_line(board.rules, board.pieces_of(p)))
+        .collect();
+    let in_play = players.iter().filter(|&&p| board.is_in_play(p)).count();
+    let top_row = size.top_row();
+    let full = board.occupied() & top_row == top_row;
+    match (board.game_status(), connected.as_slice()) {
+        (_, [_, _, ..]) => Err(PositionError::BothConnected),
+        (GameStatus::Turn(player), _) if !board.is_in_play(player) => Err(PositionError::WrongTurn),
+        (GameStatus::Turn(_), [_]) => Err(PositionError::UnmarkedGameOver),
+        (GameStatus::Turn(_), []) if full || in_play < 2 => Err(PositionError::UnmarkedGameOver),
+        (GameStatus::GameOver(GameResult::Tie), [_]) => Err(PositionError::TieWithWinner),
+        (GameStatus::GameOver(GameResult::Winner(winner)), [connected]) if winner != *connected => {
+            Err(PositionError::WrongResult)
+        }
+        _ => Ok(()),
+    }
+}
+
+// The board with these pieces, with the turn and game over markers set as
+// play_move would have left them
+pub(super) fn position_from_pieces(
+    rules: Rules,
+    blocked: u128,
+    preset: u128,
+    red: u128,
+    blue: u128,
+) -> Result<Board, PositionError> {
+    let result = check_pieces(rules, blocked, preset, red, blue)?;
+    let (red_start, blue_start) = setup_pieces(rules, preset, red, blue);
+    let red_to_move = last_player(
+        first_player(red_start, blue_start),
+        count(red & !red_start),
+        count(blue & !blue_start),
+    ) == Player::Blue;
+    let size = rules.size;
+    let (turn_indicator, game_over_indicator) = (size.turn_indicator(), size.game_over_indicator());
+    let mut board = Board::unserialize_with_setup(rules, blocked, preset, (red, blue));
+    match result {
+        None if red_to_move => board.red_pieces |= turn_indicator,
+        None => board.blue_pieces |= turn_indicator,
+        // after the last move the turn stays with the player who made it
+        Some(GameResult::Winner(winner)) => {
+            *board.pieces_of_mut(winner) |= turn_indicator | game_over_indicator
+        }
+        Some(GameResult::Tie) if red_to_move => {
+            board.blue_pieces |= turn_indicator | game_over_indicator;
+            board.red_pieces |= game_over_indicator;
+        }
+        Some(GameResult::Tie) => {
+            board.red_pieces |= turn_indicator | game_over_indicator;
+            board.blue_pieces |= game_over_indicator;
+        }
+    }
+    Ok(board)
+}
+
+// Takes back pieces from the top of the columns, last player first, until only
+// the starting pieces are left. No position on the way may have a winning line,
+// since the game would have ended there. The moves are pushed last one first.
+fn find_moves(
+    rules: Rules,
+    start: (u128, u128),
+    red: u128,
+    blue: u128,
+    moves: &mut Vec<u8>,
+    dead_ends: &mut HashSet<(u128, u128)>,
+) -> bool {
+    let (red_start, blue_start) = start;
+    if (red, blue) == (red_start, blue_start) {
+        return true;
+    }
+    if dead_ends.contains(&(red, blue)) {
+        return false;
+    }
+
+    let red_moved_last = last_player(
+        first_player(red_start, blue_start),
+        count(red & !red_start),
+        count(blue & !blue_start),
+    ) == Player::Red;
+    let last_player_pieces = if red_moved_last { red } else { blue };
+    for column in 0..rules.size.width {
+        let column_pieces = (red | blue) & rules.size.column(column);
+        if column_pieces == 0 {
+            continue;
+        }
+        // the highest bit of the column is its top piece
+        let top = 1u128 << (127 - column_pieces.leading_zeros());
+        if last_player_pieces & top == 0 || (red_start | blue_start) & top != 0 {
+            continue;
+        }
+
+        let (red_before, blue_before) = if red_moved_last {
+            (red & !top, blue)
+        } else {
+            (red, blue & !top)
+        };
+        if has_line(rules, red_before) || has_line(rules, blue_before) {
+            continue;
+        }
+        moves.push(column);
+        if find_moves(rules, start, red_before, blue_before, moves, dead_ends) {
+            return true;
+        }
+        moves.pop();
+    }
+
+    dead_ends.insert((red, blue));
+    false
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,242 @@
+use super::*;
+
+fn board_after(moves: &[u8]) -> Board {
+    let mut b = Board::empty_board();
+    for &column in moves {
+        b.play_move(column);
+    }
+    b
+}
+
+#[test]
+fn moves_are_read() {
+    assert_eq!(Board::from_moves(""), Ok(Board::empty_board()));
+    assert_eq!(Board::from_moves("4453"), Ok(board_after(&[3, 3, 4, 2])));
+    assert_eq!(
+        Board::from_moves("1212121"),
+        Ok(board_after(&[0, 1, 0, 1, 0, 1, 0]))
+    );
+}
+
+#[test]
+fn bad_moves_are_reported() {
+    assert_eq!(
+        Board::from_moves("44 5"),
+        Err(MovesError::InvalidCharacter {
+            position: 2,
+            found: ' '
+        })
+    );
+    assert_eq!(
+        Board::from_moves("08"),
+        Err(MovesError::InvalidCharacter {
+            position: 0,
+            found: '0'
+        })
+    );
+    assert_eq!(
+        Board::from_moves("1111111"),
+        Err(MovesError::ColumnFull {
+            position: 6,
+            column: 0
+        })
+    );
+    assert_eq!(
+        Board::from_moves("12121213"),
+        Err(MovesError::GameAlreadyOver { position: 7 })
+    );
+}
+
+#[test]
+fn moves_are_written() {
+    assert_eq!(moves_to_string(&[3, 3, 4, 2]), "4453");
+    assert_eq!(Board::empty_board().to_moves(), Some("".to_string()));
+
+    // any order that reaches the same position will do
+    for moves in ["4453", "1212121", "44444455", "3434343"].iter() {
+        let board = Board::from_moves(moves).unwrap();
+        let found = board.to_moves().expect("the position comes from a game");
+        assert_eq!(Board::from_moves(&found), Ok(board));
+    }
+}
+
+#[test]
+fn winning_moves_come_last() {
+    // red's second piece on the left could only have been played last if it
+    // hadn't already made four with the others
+    let board = Board::from_moves("1215141").unwrap();
+    let found = board.to_moves().unwrap();
+    assert_eq!(Board::from_moves(&found), Ok(board));
+}
+
+#[test]
+fn unreachable_positions_have_no_moves() {
+    // blue moved twice in a row
+    let rules = Rules::STANDARD;
+    let board = Board::unserialize(rules, (rules.size.turn_indicator(), 0b11));
+    assert_eq!(board.to_moves(), None);
+}
+
+#[test]
+fn grids_are_written() {
+    assert_eq!(
+        Board::empty_board().to_grid(),
+        "......./......./......./......./......./....... r"
+    );
+    assert_eq!(
+        Board::from_moves("4453").unwrap().to_grid(),
+        "......./......./......./......./...b.../..brr.. r"
+    );
+    assert_eq!(
+        Board::from_moves("1212121").unwrap().to_grid(),
+        "......./......./r....../rb...../rb...../rb..... -"
+    );
+}
+
+#[test]
+fn grids_are_read_back() {
+    for moves in ["", "4453", "1212121", "3434343", "444444"].iter() {
+        let board = Board::from_moves(moves).unwrap();
+        assert_eq!(Board::from_grid(&board.to_grid()), Ok(board));
+    }
+
+    // a full board with no four in a row is a tie
+    let tie = "rbrbrbr/rbrbrbr/rbrbrbr/brbrbrb/brbrbrb/brbrbrb -";
+    let board = Board::from_grid(tie).expect("a tied game");
+    assert_eq!(board.game_status(), GameStatus::GameOver(GameResult::Tie));
+    assert_eq!(board.to_grid(), tie);
+}
+
+#[test]
+fn bad_grids_are_reported() {
+    let empty = "......./......./......./......./......./.......";
+    assert_eq!(Board::from_grid(empty), Err(GridError::MissingTurn));
+    assert_eq!(
+        Board::from_grid(&format!("{} r now", empty)),
+        Err(GridError::TrailingText("now".to_string()))
+    );
+    assert_eq!(
+        Board::from_grid("......./......./....... r"),
+        Err(GridError::WrongRowCount(3))
+    );
+    assert_eq!(
+        Board::from_grid("......./......./......./......./......./...... r"),
+        Err(GridError::WrongRowLength { row: 5, length: 6 })
+    );
+    assert_eq!(
+        Board::from_grid("......./......./......./......./......./...y... r"),
+        Err(GridError::InvalidCell {
+            row: 5,
+            column: 3,
+            found: 'y'
+        })
+    );
+    assert_eq!(
+        Board::from_grid(&format!("{} red", empty)),
+        Err(GridError::InvalidTurn("red".to_string()))
+    );
+    assert_eq!(
+        Board::from_grid(&format!("{} b", empty)),
+        Err(GridError::WrongTurn {
+            expected: 'r',
+            found: 'b'
+        })
+    );
+    assert_eq!(
+        Board::from_grid("......./......./......./......./...r.../....... b"),
+        Err(GridError::Position(PositionError::FloatingPiece {
+            column: 3
+        }))
+    );
+    assert_eq!(
+        Board::from_grid("......./......./......./......./......./rr..... b"),
+        Err(GridError::Position(PositionError::ImpossiblePieceCount {
+            red: 2,
+            blue: 0
+        }))
+    );
+    assert_eq!(
+        Board::from_grid("......./......./......./......./rrr..../bbbbrr. -"),
+        Err(GridError::Position(PositionError::WrongWinner(
+            Player::Blue
+        )))
+    );
+}
+
+#[test]
+fn grids_give_the_board_size() {
+    let size = BoardSize::new(6, 5).unwrap();
+    let rules = Rules::new(size, 4).unwrap();
+    let board = Board::from_moves_on(rules, "3366").unwrap();
+    assert_eq!(board.to_grid(), "....../....../....../..b..b/..r..r r");
+    assert_eq!(Board::from_grid(&board.to_grid()), Ok(board.clone()));
+    assert_eq!(Board::from_grid(&board.to_grid()).unwrap().size(), size);
+    let found = board.to_moves().expect("the position comes from a game");
+    assert_eq!(Board::from_moves_on(rules, &found), Ok(board));
+
+    assert_eq!(
+        Board::from_moves_on(rules, "37"),
+        Err(MovesError::InvalidCharacter {
+            position: 1,
+            found: '7'
+        })
+    );
+    assert_eq!(
+        Board::from_grid(
+            "........./........./........./........./........./........./........./........./......... r"
+        ),
+        Err(GridError::WrongRowCount(9))
+    );
+    assert_eq!(
+        Board::from_grid("........../........../........../.........../ r"),
+        Err(GridError::WrongRowLength { row: 0, length: 10 })
+    );
+}
+
+#[test]
+fn grids_can_have_other_line_lengths() {
+    let grid = "......./......./......./......./bbbb.../rrrr... r";
+    assert_eq!(
+        Board::from_grid(grid),
+        Err(GridError::Position(PositionError::BothConnected))
+    );
+    let board = Board::from_grid_connecting(grid, 5).unwrap();
+    assert_eq!(board.line_length(), 5);
+    assert_eq!(board.to_grid(), grid);
+    assert_eq!(
+        Board::from_moves_on(board.rules(), &board.to_moves().unwrap()),
+        Ok(board)
+    );
+    assert_eq!(
+        Board::from_grid_connecting(grid, 8),
+        Err(GridError::Position(PositionError::InvalidLineLength))
+    );
+}
+
+#[test]
+fn pops_are_read_and_written() {
+    let rules = Rules {
+        variant: Variant::PopOut,
+        ..Rules::STANDARD
+    };
+    let board = Board::from_moves_on(rules, "4453p4").unwrap();
+    assert_eq!(board.slot_at(3, 0), Slot::Piece(Player::Blue));
+    assert_eq!(moves_to_string(&[3, 3, 4, 2, pop_move(3)]), "4453p4");
+    // pops leave no trace, so the pieces don't give the moves back
+    assert_eq!(board.to_moves(), None);
+
+    assert_eq!(
+        Board::from_moves_on(rules, "45p5"),
+        Err(MovesError::IllegalPop {
+            position: 2,
+            column: 4
+        })
+    );
+    assert_eq!(
+        Board::from_moves("4p4"),
+        Err(MovesError::InvalidCharacter {
+            position: 1,
+            found: 'p'
+        })
+    );
+}
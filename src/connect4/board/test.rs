@@ -1,5 +1,24 @@
 use super::*;
 
+// One letter per slot, capitals for the winning line
+const GLYPHS: Glyphs = Glyphs {
+    red: "r",
+    blue: "b",
+    green: "g",
+    yellow: "y",
+    empty: ".",
+    blocked: "#",
+    hidden: "?",
+    winning_red: "R",
+    winning_blue: "B",
+    winning_green: "G",
+    winning_yellow: "Y",
+};
+
+// Where the indicators sit on a standard board
+const TURN_INDICATOR: u128 = 1 << 42;
+const GAME_OVER_INDICATOR: u128 = 1 << 43;
+
 fn test_game(moves: &[u8], expected_result: GameResult) {
     let mut e = Board::empty_board();
     for k in moves {
@@ -79,10 +98,20 @@ fn serialize_works() {
 
 #[test]
 fn unserialize_works() {
-    let b = Board::unserialize((36438016, 4398064340992));
+    let b = Board::unserialize(Rules::STANDARD, (36438016, 4398064340992));
 
     assert_eq!(
-        b.display("r", "b", "x", "", "", "", ""),
+        b.display(
+            None,
+            &Glyphs {
+                empty: "x",
+                ..GLYPHS
+            },
+            "",
+            "",
+            "",
+            ""
+        ),
         "xxxxxxxxxxxxxxxxxrxxxxxxbxxxxxxrrxxxxbrbxx"
     );
 }
@@ -138,3 +167,790 @@ fn test_game_4() {
     ];
     test_game(&game, GameResult::Tie);
 }
+
+#[test]
+fn agreed_draw_is_a_tie() {
+    let mut e = Board::empty_board();
+    e.play_move(3);
+    e.play_move(3);
+    e.agree_draw();
+    assert_eq!(e.game_status(), GameStatus::GameOver(GameResult::Tie));
+    assert!(!e.is_move_legal(2));
+
+    let game = vec![
+        3, 3, 2, 1, 3, 5, 2, 2, 4, 3, 1, 2, 5, 1, 1, 3, 5, 5, 5, 2, 1, 2,
+    ];
+    let mut finished = Board::empty_board();
+    for k in game {
+        finished.play_move(k);
+    }
+    finished.agree_draw();
+    assert_eq!(
+        finished.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Blue))
+    );
+}
+
+#[test]
+fn undo_restores_previous_position() {
+    let mut e = Board::empty_board();
+    e.play_move(3);
+    e.play_move(3);
+    let before = e.clone();
+    e.play_move(2);
+    assert_eq!(e.undo_move(3), Err(UndoError::NotLastMove));
+    assert_eq!(e.undo_move(4), Err(UndoError::NotLastMove));
+    assert_eq!(e.undo_move(2), Ok(()));
+    assert_eq!(e, before);
+    assert_eq!(e.undo_move(3), Ok(()));
+    assert_eq!(e.undo_move(3), Ok(()));
+    assert_eq!(e, Board::empty_board());
+    assert_eq!(e.undo_move(3), Err(UndoError::NotLastMove));
+}
+
+#[test]
+fn finished_games_cannot_be_undone() {
+    let game = vec![
+        3, 3, 2, 1, 3, 5, 2, 2, 4, 3, 1, 2, 5, 1, 1, 3, 5, 5, 5, 2, 1, 2,
+    ];
+    let mut e = Board::empty_board();
+    for k in game {
+        e.play_move(k);
+    }
+    assert_eq!(e.undo_move(2), Err(UndoError::GameOver));
+}
+
+#[test]
+fn resigning_concedes_the_game() {
+    let mut e = Board::empty_board();
+    e.play_move(3);
+    e.resign(Player::Red);
+    assert_eq!(
+        e.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Blue))
+    );
+    e.resign(Player::Blue);
+    assert_eq!(
+        e.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Blue))
+    );
+
+    let mut f = Board::empty_board();
+    f.resign(Player::Blue);
+    assert_eq!(
+        f.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+}
+
+#[test]
+fn checked_unserialize_takes_boards_from_games() {
+    let mut boards = vec![Board::empty_board()];
+    let mut b = Board::empty_board();
+    for &column in [3, 3, 4, 2, 5, 6].iter() {
+        b.play_move(column);
+        boards.push(b.clone());
+    }
+    // red connects four
+    b.play_move(1);
+    boards.push(b.clone());
+
+    let mut resigned = boards[3].clone();
+    resigned.resign(Player::Blue);
+    boards.push(resigned);
+    let mut drawn = boards[4].clone();
+    drawn.agree_draw();
+    boards.push(drawn);
+
+    for b in boards {
+        assert_eq!(Board::checked_unserialize(b.rules(), b.serialize()), Ok(b));
+    }
+}
+
+#[test]
+fn checked_unserialize_takes_ties_ending_on_red() {
+    // both boards have an odd number of free slots, so red fills the last one
+    let rules = Rules::new(BoardSize::new(9, 7).unwrap(), 5).unwrap();
+    let connect_five = Board::from_moves_on(
+        rules,
+        "263965147921138756198727485281569843311383636494776982275594245",
+    )
+    .unwrap();
+    let mut blocked = Board::empty_board();
+    assert!(blocked.add_blocker(3));
+    for c in "54361611437144646653723565123357221277752".chars() {
+        blocked.play_move(c.to_digit(10).unwrap() as u8 - 1);
+    }
+
+    for b in [connect_five, blocked] {
+        assert_eq!(b.game_status(), GameStatus::GameOver(GameResult::Tie));
+        assert_eq!(
+            Board::checked_unserialize_with_blockers(b.rules(), b.blocked(), b.serialize()),
+            Ok(b)
+        );
+    }
+}
+
+#[test]
+fn checked_unserialize_refuses_inconsistent_boards() {
+    let check =
+        |red: u128, blue: u128| Board::checked_unserialize(Rules::STANDARD, (red, blue)).err();
+
+    assert_eq!(
+        check(TURN_INDICATOR | (1 << 50), 0),
+        Some(PositionError::StrayBits)
+    );
+    assert_eq!(
+        check(TURN_INDICATOR | 1, 1),
+        Some(PositionError::OverlappingPieces)
+    );
+    assert_eq!(check(0, 0), Some(PositionError::InvalidTurnIndicator));
+    assert_eq!(
+        check(TURN_INDICATOR, TURN_INDICATOR),
+        Some(PositionError::InvalidTurnIndicator)
+    );
+    // a red piece floating over the first column
+    assert_eq!(
+        check(TURN_INDICATOR | 0b10, 0),
+        Some(PositionError::FloatingPiece { column: 0 })
+    );
+    assert_eq!(
+        check(TURN_INDICATOR, 0b11),
+        Some(PositionError::ImpossiblePieceCount { red: 0, blue: 2 })
+    );
+    // red has played once, so it's blue's turn
+    assert_eq!(check(TURN_INDICATOR | 1, 0), Some(PositionError::WrongTurn));
+
+    let mut b = Board::empty_board();
+    for &column in [0, 1, 0, 1, 0, 1, 0].iter() {
+        b.play_move(column);
+    }
+    let (red, blue) = b.serialize();
+    assert_eq!(
+        check(red & !GAME_OVER_INDICATOR, blue),
+        Some(PositionError::UnmarkedGameOver)
+    );
+    assert_eq!(
+        check(red, blue | GAME_OVER_INDICATOR),
+        Some(PositionError::TieWithWinner)
+    );
+    assert_eq!(
+        check(red & !GAME_OVER_INDICATOR, blue | GAME_OVER_INDICATOR),
+        Some(PositionError::WrongResult)
+    );
+    assert_eq!(
+        check(red ^ TURN_INDICATOR, blue ^ TURN_INDICATOR),
+        Some(PositionError::WrongTurn)
+    );
+}
+
+#[test]
+fn winning_lines_are_found() {
+    let mut b = Board::empty_board();
+    assert_eq!(b.winning_line(), vec![]);
+
+    // red fills the bottom row from the sides, the last piece in the middle
+    // joining six in a row
+    for &column in [0, 0, 1, 1, 4, 4, 5, 5, 2].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.winning_line(), vec![]);
+    b.play_move(6);
+    b.play_move(3);
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(
+        b.winning_line(),
+        vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0)]
+    );
+    assert_eq!(
+        b.display_with_winning_line(None, &GLYPHS, "", "", "", "/"),
+        "/......./......./......./......./bb..bb./RRRRRRb/"
+    );
+
+    // resignations have no line
+    let mut b = Board::empty_board();
+    b.play_move(3);
+    b.resign(Player::Blue);
+    assert_eq!(b.winning_line(), vec![]);
+}
+
+#[test]
+fn boards_come_in_other_sizes() {
+    assert_eq!(
+        BoardSize::from_str("9x7"),
+        Ok(BoardSize {
+            width: 9,
+            height: 7
+        })
+    );
+    assert_eq!(BoardSize::from_str("7x6"), Ok(BoardSize::STANDARD));
+    assert_eq!(BoardSize::from_str("10x6"), Err(()));
+    assert_eq!(BoardSize::from_str("3x3"), Err(()));
+    assert_eq!(BoardSize::from_str("seven"), Err(()));
+    assert_eq!(BoardSize::STANDARD.to_string(), "7x6");
+
+    // the ninth column only exists on wide boards, and holds seven pieces
+    let size = BoardSize::new(9, 7).unwrap();
+    let mut b = Board::empty_board_of_size(size);
+    for _ in 0..7 {
+        assert!(b.is_move_legal(8));
+        b.play_move(8);
+    }
+    assert!(!b.is_move_legal(8));
+    assert!(!b.is_move_legal(9));
+    assert_eq!(b.slot_at(8, 6), Slot::Piece(Player::Red));
+    assert!(!Board::empty_board().is_move_legal(8));
+
+    // red connects four along the bottom of the wide board, up to its last
+    // column
+    let mut b = Board::empty_board_of_size(size);
+    for &column in [5, 5, 6, 6, 7, 7, 8].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(b.winning_line(), vec![(5, 0), (6, 0), (7, 0), (8, 0)]);
+    assert_eq!(
+        Board::checked_unserialize(b.rules(), b.serialize()),
+        Ok(b.clone())
+    );
+    assert_eq!(
+        Board::checked_unserialize(Rules::STANDARD, b.serialize()),
+        Err(PositionError::StrayBits)
+    );
+
+    // a small board fills up sooner
+    let size = BoardSize::new(4, 4).unwrap();
+    let mut b = Board::empty_board_of_size(size);
+    for &column in [3, 2, 1, 0, 2, 0, 0, 0, 3, 3, 1, 2, 3, 1, 2, 1].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::GameOver(GameResult::Tie));
+    assert_eq!(
+        b.display(None, &GLYPHS, "", "", "", "/"),
+        "/bbrr/rbbb/brrr/brbr/"
+    );
+}
+
+#[test]
+fn lines_can_have_other_lengths() {
+    let size = BoardSize::new(9, 7).unwrap();
+    assert_eq!(
+        Rules::new(size, 5),
+        Some(Rules {
+            size,
+            line_length: 5,
+            variant: Variant::Standard,
+            players: 2
+        })
+    );
+    assert_eq!(Rules::new(size, 2), None);
+    assert_eq!(Rules::new(size, 7), None);
+    assert_eq!(Rules::new(BoardSize::new(5, 5).unwrap(), 6), None);
+    assert_eq!(Rules::new(size, 5).unwrap().to_string(), "connect 5 on 9x7");
+
+    // four in a row isn't enough in connect 5
+    let mut b = Board::empty_board_with_rules(Rules::new(size, 5).unwrap());
+    for &column in [0, 0, 1, 1, 2, 2, 3, 3].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    b.play_move(4);
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(
+        b.winning_line(),
+        vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]
+    );
+    assert_eq!(
+        Board::checked_unserialize(b.rules(), b.serialize()),
+        Ok(b.clone())
+    );
+    // with four in a row the game would have ended earlier
+    assert_eq!(
+        Board::checked_unserialize(
+            Rules {
+                size,
+                ..Rules::STANDARD
+            },
+            b.serialize()
+        ),
+        Err(PositionError::BothConnected)
+    );
+
+    // while three is enough in connect 3, diagonals included
+    let mut b = Board::empty_board_with_rules(Rules::new(BoardSize::STANDARD, 3).unwrap());
+    for &column in [0, 1, 1, 2, 3, 2].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    b.play_move(2);
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(b.winning_line(), vec![(0, 0), (1, 1), (2, 2)]);
+}
+
+#[test]
+fn pieces_can_be_popped() {
+    let rules = Rules {
+        variant: Variant::PopOut,
+        ..Rules::STANDARD
+    };
+    assert!(!Board::from_moves("12").unwrap().is_move_legal(pop_move(0)));
+
+    // only your own pieces can be popped, and popping can be undone
+    let mut b = Board::from_moves_on(rules, "12").unwrap();
+    assert_eq!(b.legal_moves(), vec![0, 1, 2, 3, 4, 5, 6, pop_move(0)]);
+    let before = b.clone();
+    b.play_move(pop_move(1));
+    assert_eq!(b, before);
+    b.play_move(pop_move(0));
+    assert_eq!(b.slot_at(0, 0), Slot::Empty);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Blue));
+    assert_eq!(b.undo_move(pop_move(0)), Ok(()));
+    assert_eq!(b, before);
+
+    // the pieces above fall down, making lines for both players, and the
+    // player who popped wins
+    let mut b = Board::from_moves_on(rules, "1223344117").unwrap();
+    b.play_move(pop_move(0));
+    assert_eq!(b.slot_at(0, 0), Slot::Piece(Player::Blue));
+    assert_eq!(b.slot_at(0, 2), Slot::Empty);
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(b.winning_line(), vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+    assert_eq!(
+        Board::checked_unserialize(rules, b.serialize()),
+        Ok(b.clone())
+    );
+
+    // a full board isn't a tie while the player to move can pop
+    let size = BoardSize::new(4, 4).unwrap();
+    let mut b = Board::empty_board_with_rules(Rules { size, ..rules });
+    for &column in [3, 2, 1, 0, 2, 0, 0, 0, 3, 3, 1, 2, 3, 1, 2, 1].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    assert_eq!(b.legal_moves(), vec![pop_move(1), pop_move(3)]);
+    assert_eq!(
+        Board::checked_unserialize(b.rules(), b.serialize()),
+        Ok(b.clone())
+    );
+}
+
+#[test]
+fn five_in_a_row_starts_with_filled_sides() {
+    let rules = Variant::FiveInARow.usual_rules();
+    assert_eq!(
+        rules.to_string(),
+        "connect 5 on 9x6 with filled side columns"
+    );
+    assert_eq!(Variant::from_str("fiveinarow"), Ok(Variant::FiveInARow));
+
+    let mut b = Board::empty_board_with_rules(rules);
+    assert_eq!(b.slot_at(0, 0), Slot::Piece(Player::Red));
+    assert_eq!(b.slot_at(0, 1), Slot::Piece(Player::Blue));
+    assert_eq!(b.slot_at(8, 0), Slot::Piece(Player::Blue));
+    assert_eq!(b.slot_at(8, 5), Slot::Piece(Player::Red));
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    assert_eq!(b.legal_moves(), vec![1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(b.undo_move(0), Err(UndoError::NotLastMove));
+
+    // the starting pieces count towards lines
+    for &column in [1, 1, 2, 2, 3, 3, 4].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(
+        b.winning_line(),
+        vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]
+    );
+    assert_eq!(
+        Board::checked_unserialize(rules, b.serialize()),
+        Ok(b.clone())
+    );
+    let moves = b.to_moves().unwrap();
+    assert_eq!(moves.len(), 7);
+    assert_eq!(Board::from_moves_on(rules, &moves), Ok(b));
+
+    let size = rules.size;
+    assert_eq!(
+        Board::checked_unserialize(rules, (size.turn_indicator(), 0)),
+        Err(PositionError::MissingStartingPieces)
+    );
+}
+
+#[test]
+fn lines_wrap_around_cylinders() {
+    let rules = Variant::Cylinder.usual_rules();
+    let moves = "6677112";
+    assert_eq!(
+        Board::from_moves(moves).unwrap().game_status(),
+        GameStatus::Turn(Player::Blue)
+    );
+    let b = Board::from_moves_on(rules, moves).unwrap();
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(b.winning_line(), vec![(0, 0), (1, 0), (5, 0), (6, 0)]);
+    assert_eq!(
+        Board::checked_unserialize(rules, b.serialize()),
+        Ok(b.clone())
+    );
+
+    // diagonals go around too
+    let b = Board::from_moves_on(rules, "56671771211").unwrap();
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert_eq!(b.winning_line(), vec![(0, 3), (4, 0), (5, 1), (6, 2)]);
+
+    // a row narrower than the line can't make one by going around
+    let narrow = Rules {
+        variant: Variant::Cylinder,
+        ..Rules::new(BoardSize::new(4, 6).unwrap(), 5).unwrap()
+    };
+    let b = Board::from_moves_on(narrow, "11223344").unwrap();
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+}
+
+#[test]
+fn blockers_take_up_slots() {
+    let mut b = Board::empty_board();
+    assert!(b.add_blocker(3));
+    assert!(b.add_blocker(3));
+    assert!(!b.add_blocker(7));
+    assert_eq!(b.slot_at(3, 0), Slot::Blocked);
+    assert_eq!(b.slot_at(3, 1), Slot::Blocked);
+    assert_eq!(b.starting_position(), b);
+
+    // pieces land on top of blockers, which break up lines
+    for &column in [3, 3, 3, 3, 0, 0, 1, 1, 2, 2, 4, 4].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.slot_at(3, 2), Slot::Piece(Player::Red));
+    assert!(!b.is_move_legal(3));
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    assert!(!b.add_blocker(4));
+    assert_eq!(b.starting_position().blocked(), b.blocked());
+    assert_eq!(
+        Board::checked_unserialize_with_blockers(b.rules(), b.blocked(), b.serialize()),
+        Ok(b.clone())
+    );
+    assert_eq!(Board::from_grid(&b.to_grid()), Ok(b.clone()));
+
+    // a column keeps a slot free
+    let mut b = Board::empty_board();
+    for _ in 0..5 {
+        assert!(b.add_blocker(0));
+    }
+    assert!(!b.add_blocker(0));
+    for column in 1..4 {
+        assert!(b.add_blocker(column));
+    }
+    assert_eq!(b.blocked().count_ones(), Board::MAX_BLOCKERS);
+    assert!(!b.add_blocker(5));
+
+    // blockers stay under the starting pieces
+    let mut b = Board::empty_board_with_rules(Variant::FiveInARow.usual_rules());
+    assert!(!b.add_blocker(0));
+    b.add_random_blockers(20, &mut rand::thread_rng());
+    assert_eq!(b.blocked().count_ones(), Board::MAX_BLOCKERS);
+    assert_eq!(b.slot_at(0, 0), Slot::Piece(Player::Red));
+
+    let rules = Rules::STANDARD;
+    let floating = rules.size.slot(2, 1);
+    assert_eq!(
+        Board::checked_unserialize_with_blockers(rules, floating, (rules.size.turn_indicator(), 0)),
+        Err(PositionError::FloatingPiece { column: 2 })
+    );
+}
+
+#[test]
+fn fog_hides_what_players_cannot_see() {
+    let rules = Variant::FogOfWar.usual_rules();
+    let mut b = Board::from_moves_on(rules, "17").unwrap();
+    let size = rules.size;
+    assert_eq!(
+        b.visible_to(Some(Player::Red)),
+        size.slot(0, 0) | size.slot(0, 1) | size.slot(1, 0) | size.slot(1, 1)
+    );
+    assert_eq!(b.visible_to(None), 0);
+    assert_eq!(
+        b.display(Some(Player::Blue), &GLYPHS, "", "", "", "/"),
+        "/???????/???????/???????/???????/?????../?????.b/"
+    );
+    assert_eq!(
+        b.display(None, &GLYPHS, "", "", "", "/"),
+        "/???????/???????/???????/???????/???????/???????/"
+    );
+
+    // neighbors across the board edge don't wrap, and opponents' pieces
+    // next to your own show
+    b.play_move(5);
+    assert_eq!(
+        b.display(Some(Player::Blue), &GLYPHS, "", "", "", "/"),
+        "/???????/???????/???????/???????/?????../?????rb/"
+    );
+
+    // the whole board shows once the game is over
+    for &column in [6, 1, 6, 1, 6].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Blue))
+    );
+    assert_eq!(b.visible_to(None), size.cells());
+
+    // other games hide nothing
+    let b = Board::from_moves("17").unwrap();
+    assert_eq!(b.visible_to(None), size.cells());
+}
+
+#[test]
+fn games_can_be_set_up() {
+    // handicap pieces are spread out in the middle, and the other player
+    // moves first
+    let mut b = Board::empty_board();
+    b.add_handicap(Player::Red, 2);
+    assert_eq!(b.slot_at(3, 0), Slot::Piece(Player::Red));
+    assert_eq!(b.slot_at(1, 0), Slot::Piece(Player::Red));
+    assert_eq!(b.preset().count_ones(), 2);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Blue));
+    assert_eq!(b.starting_position(), b);
+    assert_eq!(
+        Board::checked_unserialize(b.rules(), b.serialize()),
+        Err(PositionError::ImpossiblePieceCount { red: 2, blue: 0 })
+    );
+
+    // the set up pieces stay when moves are taken back or replayed
+    let start = b.clone();
+    b.play_move(3);
+    b.play_move(2);
+    assert_eq!(b.to_moves(), Some("43".to_string()));
+    assert_eq!(
+        Board::checked_unserialize_with_setup(b.rules(), 0, b.preset(), b.serialize()),
+        Ok(b.clone())
+    );
+    assert_eq!(b.starting_position(), start);
+    assert_eq!(b.undo_move(2), Ok(()));
+    assert_eq!(b.undo_move(3), Ok(()));
+    assert_eq!(b.undo_move(3), Err(UndoError::NotLastMove));
+    assert_eq!(b, start);
+
+    let mut b = Board::empty_board();
+    b.add_handicap(Player::Blue, 5);
+    assert_eq!(b.preset().count_ones(), Board::MAX_HANDICAP);
+    assert_eq!(b.slot_at(5, 0), Slot::Piece(Player::Blue));
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+
+    // an opening leaves the move with whoever is next
+    let mut b = Board::empty_board();
+    assert_eq!(
+        b.play_opening("48"),
+        Err(MovesError::InvalidCharacter {
+            position: 1,
+            found: '8'
+        })
+    );
+    assert_eq!(b, Board::empty_board());
+    assert_eq!(b.play_opening("445"), Ok(()));
+    assert_eq!(b.preset().count_ones(), 3);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Blue));
+    assert_eq!(b.to_moves(), Some(String::new()));
+    b.add_handicap(Player::Blue, 2);
+    assert_eq!(b.preset().count_ones(), 3);
+    assert_eq!(b.starting_position(), b);
+
+    // popping would move set up pieces out of their slots
+    let mut b = Board::empty_board_with_rules(Variant::PopOut.usual_rules());
+    b.add_handicap(Player::Blue, 2);
+    assert_eq!(b.preset(), 0);
+
+    let rules = Rules::STANDARD;
+    let empty_slot = rules.size.slot(0, 0);
+    assert_eq!(
+        Board::checked_unserialize_with_setup(
+            rules,
+            0,
+            empty_slot,
+            (rules.size.turn_indicator(), 0)
+        ),
+        Err(PositionError::MissingStartingPieces)
+    );
+}
+
+// Every line of the rules, walked slot by slot
+fn every_line(rules: Rules) -> Vec<u128> {
+    let (width, height) = (rules.size.width as i8, rules.size.height as i8);
+    let mut lines = vec![];
+    for x in 0..width {
+        for y in 0..height {
+            for &(dx, dy) in DIRECTIONS.iter() {
+                let line = (0..rules.line_length as i8)
+                    .map(|i| {
+                        let (x, y) = (x + i * dx, y + i * dy);
+                        let x = if rules.wraps() {
+                            x.rem_euclid(width)
+                        } else {
+                            x
+                        };
+                        (x >= 0 && x < width && y >= 0 && y < height)
+                            .then(|| rules.size.slot(x as u8, y as u8))
+                    })
+                    .try_fold(0, |line, slot| slot.map(|slot| line | slot));
+                lines.extend(line);
+            }
+        }
+    }
+    lines
+}
+
+#[test]
+fn shifted_lines_match_walked_lines() {
+    let mut rng = rand::thread_rng();
+    for &variant in Variant::ALL.iter() {
+        for (width, height, line_length) in [(4, 4, 3), (4, 8, 6), (7, 6, 4), (9, 4, 5), (9, 8, 6)]
+        {
+            let rules = Rules {
+                variant,
+                ..Rules::new(BoardSize::new(width, height).unwrap(), line_length).unwrap()
+            };
+            let lines = every_line(rules);
+            for _ in 0..200 {
+                let pieces = rng.gen::<u128>() & rng.gen::<u128>() & rules.size.cells();
+                let expected = lines
+                    .iter()
+                    .filter(|&&line| line & pieces == line)
+                    .fold(0, |slots, &line| slots | line);
+                assert_eq!(line_slots(rules, pieces), expected, "{}", rules);
+                assert_eq!(has_line(rules, pieces), expected != 0, "{}", rules);
+            }
+        }
+    }
+}
+
+#[test]
+fn pieces_drop_on_the_first_empty_slot() {
+    let size = BoardSize::new(9, 8).unwrap();
+    let mut rng = rand::thread_rng();
+    for _ in 0..200 {
+        let heights: Vec<u8> = (0..size.width)
+            .map(|_| rng.gen_range(0..=size.height))
+            .collect();
+        let occupied = (0..size.width).fold(0, |occupied, x| {
+            (0..heights[x as usize]).fold(occupied, |occupied, y| occupied | size.slot(x, y))
+        });
+        let expected = (0..size.width)
+            .filter(|&x| heights[x as usize] < size.height)
+            .fold(0, |slots, x| slots | size.slot(x, heights[x as usize]));
+        assert_eq!(size.drop_slots(occupied), expected);
+    }
+}
+
+#[test]
+fn turns_go_around_the_table() {
+    let rules = Rules::usual_for_players(3);
+    assert_eq!(rules.size, BoardSize::new(9, 7).unwrap());
+    assert_eq!(
+        Rules::usual_for_players(4).size,
+        BoardSize::new(9, 8).unwrap()
+    );
+    assert_eq!(rules.with_players(5), None);
+    assert_eq!(Variant::PopOut.usual_rules().with_players(3), None);
+    assert_eq!(Variant::FiveInARow.usual_rules().with_players(4), None);
+
+    let mut b = Board::empty_board_with_rules(rules);
+    assert_eq!(b.players().count(), 3);
+    for &(column, player) in [(4, Player::Red), (4, Player::Blue), (4, Player::Green)].iter() {
+        assert_eq!(b.game_status(), GameStatus::Turn(player));
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    assert_eq!(b.slot_at(4, 2), Slot::Piece(Player::Green));
+    assert_eq!(b.slot_at(4, 3), Slot::Empty);
+
+    let mut b = Board::empty_board_with_rules(Rules::usual_for_players(4));
+    for column in 0..3 {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Yellow));
+    b.undo_move(2).unwrap();
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Green));
+    b.play_move(2);
+    b.play_move(3);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    assert_eq!(
+        Board::checked_unserialize_group(b.rules(), 0, b.serialize(), b.serialize_group()),
+        Ok(b)
+    );
+}
+
+#[test]
+fn group_games_end() {
+    let mut b = Board::empty_board_with_rules(Rules::usual_for_players(3));
+    for &column in [0, 1, 2, 0, 1, 2, 0, 1, 2, 0].iter() {
+        assert!(b.is_move_legal(column));
+        b.play_move(column);
+    }
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert!(b.legal_moves().is_empty());
+
+    // a full board with no line is a tie
+    let small = Rules::new(BoardSize::new(4, 4).unwrap(), 4)
+        .unwrap()
+        .with_players(3)
+        .unwrap();
+    let mut b = Board::empty_board_with_rules(small);
+    for &column in [0, 2, 0, 3, 1, 1, 0, 2, 1, 2, 3, 0, 3, 3, 2, 1].iter() {
+        b.play_move(column);
+    }
+    assert_eq!(b.game_status(), GameStatus::GameOver(GameResult::Tie));
+}
+
+#[test]
+fn resigning_players_drop_out() {
+    let mut b = Board::empty_board_with_rules(Rules::usual_for_players(4));
+    b.play_move(0);
+    b.resign(Player::Blue);
+    assert!(!b.is_in_play(Player::Blue));
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Green));
+    b.resign(Player::Yellow);
+    b.play_move(1);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Red));
+    b.play_move(2);
+    assert_eq!(b.game_status(), GameStatus::Turn(Player::Green));
+    assert_eq!(
+        Board::checked_unserialize_group(b.rules(), 0, b.serialize(), b.serialize_group()),
+        Ok(b.clone())
+    );
+
+    // the last player left wins
+    b.resign(Player::Green);
+    assert_eq!(
+        b.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+    assert!(!b.is_in_play(Player::Blue));
+    assert!(b.is_in_play(Player::Green));
+}
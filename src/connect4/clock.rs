@@ -0,0 +1,156 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::board::Player;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimeControl {
+    Untimed,
+    Blitz,
+    Rapid,
+    Correspondence,
+}
+
+/*
+  Times are measured in seconds since the unix epoch.
+
+  Blitz and rapid games give each player a fixed amount of time for the whole game, which is
+  kept in red_time_left and blue_time_left (and green_time_left and yellow_time_left in group
+  games) while another player is thinking. Correspondence
+  games give a fixed amount of time for every move instead.
+
+  The deadline is the time at which the player to move runs out of time. It is None for
+  untimed games.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    pub red_time_left: i64,
+    pub blue_time_left: i64,
+    pub green_time_left: i64,
+    pub yellow_time_left: i64,
+    pub deadline: Option<i64>,
+    pub warned: bool,
+}
+
+impl TimeControl {
+    fn time_per_game(&self) -> Option<i64> {
+        match self {
+            TimeControl::Blitz => Some(3 * 60),
+            TimeControl::Rapid => Some(10 * 60),
+            _ => None,
+        }
+    }
+
+    fn time_per_move(&self) -> Option<i64> {
+        match self {
+            TimeControl::Correspondence => Some(2 * 24 * 60 * 60),
+            _ => None,
+        }
+    }
+
+    // How long before running out of time players get a warning
+    pub fn warning_time(&self) -> i64 {
+        match self {
+            TimeControl::Untimed => 0,
+            TimeControl::Blitz => 30,
+            TimeControl::Rapid => 60,
+            TimeControl::Correspondence => 6 * 60 * 60,
+        }
+    }
+}
+
+impl Clock {
+    pub fn untimed() -> Clock {
+        Clock {
+            time_control: TimeControl::Untimed,
+            red_time_left: 0,
+            blue_time_left: 0,
+            green_time_left: 0,
+            yellow_time_left: 0,
+            deadline: None,
+            warned: false,
+        }
+    }
+
+    // A clock running for red, who plays first, with the same time for
+    // every player
+    pub fn start(time_control: TimeControl, now: i64) -> Clock {
+        let time_left = match time_control
+            .time_per_game()
+            .or(time_control.time_per_move())
+        {
+            None => return Clock::untimed(),
+            Some(time_left) => time_left,
+        };
+        Clock {
+            time_control,
+            red_time_left: time_left,
+            blue_time_left: time_left,
+            green_time_left: time_left,
+            yellow_time_left: time_left,
+            deadline: Some(now + time_left),
+            warned: false,
+        }
+    }
+
+    // Stops the clock of the player who was thinking and starts the one of the
+    // player who has to move next (which may be the same, after a takeback)
+    pub fn pass_turn(&mut self, from: Player, to: Player, now: i64) {
+        let deadline = match self.deadline {
+            None => return,
+            Some(deadline) => deadline,
+        };
+
+        if self.time_control.time_per_game().is_some() {
+            let time_left = if deadline > now { deadline - now } else { 0 };
+            *self.time_left_mut(from) = time_left;
+        }
+
+        let next_time_left = *self.time_left_mut(to);
+        self.deadline = Some(now + next_time_left);
+        self.warned = false;
+    }
+
+    pub fn time_left(&self, player: Player) -> i64 {
+        match player {
+            Player::Red => self.red_time_left,
+            Player::Blue => self.blue_time_left,
+            Player::Green => self.green_time_left,
+            Player::Yellow => self.yellow_time_left,
+        }
+    }
+
+    fn time_left_mut(&mut self, player: Player) -> &mut i64 {
+        match player {
+            Player::Red => &mut self.red_time_left,
+            Player::Blue => &mut self.blue_time_left,
+            Player::Green => &mut self.green_time_left,
+            Player::Yellow => &mut self.yellow_time_left,
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        match self.deadline {
+            None => false,
+            Some(deadline) => deadline <= now,
+        }
+    }
+
+    pub fn needs_warning(&self, now: i64) -> bool {
+        match self.deadline {
+            None => false,
+            Some(deadline) => !self.warned && deadline - now <= self.time_control.warning_time(),
+        }
+    }
+}
+
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock set before 1970")
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn untimed_games_never_expire() {
+    let c = Clock::start(TimeControl::Untimed, 1000);
+    assert_eq!(c, Clock::untimed());
+    assert!(!c.is_expired(i64::MAX));
+    assert!(!c.needs_warning(i64::MAX));
+}
+
+#[test]
+fn time_per_game_is_kept_between_moves() {
+    let mut c = Clock::start(TimeControl::Blitz, 1000);
+    assert_eq!(c.deadline, Some(1180));
+
+    // red thinks for 100 seconds
+    c.pass_turn(Player::Red, Player::Blue, 1100);
+    assert_eq!(c.red_time_left, 80);
+    assert_eq!(c.deadline, Some(1280));
+
+    // blue thinks for 10 seconds
+    c.pass_turn(Player::Blue, Player::Red, 1110);
+    assert_eq!(c.blue_time_left, 170);
+    assert_eq!(c.deadline, Some(1190));
+
+    assert!(!c.needs_warning(1150));
+    assert!(c.needs_warning(1160));
+    assert!(!c.is_expired(1189));
+    assert!(c.is_expired(1190));
+}
+
+#[test]
+fn time_per_move_is_reset_every_move() {
+    let mut c = Clock::start(TimeControl::Correspondence, 0);
+    let two_days = 2 * 24 * 60 * 60;
+    assert_eq!(c.deadline, Some(two_days));
+
+    c.warned = true;
+    c.pass_turn(Player::Red, Player::Blue, two_days - 1);
+    assert_eq!(c.deadline, Some(2 * two_days - 1));
+    assert!(!c.warned);
+}
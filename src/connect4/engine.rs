@@ -0,0 +1,56 @@
+use super::board::Board;
+use super::monte_carlo_ai;
+
+use std::fmt;
+
+// Anything that can play for the bot. Engines get the position both as a board
+// and as the columns played to reach it, so that each can use whichever suits
+// it. Like monte_carlo_ai, they fail on finished games.
+pub trait Engine: Sync {
+    fn best_move(&self, board: &Board, moves: &[u8]) -> Result<u8, EngineError>;
+
+    // Expected score (1 for a win, 1/2 for a tie, 0 for a loss) of the player
+    // whose turn it is
+    fn evaluate(&self, board: &Board, moves: &[u8]) -> Result<f64, EngineError>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EngineError {
+    GameOver,
+    // the engine couldn't come up with an answer, for reasons it reports itself
+    NoAnswer,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::GameOver => write!(f, "the game is already over"),
+            EngineError::NoAnswer => write!(f, "the engine gave no answer"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MonteCarlo {
+    pub rollouts: u32,
+    pub exploration: f64,
+}
+
+impl MonteCarlo {
+    pub fn at_level(ai_level: u8) -> MonteCarlo {
+        MonteCarlo {
+            rollouts: monte_carlo_ai::rollouts_at_level(ai_level),
+            exploration: monte_carlo_ai::DEFAULT_EXPLORATION,
+        }
+    }
+}
+
+impl Engine for MonteCarlo {
+    fn best_move(&self, board: &Board, _moves: &[u8]) -> Result<u8, EngineError> {
+        monte_carlo_ai::ai_move_with_exploration(board, self.rollouts, self.exploration)
+    }
+
+    fn evaluate(&self, board: &Board, _moves: &[u8]) -> Result<f64, EngineError> {
+        monte_carlo_ai::evaluate_with_exploration(board, self.rollouts, self.exploration)
+    }
+}
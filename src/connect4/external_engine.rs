@@ -0,0 +1,223 @@
+use super::board;
+use super::board::Board;
+use super::engine::{Engine, EngineError};
+
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/*
+  External engines are executables that talk to the bot over their standard input and output,
+  one line at a time.
+
+  For every position the bot writes a line with the columns played so far, numbered 1 to 7 from
+  the left, and nothing else (so the empty board is an empty line). For example, after red
+  plays in the middle column and blue next to it:
+
+    45
+
+  The engine answers with a line holding the column it would play, and optionally the score it
+  expects for the player to move, from 0 (a loss) to 1 (a win):
+
+    4 0.62
+
+  The process is started on the first position and kept running for the following ones. If it
+  exits, takes longer than its timeout to answer, or says something that can't be understood, it
+  is killed and started again for the next position.
+*/
+
+// Long enough for a strong engine to think, short enough that players aren't left waiting
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidReply(String),
+    NoScore,
+    Timeout,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "failed to talk to the engine: {}", e),
+            Error::InvalidReply(reply) => write!(f, "invalid reply from the engine: {:?}", reply),
+            Error::NoScore => write!(f, "the engine gave no score"),
+            Error::Timeout => write!(f, "the engine took too long to reply"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Reply {
+    pub column: u8,
+    pub score: Option<f64>,
+}
+
+// The engine's output is read on a thread of its own, which sends each line
+// over a channel, so that waiting for a reply can give up after a while
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<io::Result<String>>,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Clones share the same process
+#[derive(Clone)]
+pub struct ExternalEngine {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    process: Arc<Mutex<Option<Process>>>,
+}
+
+impl ExternalEngine {
+    pub fn new(program: &str, args: &[String]) -> ExternalEngine {
+        ExternalEngine {
+            program: program.to_string(),
+            args: args.to_vec(),
+            timeout: DEFAULT_TIMEOUT,
+            process: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> ExternalEngine {
+        ExternalEngine { timeout, ..self }
+    }
+
+    // Takes a whole command line, split on whitespace
+    pub fn from_command_line(command_line: &str) -> Option<ExternalEngine> {
+        let words: Vec<String> = command_line.split_whitespace().map(String::from).collect();
+        words
+            .split_first()
+            .map(|(program, args)| ExternalEngine::new(program, args))
+    }
+
+    // Asks the engine for a move in the position reached by the given columns
+    pub fn query(&self, board: &Board, moves: &[u8]) -> Result<Reply, Error> {
+        let mut process = self.process.lock().expect("engine lock poisoned");
+        if process.is_none() {
+            *process = Some(self.spawn()?);
+        }
+
+        let result = exchange(process.as_mut().unwrap(), moves, self.timeout).and_then(|line| {
+            match parse_reply(&line) {
+                Some(reply) if board.is_move_legal(reply.column) => Ok(reply),
+                _ => Err(Error::InvalidReply(line)),
+            }
+        });
+        if result.is_err() {
+            // kill it and start afresh next time, so that a late reply can't
+            // be taken for the answer to the next position
+            *process = None;
+        }
+        result
+    }
+
+    fn spawn(&self) -> Result<Process, Error> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("child stdin is piped");
+        let stdout = child.stdout.take().expect("child stdout is piped");
+
+        // the thread ends once the process is killed and its output closes
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdout = BufReader::new(stdout);
+            loop {
+                let mut line = String::new();
+                let read = stdout.read_line(&mut line).map(|_| line);
+                let done = !matches!(&read, Ok(line) if !line.is_empty());
+                if sender.send(read).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Ok(Process {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    // Failures are logged here, as the bot carries on with the built-in engine
+    fn report(&self, e: Error) -> EngineError {
+        println!("External engine {}: {}", self.program, e);
+        EngineError::NoAnswer
+    }
+}
+
+impl Engine for ExternalEngine {
+    fn best_move(&self, board: &Board, moves: &[u8]) -> Result<u8, EngineError> {
+        self.query(board, moves)
+            .map(|reply| reply.column)
+            .map_err(|e| self.report(e))
+    }
+
+    fn evaluate(&self, board: &Board, moves: &[u8]) -> Result<f64, EngineError> {
+        self.query(board, moves)
+            .and_then(|reply| reply.score.ok_or(Error::NoScore))
+            .map_err(|e| self.report(e))
+    }
+}
+
+fn exchange(process: &mut Process, moves: &[u8], timeout: Duration) -> Result<String, Error> {
+    writeln!(process.stdin, "{}", board::moves_to_string(moves))?;
+    process.stdin.flush()?;
+
+    let line = match process.lines.recv_timeout(timeout) {
+        Ok(line) => line?,
+        Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+        Err(RecvTimeoutError::Disconnected) => String::new(),
+    };
+    if line.is_empty() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the engine exited",
+        )));
+    }
+    Ok(line.trim().to_string())
+}
+
+fn parse_reply(line: &str) -> Option<Reply> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (column, score) = match words.as_slice() {
+        [column] => (column, None),
+        [column, score] => (column, Some(f64::from_str(score).ok()?)),
+        _ => return None,
+    };
+    let column = u8::from_str(column).ok().filter(|c| (1..=7).contains(c))?;
+    if score.is_some_and(|score| !(0.0..=1.0).contains(&score)) {
+        return None;
+    }
+    Some(Reply {
+        column: column - 1,
+        score,
+    })
+}
+
+#[cfg(test)]
+mod test;
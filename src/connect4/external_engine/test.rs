@@ -0,0 +1,99 @@
+use super::*;
+
+fn shell_engine(script: &str) -> ExternalEngine {
+    ExternalEngine::new("sh", &["-c".to_string(), script.to_string()])
+}
+
+fn board_after(moves: &[u8]) -> Board {
+    let mut b = Board::empty_board();
+    for &column in moves {
+        b.play_move(column);
+    }
+    b
+}
+
+#[test]
+fn replies_are_parsed() {
+    assert_eq!(
+        parse_reply("4"),
+        Some(Reply {
+            column: 3,
+            score: None
+        })
+    );
+    assert_eq!(
+        parse_reply(" 1 0.25 "),
+        Some(Reply {
+            column: 0,
+            score: Some(0.25)
+        })
+    );
+    assert_eq!(parse_reply(""), None);
+    assert_eq!(parse_reply("0"), None);
+    assert_eq!(parse_reply("8"), None);
+    assert_eq!(parse_reply("4 1.5"), None);
+    assert_eq!(parse_reply("4 good"), None);
+    assert_eq!(parse_reply("4 0.5 extra"), None);
+}
+
+#[test]
+fn engine_gets_the_moves_played() {
+    // answers the last column played, and the first one on an empty board
+    let engine =
+        shell_engine("while read moves; do echo \"$(echo \"${moves}1\" | cut -c1) 0.5\"; done");
+
+    let reply = engine
+        .query(&Board::empty_board(), &[])
+        .expect("the engine should reply");
+    assert_eq!(
+        reply,
+        Reply {
+            column: 0,
+            score: Some(0.5)
+        }
+    );
+
+    let moves = [5, 2, 3];
+    let board = board_after(&moves);
+    assert_eq!(engine.best_move(&board, &moves), Ok(5));
+    assert_eq!(engine.evaluate(&board, &moves), Ok(0.5));
+}
+
+#[test]
+fn illegal_replies_are_rejected() {
+    let engine = shell_engine("while read moves; do echo 1; done");
+    let moves = [0, 0, 0, 0, 0, 0];
+    let board = board_after(&moves);
+    match engine.query(&board, &moves) {
+        Err(Error::InvalidReply(reply)) => assert_eq!(reply, "1"),
+        other => panic!("expected an invalid reply, got {:?}", other),
+    }
+    assert_eq!(
+        engine.evaluate(&Board::empty_board(), &[]),
+        Err(EngineError::NoAnswer)
+    );
+}
+
+#[test]
+fn engines_that_exit_are_restarted() {
+    // answers a single position and exits
+    let engine = shell_engine("read moves; echo 7");
+    let board = Board::empty_board();
+    assert_eq!(engine.best_move(&board, &[]), Ok(6));
+
+    // the process may not have exited yet when the next line is written, so
+    // this can fail either while writing or while reading
+    assert!(engine.query(&board, &[]).is_err());
+    assert_eq!(engine.best_move(&board, &[]), Ok(6));
+}
+
+#[test]
+fn engines_that_hang_are_killed() {
+    // reads a position and never answers
+    let engine = shell_engine("read moves; exec sleep 5").with_timeout(Duration::from_millis(100));
+    match engine.query(&Board::empty_board(), &[]) {
+        Err(Error::Timeout) => (),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert!(engine.process.lock().unwrap().is_none());
+}
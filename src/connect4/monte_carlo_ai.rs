@@ -1,22 +1,150 @@
-use super::board::Board;
 use super::board::GameStatus;
+use super::board::{Board, Rules};
+use super::engine::EngineError;
+
+mod tree_search;
 
 extern "C" {
     fn __c_montecarlo_c4_ai(red_pieces: u64, blue_pieces: u64, tree_size: u32) -> u8;
+    fn __c_montecarlo_c4_ai_with_exploration(
+        red_pieces: u64,
+        blue_pieces: u64,
+        tree_size: u32,
+        exp_constant: f64,
+    ) -> u8;
+    fn __c_montecarlo_c4_evaluate(red_pieces: u64, blue_pieces: u64, tree_size: u32) -> f64;
+    fn __c_montecarlo_c4_evaluate_with_exploration(
+        red_pieces: u64,
+        blue_pieces: u64,
+        tree_size: u32,
+        exp_constant: f64,
+    ) -> f64;
+}
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 10;
+
+// The exploration constant of the tree search, EXP_CONSTANT in montecarlo.h
+pub const DEFAULT_EXPLORATION: f64 = 1.25;
+
+// How many rollouts the bot gets to search with at each level. Each level
+// doubles the previous one, with level 5 at 32768.
+pub fn rollouts_at_level(ai_level: u8) -> u32 {
+    1024 << ai_level.clamp(MIN_LEVEL, MAX_LEVEL)
 }
 
-pub fn ai_move(b: &Board, rollout_number: u32) -> Result<u8, ()> {
+// The C engine only knows connect four on the standard board without blockers,
+// the other games are searched in Rust
+fn in_c(b: &Board) -> bool {
+    b.rules() == Rules::STANDARD && b.blocked() == 0
+}
+
+// The bitboards as the C engine takes them. Standard boards fit in 64 bits.
+fn c_board(b: &Board) -> (u64, u64) {
+    let (red_pieces, blue_pieces) = b.serialize();
+    (red_pieces as u64, blue_pieces as u64)
+}
+
+pub fn ai_move(b: &Board, rollout_number: u32) -> Result<u8, EngineError> {
     match b.game_status() {
-        GameStatus::GameOver(_) => Err(()),
+        GameStatus::GameOver(_) => Err(EngineError::GameOver),
+        GameStatus::Turn(_) if !in_c(b) => {
+            tree_search::search(b, rollout_number, DEFAULT_EXPLORATION)
+                .map(|(column, _)| column)
+                .map_err(|_| EngineError::GameOver)
+        }
         GameStatus::Turn(_) => {
-            let (red_pieces, blue_pieces) = b.serialize();
+            let (red_pieces, blue_pieces) = c_board(b);
             let imported_ai_move: u8 =
                 unsafe { __c_montecarlo_c4_ai(red_pieces, blue_pieces, rollout_number) };
             if imported_ai_move == 7 {
-                Err(())
+                Err(EngineError::NoAnswer)
             } else {
                 Ok(imported_ai_move)
             }
         }
     }
 }
+
+// Same as ai_move, weighing unexplored moves with the given exploration
+// constant instead of the default one
+pub fn ai_move_with_exploration(
+    b: &Board,
+    rollout_number: u32,
+    exploration: f64,
+) -> Result<u8, EngineError> {
+    match b.game_status() {
+        GameStatus::GameOver(_) => Err(EngineError::GameOver),
+        GameStatus::Turn(_) if !in_c(b) => tree_search::search(b, rollout_number, exploration)
+            .map(|(column, _)| column)
+            .map_err(|_| EngineError::GameOver),
+        GameStatus::Turn(_) => {
+            let (red_pieces, blue_pieces) = c_board(b);
+            let imported_ai_move: u8 = unsafe {
+                __c_montecarlo_c4_ai_with_exploration(
+                    red_pieces,
+                    blue_pieces,
+                    rollout_number,
+                    exploration,
+                )
+            };
+            if imported_ai_move == 7 {
+                Err(EngineError::NoAnswer)
+            } else {
+                Ok(imported_ai_move)
+            }
+        }
+    }
+}
+
+// Expected score (1 for a win, 1/2 for a tie, 0 for a loss) of the player
+// whose turn it is, as estimated by the rollouts through the best move
+pub fn evaluate(b: &Board, rollout_number: u32) -> Result<f64, EngineError> {
+    match b.game_status() {
+        GameStatus::GameOver(_) => Err(EngineError::GameOver),
+        GameStatus::Turn(_) if !in_c(b) => {
+            tree_search::search(b, rollout_number, DEFAULT_EXPLORATION)
+                .map(|(_, score)| score)
+                .map_err(|_| EngineError::GameOver)
+        }
+        GameStatus::Turn(_) => {
+            let (red_pieces, blue_pieces) = c_board(b);
+            let score =
+                unsafe { __c_montecarlo_c4_evaluate(red_pieces, blue_pieces, rollout_number) };
+            if score < 0.0 {
+                Err(EngineError::NoAnswer)
+            } else {
+                Ok(score)
+            }
+        }
+    }
+}
+
+pub fn evaluate_with_exploration(
+    b: &Board,
+    rollout_number: u32,
+    exploration: f64,
+) -> Result<f64, EngineError> {
+    match b.game_status() {
+        GameStatus::GameOver(_) => Err(EngineError::GameOver),
+        GameStatus::Turn(_) if !in_c(b) => tree_search::search(b, rollout_number, exploration)
+            .map(|(_, score)| score)
+            .map_err(|_| EngineError::GameOver),
+        GameStatus::Turn(_) => {
+            let (red_pieces, blue_pieces) = c_board(b);
+            let score = unsafe {
+                __c_montecarlo_c4_evaluate_with_exploration(
+                    red_pieces,
+                    blue_pieces,
+                    rollout_number,
+                    exploration,
+                )
+            };
+            if score < 0.0 {
+                Err(EngineError::NoAnswer)
+            } else {
+                Ok(score)
+            }
+        }
+    }
+}
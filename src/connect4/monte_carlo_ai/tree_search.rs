@@ -0,0 +1,162 @@
+use super::super::board::{Board, GameResult, GameStatus, Player};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/*
+  The same search as montecarlo.c, written against Board so that it can play on the boards the C
+  engine knows nothing about. Every round walks down the tree from the root, picking moves by
+  their upper confidence bound, adds a node for the first move that isn't in the tree yet, and
+  plays random moves from there to the end of the game. The result is then counted for every
+  move on the way down.
+
+  Ties count as half a win, where the C engine tosses a coin for them.
+
+  Moves are whatever the board allows, so PopOut games get their pops searched too. Those games
+  can go on forever, so rollouts that last longer than a few boards' worth of moves are called
+  a tie.
+*/
+
+struct Edge {
+    m: u8,
+    node: Option<usize>,
+    visits: u32,
+    wins: f64,
+}
+
+struct Node {
+    // The player choosing among the edges
+    player: Player,
+    visits: u32,
+    edges: Vec<Edge>,
+}
+
+impl Node {
+    fn new(board: &Board) -> Node {
+        let player = match board.game_status() {
+            GameStatus::Turn(player) => player,
+            // finished games have no moves, so nobody chooses
+            GameStatus::GameOver(_) => Player::Red,
+        };
+        Node {
+            player,
+            visits: 0,
+            edges: board
+                .legal_moves()
+                .into_iter()
+                .map(|m| Edge {
+                    m,
+                    node: None,
+                    visits: 0,
+                    wins: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    // The edge to follow, or None if the game is over. Moves that were never
+    // tried go first.
+    fn select(&self, exploration: f64, rng: &mut impl Rng) -> Option<usize> {
+        let log_visits = f64::from(self.visits).ln();
+        let bound = |edge: &Edge| {
+            if edge.visits == 0 {
+                f64::INFINITY
+            } else {
+                let visits = f64::from(edge.visits);
+                edge.wins / visits + exploration * (log_visits / visits).sqrt()
+            }
+        };
+
+        let best = self
+            .edges
+            .iter()
+            .map(bound)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let candidates: Vec<usize> = (0..self.edges.len())
+            .filter(|&i| bound(&self.edges[i]) == best)
+            .collect();
+        candidates.choose(rng).copied()
+    }
+}
+
+// Plays random moves until the game is over
+fn rollout(board: &mut Board, rng: &mut impl Rng) -> GameResult {
+    let size = board.size();
+    let max_moves = 4 * usize::from(size.width) * usize::from(size.height);
+    for _ in 0..max_moves {
+        match board.game_status() {
+            GameStatus::GameOver(result) => return result,
+            GameStatus::Turn(_) => {
+                let m = *board
+                    .legal_moves()
+                    .choose(rng)
+                    .expect("unfinished games have legal moves");
+                board.play_move(m);
+            }
+        }
+    }
+    match board.game_status() {
+        GameStatus::GameOver(result) => result,
+        GameStatus::Turn(_) => GameResult::Tie,
+    }
+}
+
+fn score(result: GameResult, player: Player) -> f64 {
+    match result {
+        GameResult::Winner(winner) if winner == player => 1.0,
+        GameResult::Winner(_) => 0.0,
+        GameResult::Tie => 0.5,
+    }
+}
+
+// The most visited move after the given number of rounds, along with the
+// share of the rollouts through it that the player to move won
+pub fn search(board: &Board, rounds: u32, exploration: f64) -> Result<(u8, f64), ()> {
+    if let GameStatus::GameOver(_) = board.game_status() {
+        return Err(());
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut tree = vec![Node::new(board)];
+    for _ in 1..rounds {
+        let mut position = board.clone();
+        let mut path = vec![];
+        let mut current = 0;
+
+        // selection, then expansion of the first move out of the tree
+        while let Some(edge) = tree[current].select(exploration, &mut rng) {
+            position.play_move(tree[current].edges[edge].m);
+            path.push((current, edge));
+            match tree[current].edges[edge].node {
+                Some(next) => current = next,
+                None => {
+                    tree.push(Node::new(&position));
+                    tree[current].edges[edge].node = Some(tree.len() - 1);
+                    break;
+                }
+            }
+        }
+
+        let result = rollout(&mut position, &mut rng);
+
+        for (node, edge) in path {
+            let node = &mut tree[node];
+            node.visits += 1;
+            node.edges[edge].visits += 1;
+            node.edges[edge].wins += score(result, node.player);
+        }
+    }
+
+    tree[0]
+        .edges
+        .iter()
+        .max_by_key(|edge| edge.visits)
+        .map(|edge| {
+            let visits = f64::from(edge.visits.max(1));
+            (edge.m, edge.wins / visits)
+        })
+        .ok_or(())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,29 @@
+use super::*;
+use crate::connect4::board::{BoardSize, Rules};
+
+#[test]
+fn takes_an_immediate_win() {
+    // red has three in the last column of a wide board, blue three in the first
+    let size = BoardSize::new(9, 7).unwrap();
+    let b = Board::from_moves_on(Rules::new(size, 4).unwrap(), "919191").unwrap();
+    assert_eq!(search(&b, 2000, 1.25).map(|(column, _)| column), Ok(8));
+}
+
+#[test]
+fn finished_games_have_no_moves() {
+    let size = BoardSize::new(6, 5).unwrap();
+    let b = Board::from_moves_on(Rules::new(size, 4).unwrap(), "1212121").unwrap();
+    assert_eq!(search(&b, 100, 1.25), Err(()));
+}
+
+#[test]
+fn wins_with_longer_lines() {
+    // both have four in a row, which isn't enough, and red can make five at
+    // either end of the bottom row
+    let rules = Rules::new(BoardSize::new(9, 7).unwrap(), 5).unwrap();
+    let b = Board::from_moves_on(rules, "22334455").unwrap();
+    assert!(matches!(
+        search(&b, 20000, 1.25).map(|(column, _)| column),
+        Ok(0) | Ok(5)
+    ));
+}
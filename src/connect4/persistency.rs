@@ -2,44 +2,92 @@ use rusqlite::params;
 use rusqlite::Connection;
 use rusqlite::OptionalExtension;
 
-use crate::connect4::board::{Board, GameStatus, Player};
+use std::time::Duration;
+
+use crate::connect4::board;
+use crate::connect4::board::{
+    Board, BoardSize, GameResult, GameStatus, Player, PositionError, Rules, Variant,
+};
+use crate::connect4::clock::{Clock, TimeControl};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum NotCompletedReason {
-    RedAlreadyPlaying,
-    BlueAlreadyPlaying,
+    AlreadyPlayingEachOther,
     PlayerAlreadyPlaying,
     PlayerHasNoMatches,
+    SeveralMatches,
+    NoSuchMatch,
     UnrecoverableError,
     InvalidAiLevel,
     InteractionRequestedForGameOver,
     NoSuchInteraction,
+    InvalidPlayerCount,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InteractionKind {
+    PlayMove,
+    AcceptDraw,
+    ApproveTakeback,
+    // Rematch interactions refer to a row of finished_matches through archive_id
+    Rematch,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Termination {
+    Normal,
+    DrawAgreed,
+    Resignation,
+    Timeout,
+    // the last two end the match without a result
+    Abandoned,
+    Cancelled,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct PlayerRecord {
+    pub wins: u32,
+    pub losses: u32,
+    pub ties: u32,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     SqliteError(rusqlite::Error),
     NotCompleted(NotCompletedReason),
+    // The stored board of the match with the given id is not one a game could
+    // have reached
+    CorruptedMatch(u64, PositionError),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct HumanMatch {
     pub match_id: u64,
-    pub server_id: u64,
+    // 0 for matches started before guild ids were stored, until their guild is
+    // looked up at startup
+    pub guild_id: u64,
+    pub channel_id: u64,
     pub red_player_id: u64,
     pub blue_player_id: u64,
+    // only seated in group games, green before yellow
+    pub green_player_id: Option<u64>,
+    pub yellow_player_id: Option<u64>,
     pub board: Board,
+    pub move_history: Vec<u8>,
     pub message_id: Option<u64>,
+    pub clock: Clock,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ComputerMatch {
     pub match_id: u64,
-    pub server_id: u64,
+    pub guild_id: u64,
+    pub channel_id: u64,
     pub player_id: u64,
     pub player_is_red: bool,
     pub ai_level: u8,
     pub board: Board,
+    pub move_history: Vec<u8>,
     pub message_id: Option<u64>,
 }
 
@@ -51,12 +99,83 @@ pub enum OngoingMatch {
 
 struct DatabaseRow {
     match_id: i64,
-    server_id: i64,
+    channel_id: i64,
     red_player_id: i64,
     blue_player_id: i64,
     red_pieces: i64,
     blue_pieces: i64,
+    move_history: String,
     message_id: Option<i64>,
+    time_control: i64,
+    red_time_left: i64,
+    blue_time_left: i64,
+    deadline: Option<i64>,
+    deadline_warned: bool,
+    guild_id: i64,
+    board_width: i64,
+    board_height: i64,
+    red_pieces_high: i64,
+    blue_pieces_high: i64,
+    line_length: i64,
+    variant: i64,
+    blocked: i64,
+    blocked_high: i64,
+    preset: i64,
+    preset_high: i64,
+    green_player_id: Option<i64>,
+    yellow_player_id: Option<i64>,
+    green_pieces: i64,
+    green_pieces_high: i64,
+    yellow_pieces: i64,
+    yellow_pieces_high: i64,
+    green_time_left: i64,
+    yellow_time_left: i64,
+}
+
+// The columns of the matches table read by read_database_row, in order
+const MATCH_COLUMNS: &str = "match_id, channel_id, red_player_id, blue_player_id,
+    red_pieces, blue_pieces, move_history, message_id,
+    time_control, red_time_left, blue_time_left, deadline, deadline_warned, guild_id,
+    board_width, board_height, red_pieces_high, blue_pieces_high, line_length,
+    variant, blocked, blocked_high, preset, preset_high,
+    green_player_id, yellow_player_id, green_pieces, green_pieces_high,
+    yellow_pieces, yellow_pieces_high, green_time_left, yellow_time_left";
+
+fn read_database_row(row: &rusqlite::Row) -> rusqlite::Result<DatabaseRow> {
+    Ok(DatabaseRow {
+        match_id: row.get(0)?,
+        channel_id: row.get(1)?,
+        red_player_id: row.get(2)?,
+        blue_player_id: row.get(3)?,
+        red_pieces: row.get(4)?,
+        blue_pieces: row.get(5)?,
+        move_history: row.get(6)?,
+        message_id: row.get(7)?,
+        time_control: row.get(8)?,
+        red_time_left: row.get(9)?,
+        blue_time_left: row.get(10)?,
+        deadline: row.get(11)?,
+        deadline_warned: row.get(12)?,
+        guild_id: row.get(13)?,
+        board_width: row.get(14)?,
+        board_height: row.get(15)?,
+        red_pieces_high: row.get(16)?,
+        blue_pieces_high: row.get(17)?,
+        line_length: row.get(18)?,
+        variant: row.get(19)?,
+        blocked: row.get(20)?,
+        blocked_high: row.get(21)?,
+        preset: row.get(22)?,
+        preset_high: row.get(23)?,
+        green_player_id: row.get(24)?,
+        yellow_player_id: row.get(25)?,
+        green_pieces: row.get(26)?,
+        green_pieces_high: row.get(27)?,
+        yellow_pieces: row.get(28)?,
+        yellow_pieces_high: row.get(29)?,
+        green_time_left: row.get(30)?,
+        yellow_time_left: row.get(31)?,
+    })
 }
 
 impl From<rusqlite::Error> for Error {
@@ -65,6 +184,108 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
+impl InteractionKind {
+    fn to_db(self) -> i64 {
+        match self {
+            InteractionKind::PlayMove => 0,
+            InteractionKind::AcceptDraw => 1,
+            InteractionKind::ApproveTakeback => 2,
+            InteractionKind::Rematch => 3,
+        }
+    }
+}
+
+impl Termination {
+    fn to_db(self) -> i64 {
+        match self {
+            Termination::Normal => 0,
+            Termination::DrawAgreed => 1,
+            Termination::Resignation => 2,
+            Termination::Timeout => 3,
+            Termination::Abandoned => 4,
+            Termination::Cancelled => 5,
+        }
+    }
+}
+
+fn time_control_to_db(time_control: TimeControl) -> i64 {
+    match time_control {
+        TimeControl::Untimed => 0,
+        TimeControl::Blitz => 1,
+        TimeControl::Rapid => 2,
+        TimeControl::Correspondence => 3,
+    }
+}
+
+fn time_control_from_db(time_control: i64) -> TimeControl {
+    match time_control {
+        1 => TimeControl::Blitz,
+        2 => TimeControl::Rapid,
+        3 => TimeControl::Correspondence,
+        _ => TimeControl::Untimed,
+    }
+}
+
+fn variant_to_db(variant: Variant) -> i64 {
+    match variant {
+        Variant::Standard => 0,
+        Variant::PopOut => 1,
+        Variant::FiveInARow => 2,
+        Variant::Cylinder => 3,
+        Variant::FogOfWar => 4,
+    }
+}
+
+// Seats are numbered from 1 in the order players move, which is also how
+// finished_matches stores the winner
+fn player_to_db(player: Player) -> i64 {
+    match player {
+        Player::Red => 1,
+        Player::Blue => 2,
+        Player::Green => 3,
+        Player::Yellow => 4,
+    }
+}
+
+fn variant_from_db(variant: i64) -> Option<Variant> {
+    match variant {
+        0 => Some(Variant::Standard),
+        1 => Some(Variant::PopOut),
+        2 => Some(Variant::FiveInARow),
+        3 => Some(Variant::Cylinder),
+        4 => Some(Variant::FogOfWar),
+        _ => None,
+    }
+}
+
+impl HumanMatch {
+    // The id of the player in the given seat, if anyone sits there
+    pub fn player_id(&self, player: Player) -> Option<u64> {
+        match player {
+            Player::Red => Some(self.red_player_id),
+            Player::Blue => Some(self.blue_player_id),
+            Player::Green => self.green_player_id,
+            Player::Yellow => self.yellow_player_id,
+        }
+    }
+
+    // The seat of the given player
+    pub fn player_of(&self, player_id: u64) -> Option<Player> {
+        Player::ALL
+            .iter()
+            .copied()
+            .find(|&player| self.player_id(player) == Some(player_id))
+    }
+
+    // The ids of the players, in the order they move
+    pub fn player_ids(&self) -> Vec<u64> {
+        Player::ALL
+            .iter()
+            .filter_map(|&player| self.player_id(player))
+            .collect()
+    }
+}
+
 impl OngoingMatch {
     pub fn get_id(&self) -> u64 {
         match self {
@@ -87,10 +308,24 @@ impl OngoingMatch {
         }
     }
 
-    pub fn get_server_id(&self) -> u64 {
+    pub fn get_guild_id(&self) -> u64 {
+        match self {
+            OngoingMatch::HumanMatch(h) => h.guild_id,
+            OngoingMatch::ComputerMatch(c) => c.guild_id,
+        }
+    }
+
+    pub fn get_channel_id(&self) -> u64 {
+        match self {
+            OngoingMatch::HumanMatch(h) => h.channel_id,
+            OngoingMatch::ComputerMatch(c) => c.channel_id,
+        }
+    }
+
+    pub fn get_move_history(&self) -> &Vec<u8> {
         match self {
-            OngoingMatch::HumanMatch(h) => h.server_id,
-            OngoingMatch::ComputerMatch(c) => c.server_id,
+            OngoingMatch::HumanMatch(h) => &h.move_history,
+            OngoingMatch::ComputerMatch(c) => &c.move_history,
         }
     }
 
@@ -100,13 +335,173 @@ impl OngoingMatch {
             OngoingMatch::ComputerMatch(c) => c.message_id,
         }
     }
+
+    // The player ids as they are stored in the matches table, where the bot
+    // is represented by minus its ai level
+    fn stored_player_ids(&self) -> (i64, i64) {
+        match self {
+            OngoingMatch::HumanMatch(h) => (h.red_player_id as i64, h.blue_player_id as i64),
+            OngoingMatch::ComputerMatch(c) => {
+                if c.player_is_red {
+                    (c.player_id as i64, -(c.ai_level as i64))
+                } else {
+                    (-(c.ai_level as i64), c.player_id as i64)
+                }
+            }
+        }
+    }
 }
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
 
+// Schema changes applied on top of the tables created in `initialize`. The
+// number of migrations already applied is kept in sqlite's user_version, so
+// new entries must only ever be appended to this list.
+const MIGRATIONS: [&str; 13] = [
+    "ALTER TABLE interactions ADD COLUMN kind INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE finished_matches (
+        archive_id INTEGER PRIMARY KEY,
+        match_id INTEGER NOT NULL,
+        server_id INTEGER NOT NULL,
+        red_player_id INTEGER NOT NULL,
+        blue_player_id INTEGER NOT NULL,
+        red_pieces INTEGER NOT NULL,
+        blue_pieces INTEGER NOT NULL,
+        result INTEGER,
+        termination INTEGER NOT NULL
+        );",
+    "ALTER TABLE matches ADD COLUMN move_history TEXT NOT NULL DEFAULT '';
+    ALTER TABLE finished_matches ADD COLUMN move_history TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE matches ADD COLUMN time_control INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN red_time_left INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN blue_time_left INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN deadline INTEGER;
+    ALTER TABLE matches ADD COLUMN deadline_warned INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN time_control INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE matches ADD COLUMN last_move_at INTEGER NOT NULL DEFAULT 0;
+    UPDATE matches SET last_move_at = CAST(strftime('%s', 'now') AS INTEGER);",
+    // server_id always held the channel id. The guild of existing rows isn't
+    // known yet, so it's left as 0 until it's looked up at startup.
+    "ALTER TABLE matches RENAME COLUMN server_id TO channel_id;
+    ALTER TABLE matches ADD COLUMN guild_id INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches RENAME COLUMN server_id TO channel_id;
+    ALTER TABLE finished_matches ADD COLUMN guild_id INTEGER NOT NULL DEFAULT 0;",
+    // Boards bigger than the standard one don't fit in 64 bits, so their
+    // pieces carry on in the _high columns
+    "ALTER TABLE matches ADD COLUMN board_width INTEGER NOT NULL DEFAULT 7;
+    ALTER TABLE matches ADD COLUMN board_height INTEGER NOT NULL DEFAULT 6;
+    ALTER TABLE matches ADD COLUMN red_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN blue_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN board_width INTEGER NOT NULL DEFAULT 7;
+    ALTER TABLE finished_matches ADD COLUMN board_height INTEGER NOT NULL DEFAULT 6;
+    ALTER TABLE finished_matches ADD COLUMN red_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN blue_pieces_high INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE matches ADD COLUMN line_length INTEGER NOT NULL DEFAULT 4;
+    ALTER TABLE finished_matches ADD COLUMN line_length INTEGER NOT NULL DEFAULT 4;",
+    "ALTER TABLE matches ADD COLUMN variant INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN variant INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE matches ADD COLUMN blocked INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN blocked_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN blocked INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN blocked_high INTEGER NOT NULL DEFAULT 0;",
+    // The slots of the pieces a game was set up with
+    "ALTER TABLE matches ADD COLUMN preset INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN preset_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN preset INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN preset_high INTEGER NOT NULL DEFAULT 0;",
+    // Rematch offers point at the archived match in archive_id, and have no
+    // match_id, so that they never get mixed up with an ongoing match
+    "CREATE TABLE interactions_new (
+        interaction_id INTEGER PRIMARY KEY,
+        message_id INTEGER NOT NULL,
+        match_id INTEGER,
+        prompted_player_id INTEGER NOT NULL,
+        kind INTEGER NOT NULL DEFAULT 0,
+        archive_id INTEGER
+        );
+    INSERT INTO interactions_new
+        (interaction_id, message_id, match_id, prompted_player_id, kind, archive_id)
+        SELECT interaction_id, message_id,
+            CASE WHEN kind = 3 THEN NULL ELSE match_id END,
+            prompted_player_id, kind,
+            CASE WHEN kind = 3 THEN match_id END
+        FROM interactions;
+    DROP TABLE interactions;
+    ALTER TABLE interactions_new RENAME TO interactions;",
+    // Match ids are never handed out again, not even those of archived matches,
+    // so that a #id always means the same game
+    "CREATE TABLE matches_new (
+        match_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        channel_id INTEGER NOT NULL,
+        red_player_id INTEGER NOT NULL,
+        blue_player_id INTEGER NOT NULL,
+        red_pieces INTEGER NOT NULL,
+        blue_pieces INTEGER NOT NULL,
+        message_id INTEGER,
+        move_history TEXT NOT NULL DEFAULT '',
+        time_control INTEGER NOT NULL DEFAULT 0,
+        red_time_left INTEGER NOT NULL DEFAULT 0,
+        blue_time_left INTEGER NOT NULL DEFAULT 0,
+        deadline INTEGER,
+        deadline_warned INTEGER NOT NULL DEFAULT 0,
+        last_move_at INTEGER NOT NULL DEFAULT 0,
+        guild_id INTEGER NOT NULL DEFAULT 0,
+        board_width INTEGER NOT NULL DEFAULT 7,
+        board_height INTEGER NOT NULL DEFAULT 6,
+        red_pieces_high INTEGER NOT NULL DEFAULT 0,
+        blue_pieces_high INTEGER NOT NULL DEFAULT 0,
+        line_length INTEGER NOT NULL DEFAULT 4,
+        variant INTEGER NOT NULL DEFAULT 0,
+        blocked INTEGER NOT NULL DEFAULT 0,
+        blocked_high INTEGER NOT NULL DEFAULT 0,
+        preset INTEGER NOT NULL DEFAULT 0,
+        preset_high INTEGER NOT NULL DEFAULT 0
+        );
+    INSERT INTO matches_new
+        (match_id, channel_id, red_player_id, blue_player_id, red_pieces, blue_pieces,
+        message_id, move_history, time_control, red_time_left, blue_time_left, deadline,
+        deadline_warned, last_move_at, guild_id, board_width, board_height,
+        red_pieces_high, blue_pieces_high, line_length, variant, blocked, blocked_high,
+        preset, preset_high)
+        SELECT match_id, channel_id, red_player_id, blue_player_id, red_pieces, blue_pieces,
+        message_id, move_history, time_control, red_time_left, blue_time_left, deadline,
+        deadline_warned, last_move_at, guild_id, board_width, board_height,
+        red_pieces_high, blue_pieces_high, line_length, variant, blocked, blocked_high,
+        preset, preset_high
+        FROM matches;
+    DROP TABLE matches;
+    ALTER TABLE matches_new RENAME TO matches;
+    DELETE FROM sqlite_sequence WHERE name = 'matches';
+    INSERT INTO sqlite_sequence (name, seq)
+        SELECT 'matches', COALESCE(MAX(match_id), 0)
+        FROM (SELECT match_id FROM matches UNION ALL SELECT match_id FROM finished_matches);",
+    // Games of more than two players seat green and yellow after red and blue.
+    // dropped_out has the bit 1 << seat set for each player who left a
+    // finished game before it ended, and offered_by is who made an offer.
+    "ALTER TABLE matches ADD COLUMN green_player_id INTEGER;
+    ALTER TABLE matches ADD COLUMN yellow_player_id INTEGER;
+    ALTER TABLE matches ADD COLUMN green_pieces INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN green_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN yellow_pieces INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN yellow_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN green_time_left INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE matches ADD COLUMN yellow_time_left INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN green_player_id INTEGER;
+    ALTER TABLE finished_matches ADD COLUMN yellow_player_id INTEGER;
+    ALTER TABLE finished_matches ADD COLUMN green_pieces INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN green_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN yellow_pieces INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN yellow_pieces_high INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE finished_matches ADD COLUMN dropped_out INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE interactions ADD COLUMN offered_by INTEGER;",
+];
+
 pub fn initialize(db_name: &str) -> Result<Connection> {
     let conn = Connection::open(db_name)?;
 
+    // the clock scheduler keeps its own connection to the database
+    conn.busy_timeout(Duration::from_secs(5))?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS matches (
             match_id INTEGER PRIMARY KEY,
@@ -130,93 +525,226 @@ pub fn initialize(db_name: &str) -> Result<Connection> {
         params![],
     )?;
 
+    migrate(&conn)?;
+
     Ok(conn)
 }
 
+// Moves are stored as the 1-based column numbers, with a p before pops,
+// e.g. "4453p4"
+fn encode_move_history(move_history: &[u8]) -> String {
+    board::moves_to_string(move_history)
+}
+
+// Bitboards are stored as their lower 64 bits and the bits above those
+fn split_pieces(pieces: u128) -> (i64, i64) {
+    (pieces as u64 as i64, (pieces >> 64) as u64 as i64)
+}
+
+fn join_pieces(low: i64, high: i64) -> u128 {
+    u128::from(low as u64) | u128::from(high as u64) << 64
+}
+
+fn decode_move_history(encoded: &str) -> Vec<u8> {
+    let mut moves = vec![];
+    let mut pop = false;
+    for c in encoded.bytes() {
+        match c {
+            b'p' => pop = true,
+            b'1'..=b'9' if pop => {
+                moves.push(board::pop_move(c - b'1'));
+                pop = false;
+            }
+            b'1'..=b'9' => moves.push(c - b'1'),
+            _ => (),
+        }
+    }
+    moves
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    let applied: i64 = conn.query_row("PRAGMA user_version;", params![], |row| row.get(0))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        conn.execute_batch(&format!(
+            "BEGIN;
+            {}
+            PRAGMA user_version = {};
+            COMMIT;",
+            migration,
+            version + 1
+        ))?;
+    }
+
+    Ok(())
+}
+
 pub fn new_human_match(
     conn: &mut Connection,
-    server_id: u64,
+    guild_id: u64,
+    channel_id: u64,
     red_id: u64,
     blue_id: u64,
+    clock: Clock,
+    start: Board,
+) -> Result<HumanMatch> {
+    insert_human_match(conn, guild_id, channel_id, &[red_id, blue_id], clock, start)
+}
+
+// Starts a game of more than two players, who are listed in the order they move
+pub fn new_group_match(
+    conn: &mut Connection,
+    guild_id: u64,
+    channel_id: u64,
+    player_ids: &[u64],
+    clock: Clock,
+    start: Board,
+) -> Result<HumanMatch> {
+    let mut distinct = player_ids.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if player_ids.len() < 3
+        || distinct.len() != player_ids.len()
+        || player_ids.len() != usize::from(start.rules().players)
+    {
+        return Err(Error::NotCompleted(NotCompletedReason::InvalidPlayerCount));
+    }
+
+    insert_human_match(conn, guild_id, channel_id, player_ids, clock, start)
+}
+
+// Whether the players already have a game in the channel, with one another and
+// no one else
+fn already_playing_together(
+    conn: &Connection,
+    channel_id: u64,
+    player_ids: &[u64],
+) -> Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT red_player_id, blue_player_id, green_player_id, yellow_player_id
+            FROM matches
+            WHERE channel_id=?1
+            ;",
+    )?;
+    let rows = stmt.query_map(params![channel_id as i64], |row| {
+        Ok([
+            row.get::<_, Option<i64>>(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+        ])
+    })?;
+
+    let mut players: Vec<i64> = player_ids.iter().map(|&id| id as i64).collect();
+    players.sort_unstable();
+    for row in rows {
+        let mut seated: Vec<i64> = row?.iter().flatten().copied().collect();
+        seated.sort_unstable();
+        if seated == players {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn insert_human_match(
+    conn: &mut Connection,
+    guild_id: u64,
+    channel_id: u64,
+    player_ids: &[u64],
+    clock: Clock,
+    start: Board,
 ) -> Result<HumanMatch> {
     let tx = conn.transaction()?;
 
-    let e = Board::empty_board();
-    let (red_pieces, blue_pieces) = e.serialize();
+    let rules = start.rules();
+    let (red_pieces, blue_pieces) = start.serialize();
+    let (green_pieces, yellow_pieces) = start.serialize_group();
+    let ((red_low, red_high), (blue_low, blue_high)) =
+        (split_pieces(red_pieces), split_pieces(blue_pieces));
+    let ((green_low, green_high), (yellow_low, yellow_high)) =
+        (split_pieces(green_pieces), split_pieces(yellow_pieces));
+    let (blocked_low, blocked_high) = split_pieces(start.blocked());
+    let (preset_low, preset_high) = split_pieces(start.preset());
+    let (green_id, yellow_id) = (player_ids.get(2).copied(), player_ids.get(3).copied());
+
+    if already_playing_together(&tx, channel_id, player_ids)? {
+        return Err(Error::NotCompleted(
+            NotCompletedReason::AlreadyPlayingEachOther,
+        ));
+    }
 
     tx.execute(
         "INSERT INTO matches
-            (server_id, red_player_id, blue_player_id, red_pieces, blue_pieces)
+            (channel_id, red_player_id, blue_player_id, red_pieces, blue_pieces,
+            time_control, red_time_left, blue_time_left, deadline, guild_id, last_move_at,
+            board_width, board_height, red_pieces_high, blue_pieces_high, line_length, variant,
+            blocked, blocked_high, preset, preset_high,
+            green_player_id, yellow_player_id, green_pieces, green_pieces_high,
+            yellow_pieces, yellow_pieces_high, green_time_left, yellow_time_left)
             VALUES
-            (?1, ?2, ?3, ?4, ?5)
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, CAST(strftime('%s', 'now') AS INTEGER),
+            ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20,
+            ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28)
             ;",
         params![
-            server_id as i64,
-            red_id as i64,
-            blue_id as i64,
-            red_pieces as i64,
-            blue_pieces as i64
+            channel_id as i64,
+            player_ids[0] as i64,
+            player_ids[1] as i64,
+            red_low,
+            blue_low,
+            time_control_to_db(clock.time_control),
+            clock.red_time_left,
+            clock.blue_time_left,
+            clock.deadline,
+            guild_id as i64,
+            rules.size.width,
+            rules.size.height,
+            red_high,
+            blue_high,
+            rules.line_length,
+            variant_to_db(rules.variant),
+            blocked_low,
+            blocked_high,
+            preset_low,
+            preset_high,
+            green_id.map(|id| id as i64),
+            yellow_id.map(|id| id as i64),
+            green_low,
+            green_high,
+            yellow_low,
+            yellow_high,
+            clock.green_time_left,
+            clock.yellow_time_left
         ],
     )?;
 
-    let mut stmt1 = tx.prepare(
-        "SELECT match_id 
-            FROM matches 
-            WHERE server_id=?1
-            AND (red_player_id=?2 OR blue_player_id=?2)
-            ;",
-    )?;
-
-    let mut red_found =
-        stmt1.query_map(params![server_id as i64, red_id as i64], |row| row.get(0))?;
+    let match_id = tx.last_insert_rowid();
 
-    let match_id: i64 = match red_found.next() {
-        Some(Ok(n)) => match red_found.next() {
-            None => Ok(n),
-            Some(_) => Err(Error::NotCompleted(NotCompletedReason::RedAlreadyPlaying)),
-        },
-        Some(Err(r)) => Err(Error::SqliteError(r)),
-        None => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
-    }?;
-
-    drop(red_found);
-
-    let mut blue_found =
-        stmt1.query_map(params![server_id as i64, blue_id as i64], |row| row.get(0))?;
-
-    let match_id_2: i64 = match blue_found.next() {
-        Some(Ok(n)) => match blue_found.next() {
-            None => Ok(n),
-            Some(_) => Err(Error::NotCompleted(NotCompletedReason::BlueAlreadyPlaying)),
-        },
-        Some(Err(r)) => Err(Error::SqliteError(r)),
-        None => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
-    }?;
-
-    drop(blue_found);
-    drop(stmt1);
-
-    if match_id == match_id_2 {
-        tx.commit()?;
-        Ok(HumanMatch {
-            match_id: match_id as u64,
-            server_id,
-            red_player_id: red_id,
-            blue_player_id: blue_id,
-            board: e,
-            message_id: None,
-        })
-    } else {
-        Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError))
-    }
+    tx.commit()?;
+    Ok(HumanMatch {
+        match_id: match_id as u64,
+        guild_id,
+        channel_id,
+        red_player_id: player_ids[0],
+        blue_player_id: player_ids[1],
+        green_player_id: green_id,
+        yellow_player_id: yellow_id,
+        board: start,
+        move_history: vec![],
+        message_id: None,
+        clock,
+    })
 }
 
 pub fn new_computer_match(
     conn: &mut Connection,
-    server_id: u64,
+    guild_id: u64,
+    channel_id: u64,
     player_id: u64,
     player_is_red: bool,
     ai_level: u8,
+    start: Board,
 ) -> Result<ComputerMatch> {
     if ai_level > 10 {
         return Err(Error::NotCompleted(NotCompletedReason::InvalidAiLevel));
@@ -224,24 +752,56 @@ pub fn new_computer_match(
 
     let tx = conn.transaction()?;
 
-    let e = Board::empty_board();
-    let (red_pieces, blue_pieces) = e.serialize();
+    let rules = start.rules();
+    let (red_pieces, blue_pieces) = start.serialize();
+    let ((red_low, red_high), (blue_low, blue_high)) =
+        (split_pieces(red_pieces), split_pieces(blue_pieces));
+    let (blocked_low, blocked_high) = split_pieces(start.blocked());
+    let (preset_low, preset_high) = split_pieces(start.preset());
+
+    // a player can have one game against the bot per channel, at any level
+    let already_playing: Option<i64> = tx
+        .query_row(
+            "SELECT match_id
+            FROM matches
+            WHERE channel_id=?1
+            AND ((red_player_id=?2 AND blue_player_id < 0)
+            OR (blue_player_id=?2 AND red_player_id < 0))
+            ;",
+            params![channel_id as i64, player_id as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    if already_playing.is_some() {
+        return Err(Error::NotCompleted(
+            NotCompletedReason::PlayerAlreadyPlaying,
+        ));
+    }
 
     let query = if player_is_red {
         format!(
             "INSERT INTO matches
-                    (server_id, red_player_id, blue_player_id, red_pieces, blue_pieces)
+                    (channel_id, red_player_id, blue_player_id, red_pieces, blue_pieces,
+                    guild_id, last_move_at,
+                    board_width, board_height, red_pieces_high, blue_pieces_high, line_length,
+                    variant, blocked, blocked_high, preset, preset_high)
                     VALUES
-                    (?1, ?2, -{}, ?3, ?4)
+                    (?1, ?2, -{}, ?3, ?4, ?5, CAST(strftime('%s', 'now') AS INTEGER),
+                    ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
                     ;",
             ai_level
         )
     } else {
         format!(
             "INSERT INTO matches
-                    (server_id, red_player_id, blue_player_id, red_pieces, blue_pieces)
+                    (channel_id, red_player_id, blue_player_id, red_pieces, blue_pieces,
+                    guild_id, last_move_at,
+                    board_width, board_height, red_pieces_high, blue_pieces_high, line_length,
+                    variant, blocked, blocked_high, preset, preset_high)
                     VALUES
-                    (?1, -{}, ?2, ?3, ?4)
+                    (?1, -{}, ?2, ?3, ?4, ?5, CAST(strftime('%s', 'now') AS INTEGER),
+                    ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
                     ;",
             ai_level
         )
@@ -250,145 +810,294 @@ pub fn new_computer_match(
     tx.execute(
         &query,
         params![
-            server_id as i64,
+            channel_id as i64,
             player_id as i64,
-            red_pieces as i64,
-            blue_pieces as i64
+            red_low,
+            blue_low,
+            guild_id as i64,
+            rules.size.width,
+            rules.size.height,
+            red_high,
+            blue_high,
+            rules.line_length,
+            variant_to_db(rules.variant),
+            blocked_low,
+            blocked_high,
+            preset_low,
+            preset_high
         ],
     )?;
 
-    let mut stmt1 = tx.prepare(
-        "SELECT match_id 
-            FROM matches 
-            WHERE server_id=?1
-            AND (red_player_id=?2 OR blue_player_id=?2)
-            ;",
-    )?;
-
-    let mut player_found = stmt1.query_map(params![server_id as i64, player_id as i64], |row| {
-        row.get(0)
-    })?;
-
-    let match_id: i64 = match player_found.next() {
-        Some(Ok(n)) => match player_found.next() {
-            None => Ok(n),
-            Some(_) => Err(Error::NotCompleted(
-                NotCompletedReason::PlayerAlreadyPlaying,
-            )),
-        },
-        Some(Err(r)) => Err(Error::SqliteError(r)),
-        None => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
-    }?;
-
-    drop(player_found);
-    drop(stmt1);
+    let match_id = tx.last_insert_rowid();
 
     tx.commit()?;
     Ok(ComputerMatch {
         match_id: match_id as u64,
-        server_id,
+        guild_id,
+        channel_id,
         player_id,
         player_is_red,
         ai_level,
-        board: e,
+        board: start,
+        move_history: vec![],
         message_id: None,
     })
 }
 
-fn data_row_to_match(row: &DatabaseRow) -> OngoingMatch {
-    let board = Board::unserialize((row.red_pieces as u64, row.blue_pieces as u64));
-    if row.red_player_id < 0 && row.red_player_id > -10 {
+// The bot is stored in place of a player id as minus its ai level
+fn is_bot_id(player_id: i64) -> bool {
+    player_id < 0
+}
+
+fn data_row_to_match(row: &DatabaseRow) -> Result<OngoingMatch> {
+    let corrupted = |e| Error::CorruptedMatch(row.match_id as u64, e);
+    let size = match (
+        u8::try_from(row.board_width),
+        u8::try_from(row.board_height),
+    ) {
+        (Ok(width), Ok(height)) => BoardSize::new(width, height),
+        _ => None,
+    }
+    .ok_or(corrupted(PositionError::InvalidSize))?;
+    let rules = u8::try_from(row.line_length)
+        .ok()
+        .and_then(|line_length| Rules::new(size, line_length))
+        .ok_or(corrupted(PositionError::InvalidLineLength))?;
+    let rules = Rules {
+        variant: variant_from_db(row.variant).ok_or(corrupted(PositionError::UnknownVariant))?,
+        ..rules
+    };
+    let rules = match (row.green_player_id, row.yellow_player_id) {
+        (None, None) => Some(2),
+        (Some(_), None) => Some(3),
+        (Some(_), Some(_)) => Some(4),
+        (None, Some(_)) => None,
+    }
+    .filter(|&players| {
+        // the bot only plays two player games
+        players == 2 || !is_bot_id(row.red_player_id) && !is_bot_id(row.blue_player_id)
+    })
+    .and_then(|players| rules.with_players(players))
+    .ok_or(corrupted(PositionError::InvalidPlayerCount))?;
+    let pieces = (
+        join_pieces(row.red_pieces, row.red_pieces_high),
+        join_pieces(row.blue_pieces, row.blue_pieces_high),
+    );
+    let group = (
+        join_pieces(row.green_pieces, row.green_pieces_high),
+        join_pieces(row.yellow_pieces, row.yellow_pieces_high),
+    );
+    let blocked = join_pieces(row.blocked, row.blocked_high);
+    let preset = join_pieces(row.preset, row.preset_high);
+    let board = if rules.players > 2 {
+        if preset != 0 {
+            return Err(corrupted(PositionError::StrayBits));
+        }
+        Board::checked_unserialize_group(rules, blocked, pieces, group)
+    } else if group != (0, 0) {
+        Err(PositionError::StrayBits)
+    } else {
+        Board::checked_unserialize_with_setup(rules, blocked, preset, pieces)
+    }
+    .map_err(corrupted)?;
+    let ongoing_match = if is_bot_id(row.red_player_id) {
         OngoingMatch::ComputerMatch(ComputerMatch {
             match_id: row.match_id as u64,
-            server_id: row.server_id as u64,
+            guild_id: row.guild_id as u64,
+            channel_id: row.channel_id as u64,
             player_id: row.blue_player_id as u64,
             player_is_red: false,
             ai_level: (-row.red_player_id) as u8,
             board,
+            move_history: decode_move_history(&row.move_history),
             message_id: row.message_id.map(|x| x as u64),
         })
-    } else if row.blue_player_id < 0 && row.blue_player_id > -10 {
+    } else if is_bot_id(row.blue_player_id) {
         OngoingMatch::ComputerMatch(ComputerMatch {
             match_id: row.match_id as u64,
-            server_id: row.server_id as u64,
+            guild_id: row.guild_id as u64,
+            channel_id: row.channel_id as u64,
             player_id: row.red_player_id as u64,
             player_is_red: true,
             ai_level: (-row.blue_player_id) as u8,
             board,
+            move_history: decode_move_history(&row.move_history),
             message_id: row.message_id.map(|x| x as u64),
         })
     } else {
         OngoingMatch::HumanMatch(HumanMatch {
             match_id: row.match_id as u64,
-            server_id: row.server_id as u64,
+            guild_id: row.guild_id as u64,
+            channel_id: row.channel_id as u64,
             red_player_id: row.red_player_id as u64,
             blue_player_id: row.blue_player_id as u64,
+            green_player_id: row.green_player_id.map(|x| x as u64),
+            yellow_player_id: row.yellow_player_id.map(|x| x as u64),
             board,
+            move_history: decode_move_history(&row.move_history),
             message_id: row.message_id.map(|x| x as u64),
+            clock: Clock {
+                time_control: time_control_from_db(row.time_control),
+                red_time_left: row.red_time_left,
+                blue_time_left: row.blue_time_left,
+                green_time_left: row.green_time_left,
+                yellow_time_left: row.yellow_time_left,
+                deadline: row.deadline,
+                warned: row.deadline_warned,
+            },
         })
+    };
+    Ok(ongoing_match)
+}
+
+// The rows of the player's matches in the channel, oldest first. They are only
+// turned into matches once picked, so that a corrupted one doesn't hide the
+// others.
+fn retrieve_rows_by_player(
+    conn: &Connection,
+    channel_id: u64,
+    player_id: u64,
+) -> Result<Vec<DatabaseRow>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+            FROM matches
+            WHERE channel_id=?1
+            AND ?2 IN (red_player_id, blue_player_id, green_player_id, yellow_player_id)
+            ORDER BY match_id
+            ;",
+        MATCH_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(
+        params![channel_id as i64, player_id as i64],
+        read_database_row,
+    )?;
+
+    let mut database_rows = vec![];
+    for row in rows {
+        database_rows.push(row?);
     }
+    Ok(database_rows)
 }
 
+// The player's matches in the channel, leaving out the corrupted ones
+pub fn retrieve_matches_by_player(
+    conn: &Connection,
+    channel_id: u64,
+    player_id: u64,
+) -> Result<Vec<OngoingMatch>> {
+    let rows = retrieve_rows_by_player(conn, channel_id, player_id)?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| data_row_to_match(row).ok())
+        .collect())
+}
+
+// The ids of all of the player's matches in the channel, corrupted or not
+pub fn retrieve_match_ids_by_player(
+    conn: &Connection,
+    channel_id: u64,
+    player_id: u64,
+) -> Result<Vec<u64>> {
+    let rows = retrieve_rows_by_player(conn, channel_id, player_id)?;
+    Ok(rows.iter().map(|row| row.match_id as u64).collect())
+}
+
+// The match with the given id if the player takes part in it, or else the
+// player's only match in the channel. Only that match has to be readable.
 pub fn retrieve_match_by_player(
     conn: &Connection,
-    server_id: u64,
+    channel_id: u64,
     player_id: u64,
+    match_id: Option<u64>,
 ) -> Result<OngoingMatch> {
-    let corresponding_row_opt = conn
-        .query_row(
-            "SELECT match_id, red_player_id, blue_player_id, red_pieces, blue_pieces, message_id
-            FROM matches 
-            WHERE server_id=?1
-            AND (red_player_id=?2 OR blue_player_id=?2)
-            ;",
-            params![server_id as i64, player_id as i64],
-            |row| {
-                Ok(DatabaseRow {
-                    match_id: row.get(0)?,
-                    server_id: server_id as i64,
-                    red_player_id: row.get(1)?,
-                    blue_player_id: row.get(2)?,
-                    red_pieces: row.get(3)?,
-                    blue_pieces: row.get(4)?,
-                    message_id: row.get(5)?,
-                })
-            },
-        )
-        .optional()?;
-    match corresponding_row_opt {
-        None => Err(Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)),
-        Some(corresponding_row) => Ok(data_row_to_match(&corresponding_row)),
+    let mut rows = retrieve_rows_by_player(conn, channel_id, player_id)?;
+
+    if let Some(match_id) = match_id {
+        return match rows.iter().find(|row| row.match_id as u64 == match_id) {
+            None => Err(Error::NotCompleted(NotCompletedReason::NoSuchMatch)),
+            Some(row) => data_row_to_match(row),
+        };
+    }
+
+    match rows.len() {
+        0 => Err(Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)),
+        1 => data_row_to_match(&rows.remove(0)),
+        _ => Err(Error::NotCompleted(NotCompletedReason::SeveralMatches)),
     }
 }
 
 pub fn retrieve_match_by_id(conn: &Connection, match_id: u64) -> Result<OngoingMatch> {
     let corresponding_row_opt = conn
         .query_row(
-            "SELECT match_id, server_id, red_player_id, blue_player_id, red_pieces, blue_pieces, message_id
-            FROM matches 
-            WHERE match_id = ?1
-            ;",
+            &format!(
+                "SELECT {}
+                FROM matches
+                WHERE match_id = ?1
+                ;",
+                MATCH_COLUMNS
+            ),
             params![match_id as i64],
-            |row| {
-                Ok(DatabaseRow {
-                    match_id: row.get(0)?,
-                    server_id: row.get(1)?,
-                    red_player_id: row.get(2)?,
-                    blue_player_id: row.get(3)?,
-                    red_pieces: row.get(4)?,
-                    blue_pieces: row.get(5)?,
-                    message_id: row.get(6)?,
-                })
-            },
+            read_database_row,
         )
         .optional()?;
     match corresponding_row_opt {
         None => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
-        Some(corresponding_row) => Ok(data_row_to_match(&corresponding_row)),
+        Some(corresponding_row) => data_row_to_match(&corresponding_row),
+    }
+}
+
+// Timed matches whose deadline falls before the given time
+pub fn retrieve_matches_due(conn: &Connection, before: i64) -> Result<Vec<OngoingMatch>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+            FROM matches
+            WHERE deadline IS NOT NULL AND deadline <= ?1
+            ;",
+        MATCH_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(params![before], read_database_row)?;
+
+    // corrupted matches can't be timed out, they are left for players to
+    // report when they try to play them
+    let mut due_matches = vec![];
+    for row in rows {
+        if let Ok(due_match) = data_row_to_match(&row?) {
+            due_matches.push(due_match);
+        }
     }
+    Ok(due_matches)
 }
 
-pub fn update_match_board(conn: &Connection, match_id: u64, board: &Board) -> Result<()> {
+// Matches in which no move has been played since the given time
+pub fn retrieve_matches_inactive_since(conn: &Connection, since: i64) -> Result<Vec<OngoingMatch>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+            FROM matches
+            WHERE last_move_at < ?1
+            ;",
+        MATCH_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(params![since], read_database_row)?;
+
+    // as in retrieve_matches_due, corrupted matches are skipped
+    let mut inactive_matches = vec![];
+    for row in rows {
+        if let Ok(inactive_match) = data_row_to_match(&row?) {
+            inactive_matches.push(inactive_match);
+        }
+    }
+    Ok(inactive_matches)
+}
+
+pub fn update_match_board(
+    conn: &Connection,
+    match_id: u64,
+    board: &Board,
+    move_history: &[u8],
+) -> Result<()> {
     conn.execute(
         "DELETE FROM interactions
             WHERE match_id = ?1;",
@@ -396,11 +1105,50 @@ pub fn update_match_board(conn: &Connection, match_id: u64, board: &Board) -> Re
     )?;
 
     let (red_pieces, blue_pieces) = board.serialize();
+    let (green_pieces, yellow_pieces) = board.serialize_group();
+    let ((red_low, red_high), (blue_low, blue_high)) =
+        (split_pieces(red_pieces), split_pieces(blue_pieces));
+    let ((green_low, green_high), (yellow_low, yellow_high)) =
+        (split_pieces(green_pieces), split_pieces(yellow_pieces));
     conn.execute(
         "UPDATE matches
-            SET red_pieces = ?1, blue_pieces = ?2
-            WHERE match_id = ?3",
-        params![red_pieces as i64, blue_pieces as i64, match_id as i64],
+            SET red_pieces = ?1, blue_pieces = ?2, move_history = ?3,
+            last_move_at = CAST(strftime('%s', 'now') AS INTEGER),
+            red_pieces_high = ?5, blue_pieces_high = ?6,
+            green_pieces = ?7, green_pieces_high = ?8,
+            yellow_pieces = ?9, yellow_pieces_high = ?10
+            WHERE match_id = ?4",
+        params![
+            red_low,
+            blue_low,
+            encode_move_history(move_history),
+            match_id as i64,
+            red_high,
+            blue_high,
+            green_low,
+            green_high,
+            yellow_low,
+            yellow_high
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn update_match_clock(conn: &Connection, match_id: u64, clock: &Clock) -> Result<()> {
+    conn.execute(
+        "UPDATE matches
+            SET red_time_left = ?1, blue_time_left = ?2, deadline = ?3, deadline_warned = ?4,
+            green_time_left = ?6, yellow_time_left = ?7
+            WHERE match_id = ?5",
+        params![
+            clock.red_time_left,
+            clock.blue_time_left,
+            clock.deadline,
+            clock.warned,
+            match_id as i64,
+            clock.green_time_left,
+            clock.yellow_time_left
+        ],
     )?;
     Ok(())
 }
@@ -421,16 +1169,119 @@ pub fn delete_match(conn: &Connection, match_id: u64) -> Result<()> {
     Ok(())
 }
 
+// Moves the match from matches to finished_matches. The clock scheduler has a
+// connection of its own, so a match can be ended twice at once, by a timeout and
+// a move. Only the first to delete the row archives it, the other gets
+// NoSuchMatch and nothing changes.
+pub fn archive_match(
+    conn: &Connection,
+    ongoing_match: &OngoingMatch,
+    termination: Termination,
+) -> Result<()> {
+    let (red_player_id, blue_player_id) = ongoing_match.stored_player_ids();
+    let board = ongoing_match.get_board();
+    let (red_pieces, blue_pieces) = board.serialize();
+    let (green_pieces, yellow_pieces) = board.serialize_group();
+    let ((red_low, red_high), (blue_low, blue_high)) =
+        (split_pieces(red_pieces), split_pieces(blue_pieces));
+    let ((green_low, green_high), (yellow_low, yellow_high)) =
+        (split_pieces(green_pieces), split_pieces(yellow_pieces));
+    let (blocked_low, blocked_high) = split_pieces(board.blocked());
+    let (preset_low, preset_high) = split_pieces(board.preset());
+    let result: Option<i64> = match board.game_status() {
+        GameStatus::GameOver(GameResult::Tie) => Some(0),
+        GameStatus::GameOver(GameResult::Winner(player)) => Some(player_to_db(player)),
+        GameStatus::Turn(_) => None,
+    };
+    let dropped_out = board
+        .players()
+        .filter(|&player| !board.is_in_play(player))
+        .fold(0i64, |dropped_out, player| {
+            dropped_out | 1 << player_to_db(player)
+        });
+    let (time_control, green_player_id, yellow_player_id) = match ongoing_match {
+        OngoingMatch::HumanMatch(h) => (
+            h.clock.time_control,
+            h.green_player_id.map(|id| id as i64),
+            h.yellow_player_id.map(|id| id as i64),
+        ),
+        OngoingMatch::ComputerMatch(_) => (TimeControl::Untimed, None, None),
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    let deleted = tx.execute(
+        "DELETE FROM matches
+            WHERE match_id = ?1;",
+        params![ongoing_match.get_id() as i64],
+    )?;
+    if deleted != 1 {
+        return Err(Error::NotCompleted(NotCompletedReason::NoSuchMatch));
+    }
+    tx.execute(
+        "DELETE FROM interactions
+            WHERE match_id = ?1;",
+        params![ongoing_match.get_id() as i64],
+    )?;
+
+    tx.execute(
+        "INSERT INTO finished_matches
+            (match_id, channel_id, red_player_id, blue_player_id,
+            red_pieces, blue_pieces, move_history, result, termination, time_control,
+            guild_id, board_width, board_height, red_pieces_high, blue_pieces_high, line_length,
+            variant, blocked, blocked_high, preset, preset_high,
+            green_player_id, yellow_player_id, green_pieces, green_pieces_high,
+            yellow_pieces, yellow_pieces_high, dropped_out)
+            VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+            ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28)
+            ;",
+        params![
+            ongoing_match.get_id() as i64,
+            ongoing_match.get_channel_id() as i64,
+            red_player_id,
+            blue_player_id,
+            red_low,
+            blue_low,
+            encode_move_history(ongoing_match.get_move_history()),
+            result,
+            termination.to_db(),
+            time_control_to_db(time_control),
+            ongoing_match.get_guild_id() as i64,
+            board.size().width,
+            board.size().height,
+            red_high,
+            blue_high,
+            board.line_length(),
+            variant_to_db(board.rules().variant),
+            blocked_low,
+            blocked_high,
+            preset_low,
+            preset_high,
+            green_player_id,
+            yellow_player_id,
+            green_low,
+            green_high,
+            yellow_low,
+            yellow_high,
+            dropped_out
+        ],
+    )?;
+
+    tx.commit()?;
+    Ok(())
+}
+
 pub fn search_interaction(
     conn: &Connection,
     message_id: u64,
     player_id: u64,
+    kind: InteractionKind,
 ) -> Result<OngoingMatch> {
     let found_match_opt: Option<i64> = conn
         .query_row(
             "SELECT match_id FROM interactions
-            WHERE message_id = ?1 AND prompted_player_id = ?2;",
-            params![message_id as i64, player_id as i64],
+            WHERE message_id = ?1 AND prompted_player_id = ?2 AND kind = ?3;",
+            params![message_id as i64, player_id as i64, kind.to_db()],
             |row| row.get(0),
         )
         .optional()?;
@@ -448,19 +1299,18 @@ pub fn register_interaction(
 ) -> Result<()> {
     conn.execute(
         "DELETE FROM interactions
-            WHERE match_id = ?1;",
-        params![ongoing_match.get_id() as i64],
+            WHERE match_id = ?1 AND kind = ?2;",
+        params![
+            ongoing_match.get_id() as i64,
+            InteractionKind::PlayMove.to_db()
+        ],
     )?;
 
     let player_id = match ongoing_match {
         OngoingMatch::HumanMatch(h) => match h.board.game_status() {
-            GameStatus::Turn(t) => {
-                if t == Player::Red {
-                    Ok(h.red_player_id)
-                } else {
-                    Ok(h.blue_player_id)
-                }
-            }
+            GameStatus::Turn(t) => h
+                .player_id(t)
+                .ok_or(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
             GameStatus::GameOver(_) => Err(Error::NotCompleted(
                 NotCompletedReason::InteractionRequestedForGameOver,
             )),
@@ -474,12 +1324,13 @@ pub fn register_interaction(
     }?;
 
     conn.execute(
-        "INSERT INTO interactions (message_id, match_id, prompted_player_id)
-            VALUES (?1, ?2, ?3);",
+        "INSERT INTO interactions (message_id, match_id, prompted_player_id, kind)
+            VALUES (?1, ?2, ?3, ?4);",
         params![
             message_id as i64,
             ongoing_match.get_id() as i64,
-            player_id as i64
+            player_id as i64,
+            InteractionKind::PlayMove.to_db()
         ],
     )?;
 
@@ -493,5 +1344,277 @@ pub fn register_interaction(
     Ok(())
 }
 
+// Registers an offer (of a draw, a takeback, ...) that each of the offered
+// players can accept by reacting to the given message. Earlier offers of the
+// same kind are withdrawn.
+pub fn register_offer(
+    conn: &Connection,
+    message_id: u64,
+    match_id: u64,
+    offered_by: u64,
+    offered_player_ids: &[u64],
+    kind: InteractionKind,
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM interactions
+            WHERE match_id = ?1 AND kind = ?2;",
+        params![match_id as i64, kind.to_db()],
+    )?;
+
+    for &offered_player_id in offered_player_ids.iter() {
+        conn.execute(
+            "INSERT INTO interactions
+                (message_id, match_id, prompted_player_id, kind, offered_by)
+                VALUES (?1, ?2, ?3, ?4, ?5);",
+            params![
+                message_id as i64,
+                match_id as i64,
+                offered_player_id as i64,
+                kind.to_db(),
+                offered_by as i64
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+// Accepts the offer made to the player, and returns who made it
+pub fn accept_offer(
+    conn: &Connection,
+    match_id: u64,
+    player_id: u64,
+    kind: InteractionKind,
+) -> Result<u64> {
+    let offered_by: Option<Option<i64>> = conn
+        .query_row(
+            "SELECT offered_by FROM interactions
+            WHERE match_id = ?1 AND prompted_player_id = ?2 AND kind = ?3;",
+            params![match_id as i64, player_id as i64, kind.to_db()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match offered_by {
+        None => Err(Error::NotCompleted(NotCompletedReason::NoSuchInteraction)),
+        Some(None) => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
+        Some(Some(offered_by)) => {
+            conn.execute(
+                "DELETE FROM interactions
+                    WHERE match_id = ?1 AND prompted_player_id = ?2 AND kind = ?3;",
+                params![match_id as i64, player_id as i64, kind.to_db()],
+            )?;
+            Ok(offered_by as u64)
+        }
+    }
+}
+
+// The players who still have to accept the offer of the given kind
+pub fn pending_offer(conn: &Connection, match_id: u64, kind: InteractionKind) -> Result<Vec<u64>> {
+    let mut stmt = conn.prepare(
+        "SELECT prompted_player_id FROM interactions
+            WHERE match_id = ?1 AND kind = ?2
+            ORDER BY interaction_id;",
+    )?;
+    let rows = stmt.query_map(params![match_id as i64, kind.to_db()], |row| {
+        row.get::<_, i64>(0)
+    })?;
+
+    let mut player_ids = vec![];
+    for row in rows {
+        player_ids.push(row? as u64);
+    }
+    Ok(player_ids)
+}
+
+pub fn has_pending_interaction(
+    conn: &Connection,
+    match_id: u64,
+    player_id: u64,
+    kind: InteractionKind,
+) -> Result<bool> {
+    let found: Option<i64> = conn
+        .query_row(
+            "SELECT interaction_id FROM interactions
+            WHERE match_id = ?1 AND prompted_player_id = ?2 AND kind = ?3;",
+            params![match_id as i64, player_id as i64, kind.to_db()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(found.is_some())
+}
+
+pub fn retrieve_archived_match(conn: &Connection, archive_id: u64) -> Result<OngoingMatch> {
+    let corresponding_row_opt = conn
+        .query_row(
+            "SELECT match_id, channel_id, red_player_id, blue_player_id,
+            red_pieces, blue_pieces, move_history, NULL,
+            time_control, 0, 0, NULL, 0, guild_id,
+            board_width, board_height, red_pieces_high, blue_pieces_high, line_length,
+            variant, blocked, blocked_high, preset, preset_high,
+            green_player_id, yellow_player_id, green_pieces, green_pieces_high,
+            yellow_pieces, yellow_pieces_high, 0, 0
+            FROM finished_matches
+            WHERE archive_id = ?1
+            ;",
+            params![archive_id as i64],
+            read_database_row,
+        )
+        .optional()?;
+    match corresponding_row_opt {
+        None => Err(Error::NotCompleted(NotCompletedReason::UnrecoverableError)),
+        Some(corresponding_row) => data_row_to_match(&corresponding_row),
+    }
+}
+
+// Lets the human players of the archived match with the given id ask for a
+// rematch by reacting to the given message
+pub fn register_rematch_offer(conn: &Connection, message_id: u64, match_id: u64) -> Result<()> {
+    let (archive_id, player_ids): (i64, [Option<i64>; 4]) = conn.query_row(
+        "SELECT archive_id, red_player_id, blue_player_id, green_player_id, yellow_player_id
+            FROM finished_matches
+            WHERE match_id = ?1;",
+        params![match_id as i64],
+        |row| {
+            Ok((
+                row.get(0)?,
+                [row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?],
+            ))
+        },
+    )?;
+
+    for player_id in player_ids.iter().flatten() {
+        if !is_bot_id(*player_id) {
+            conn.execute(
+                "INSERT INTO interactions (message_id, archive_id, prompted_player_id, kind)
+                    VALUES (?1, ?2, ?3, ?4);",
+                params![
+                    message_id as i64,
+                    archive_id,
+                    player_id,
+                    InteractionKind::Rematch.to_db()
+                ],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the finished match a rematch was asked for, and withdraws the offer so
+// that only one rematch is started from it
+pub fn take_rematch_offer(
+    conn: &Connection,
+    message_id: u64,
+    player_id: u64,
+) -> Result<OngoingMatch> {
+    let found_archive_opt: Option<i64> = conn
+        .query_row(
+            "SELECT archive_id FROM interactions
+            WHERE message_id = ?1 AND prompted_player_id = ?2 AND kind = ?3;",
+            params![
+                message_id as i64,
+                player_id as i64,
+                InteractionKind::Rematch.to_db()
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match found_archive_opt {
+        None => Err(Error::NotCompleted(NotCompletedReason::NoSuchInteraction)),
+        Some(archive_id) => {
+            // the offer stays if the game can't be loaded
+            let archived_match = retrieve_archived_match(conn, archive_id as u64)?;
+            conn.execute(
+                "DELETE FROM interactions
+                    WHERE archive_id = ?1;",
+                params![archive_id],
+            )?;
+            Ok(archived_match)
+        }
+    }
+}
+
+// The channels of the matches that were stored before guild ids were
+pub fn channels_without_guild(conn: &Connection) -> Result<Vec<u64>> {
+    let mut stmt = conn.prepare(
+        "SELECT channel_id FROM matches WHERE guild_id = 0
+            UNION
+            SELECT channel_id FROM finished_matches WHERE guild_id = 0;",
+    )?;
+    let rows = stmt.query_map(params![], |row| row.get::<_, i64>(0))?;
+
+    let mut channel_ids = vec![];
+    for row in rows {
+        channel_ids.push(row? as u64);
+    }
+    Ok(channel_ids)
+}
+
+// Fills in the guild of the matches in a channel that were stored before guild
+// ids were
+pub fn assign_guild(conn: &Connection, channel_id: u64, guild_id: u64) -> Result<()> {
+    conn.execute(
+        "UPDATE matches
+            SET guild_id = ?1
+            WHERE channel_id = ?2 AND guild_id = 0",
+        params![guild_id as i64, channel_id as i64],
+    )?;
+
+    conn.execute(
+        "UPDATE finished_matches
+            SET guild_id = ?1
+            WHERE channel_id = ?2 AND guild_id = 0",
+        params![guild_id as i64, channel_id as i64],
+    )?;
+
+    Ok(())
+}
+
+// Games against the bot, stored with a negative id for it, are left out. Their
+// moves can be taken back at will. A player who dropped out of a group game
+// lost it, even if the others then tied.
+pub fn player_record(conn: &Connection, guild_id: u64, player_id: u64) -> Result<PlayerRecord> {
+    let mut stmt = conn.prepare(
+        "SELECT result, dropped_out,
+            CASE ?2
+                WHEN red_player_id THEN 1
+                WHEN blue_player_id THEN 2
+                WHEN green_player_id THEN 3
+                ELSE 4
+            END
+            FROM finished_matches
+            WHERE guild_id = ?1
+            AND ?2 IN (red_player_id, blue_player_id, green_player_id, yellow_player_id)
+            AND red_player_id >= 0 AND blue_player_id >= 0
+            AND result IS NOT NULL
+            ;",
+    )?;
+
+    let results = stmt.query_map(params![guild_id as i64, player_id as i64], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+        ))
+    })?;
+
+    let mut record = PlayerRecord::default();
+    for result in results {
+        let (result, dropped_out, seat) = result?;
+        if result == seat {
+            record.wins += 1;
+        } else if result == 0 && dropped_out & 1 << seat == 0 {
+            record.ties += 1;
+        } else {
+            record.losses += 1;
+        }
+    }
+
+    Ok(record)
+}
+
 #[cfg(test)]
 mod test;
@@ -1,6 +1,7 @@
 use super::*;
 use crate::connect4::board;
 use crate::connect4::board::{GameResult, Player};
+use crate::connect4::clock::{unix_time, TimeControl};
 use std::fs;
 
 #[test]
@@ -8,50 +9,143 @@ fn game_creation_stops_duplicates() {
     let _ = fs::remove_file("test1.sqlite");
     let mut conn = initialize("test1.sqlite").expect("Failed to create database");
 
-    new_human_match(&mut conn, 1, 12, 21).expect("failed to create a game");
+    new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
 
-    let error_1 = new_human_match(&mut conn, 1, 12, 22)
-        .err()
-        .expect("Creation should have failed");
+    let error_1 = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .err()
+    .expect("Creation should have failed");
     assert_eq!(
         error_1,
-        Error::NotCompleted(NotCompletedReason::RedAlreadyPlaying)
+        Error::NotCompleted(NotCompletedReason::AlreadyPlayingEachOther)
     );
 
-    let error_2 = new_human_match(&mut conn, 1, 22, 12)
-        .err()
-        .expect("Creation should have failed");
+    let error_2 = new_human_match(
+        &mut conn,
+        1,
+        1,
+        21,
+        12,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .err()
+    .expect("Creation should have failed");
     assert_eq!(
         error_2,
-        Error::NotCompleted(NotCompletedReason::BlueAlreadyPlaying)
+        Error::NotCompleted(NotCompletedReason::AlreadyPlayingEachOther)
     );
 
-    let error_3 = new_human_match(&mut conn, 1, 21, 22)
+    new_computer_match(&mut conn, 1, 1, 21, false, 5, Board::empty_board())
+        .expect("failed to create a game");
+
+    let error_3 = new_computer_match(&mut conn, 1, 1, 21, true, 3, Board::empty_board())
         .err()
         .expect("Creation should have failed");
     assert_eq!(
         error_3,
-        Error::NotCompleted(NotCompletedReason::RedAlreadyPlaying)
+        Error::NotCompleted(NotCompletedReason::PlayerAlreadyPlaying)
+    );
+
+    // the same players can still meet in another channel
+    new_human_match(
+        &mut conn,
+        1,
+        2,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    new_computer_match(&mut conn, 1, 2, 21, false, 5, Board::empty_board())
+        .expect("failed to create a game");
+
+    drop(conn);
+    fs::remove_file("test1.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn players_can_have_several_matches() {
+    let _ = fs::remove_file("test11.sqlite");
+    let mut conn = initialize("test11.sqlite").expect("Failed to create database");
+
+    let first = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let second = new_human_match(
+        &mut conn,
+        1,
+        1,
+        22,
+        12,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let third = new_computer_match(&mut conn, 1, 1, 12, true, 5, Board::empty_board())
+        .expect("failed to create a game");
+    assert_ne!(first.match_id, second.match_id);
+
+    let matches = retrieve_matches_by_player(&conn, 1, 12).expect("failed to retrieve the matches");
+    assert_eq!(
+        matches,
+        vec![
+            OngoingMatch::HumanMatch(first.clone()),
+            OngoingMatch::HumanMatch(second.clone()),
+            OngoingMatch::ComputerMatch(third.clone())
+        ]
     );
 
-    let error_4 = new_human_match(&mut conn, 1, 22, 21)
+    let error_1 = retrieve_match_by_player(&conn, 1, 12, None)
         .err()
-        .expect("Creation should have failed");
+        .expect("The match to retrieve should be ambiguous");
     assert_eq!(
-        error_4,
-        Error::NotCompleted(NotCompletedReason::BlueAlreadyPlaying)
+        error_1,
+        Error::NotCompleted(NotCompletedReason::SeveralMatches)
     );
 
-    let error_5 = new_computer_match(&mut conn, 1, 21, false, 5)
+    let found = retrieve_match_by_player(&conn, 1, 12, Some(second.match_id))
+        .expect("failed to select a match");
+    assert_eq!(found, OngoingMatch::HumanMatch(second.clone()));
+
+    let found = retrieve_match_by_player(&conn, 1, 21, None).expect("failed to find the match");
+    assert_eq!(found, OngoingMatch::HumanMatch(first));
+
+    // players can't select matches they don't take part in
+    let error_2 = retrieve_match_by_player(&conn, 1, 21, Some(second.match_id))
         .err()
-        .expect("Creation should have failed");
+        .expect("Selecting somebody else's match should fail");
     assert_eq!(
-        error_5,
-        Error::NotCompleted(NotCompletedReason::PlayerAlreadyPlaying)
+        error_2,
+        Error::NotCompleted(NotCompletedReason::NoSuchMatch)
     );
 
     drop(conn);
-    fs::remove_file("test1.sqlite").expect("failed to remove temp database");
+    fs::remove_file("test11.sqlite").expect("failed to remove temp database");
 }
 
 #[test]
@@ -61,13 +155,23 @@ fn retrieving_empty_game_works() {
 
     let e = Board::empty_board();
 
-    new_human_match(&mut conn, 1, 12, 21).expect("failed to create a game");
-    new_computer_match(&mut conn, 1, 13, true, 5).expect("failed to create a game");
+    new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    new_computer_match(&mut conn, 1, 1, 13, true, 5, Board::empty_board())
+        .expect("failed to create a game");
 
     let found_human_match =
-        retrieve_match_by_player(&conn, 1, 21).expect("Match just created not found");
+        retrieve_match_by_player(&conn, 1, 21, None).expect("Match just created not found");
     let found_computer_match =
-        retrieve_match_by_player(&conn, 1, 13).expect("Match just created not found");
+        retrieve_match_by_player(&conn, 1, 13, None).expect("Match just created not found");
 
     match found_human_match {
         OngoingMatch::ComputerMatch(_) => {
@@ -96,26 +200,31 @@ fn example_match_in_database(
 ) {
     let mut e = Board::empty_board();
     let mut m: OngoingMatch;
-    let mut m_id = 444444u64;
+    let mut m_id;
+    let mut history = vec![];
 
-    new_human_match(conn, 1, p1, p2).expect("Failed to create a game");
+    new_human_match(conn, 1, 1, p1, p2, Clock::untimed(), Board::empty_board())
+        .expect("Failed to create a game");
 
     for k in moves {
-        m = retrieve_match_by_player(conn, 1, p2).expect("Failed to retrieve game");
+        m = retrieve_match_by_player(conn, 1, p2, None).expect("Failed to retrieve game");
         e = match m {
             OngoingMatch::ComputerMatch(_) => {
                 panic!("Found computer match where a human match was inserted")
             }
             OngoingMatch::HumanMatch(h) => {
                 m_id = h.match_id;
+                history = h.move_history;
                 h.board
             }
         };
         assert!(e.is_move_legal(*k));
         e.play_move(*k);
+        history.push(*k);
 
-        update_match_board(conn, m_id, &e);
+        update_match_board(conn, m_id, &e, &history).expect("Failed to update game");
     }
+    assert_eq!(history, moves);
     assert!(e.game_status() == board::GameStatus::GameOver(expected_result));
 }
 
@@ -152,22 +261,31 @@ fn create_interact_delete() {
     let _ = fs::remove_file("test4.sqlite");
     let mut conn = initialize("test4.sqlite").expect("Failed to create database");
 
-    let server_id = 3;
+    let channel_id = 3;
     let red_player_id = 22;
     let blue_player_id = 33;
 
-    let match_id1 = new_human_match(&mut conn, server_id, red_player_id, blue_player_id)
-        .expect("failed to create match");
+    let match_id1 = new_human_match(
+        &mut conn,
+        1,
+        channel_id,
+        red_player_id,
+        blue_player_id,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create match");
 
-    let mut ongoing_match = retrieve_match_by_player(&conn, server_id, blue_player_id)
+    let mut ongoing_match = retrieve_match_by_player(&conn, channel_id, blue_player_id, None)
         .expect("failed to retrieve game");
+    assert_eq!(ongoing_match.get_id(), match_id1.match_id);
 
     let message_id = 12;
 
     register_interaction(&conn, message_id, &ongoing_match)
         .expect("failed to register interaction");
 
-    let error1 = search_interaction(&conn, message_id, blue_player_id)
+    let error1 = search_interaction(&conn, message_id, blue_player_id, InteractionKind::PlayMove)
         .err()
         .expect("No interaction should have been found");
     assert_eq!(
@@ -175,9 +293,10 @@ fn create_interact_delete() {
         Error::NotCompleted(NotCompletedReason::NoSuchInteraction)
     );
 
-    search_interaction(&conn, message_id, red_player_id).expect("Interaction not found");
+    search_interaction(&conn, message_id, red_player_id, InteractionKind::PlayMove)
+        .expect("Interaction not found");
 
-    let mut board = match &mut ongoing_match {
+    let board = match &mut ongoing_match {
         OngoingMatch::ComputerMatch(_) => panic!("Match should be a human match"),
         OngoingMatch::HumanMatch(h) => &mut h.board,
     };
@@ -187,7 +306,7 @@ fn create_interact_delete() {
     register_interaction(&conn, message_id, &ongoing_match)
         .expect("failed to register interaction");
 
-    let error2 = search_interaction(&conn, message_id, red_player_id)
+    let error2 = search_interaction(&conn, message_id, red_player_id, InteractionKind::PlayMove)
         .err()
         .expect("No interaction should have been found");
     assert_eq!(
@@ -195,8 +314,1071 @@ fn create_interact_delete() {
         Error::NotCompleted(NotCompletedReason::NoSuchInteraction)
     );
 
-    search_interaction(&conn, message_id, blue_player_id).expect("Interaction not found");
+    search_interaction(&conn, message_id, blue_player_id, InteractionKind::PlayMove)
+        .expect("Interaction not found");
 
     drop(conn);
     fs::remove_file("test4.sqlite").expect("failed to remove temp database");
 }
+
+#[test]
+fn draw_offer_and_archive() {
+    let _ = fs::remove_file("test5.sqlite");
+    let mut conn = initialize("test5.sqlite").expect("Failed to create database");
+
+    let channel_id = 3;
+    let red_player_id = 22;
+    let blue_player_id = 33;
+
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        channel_id,
+        red_player_id,
+        blue_player_id,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create match");
+    let match_id = human_match.match_id;
+    let message_id = 12;
+
+    register_offer(
+        &conn,
+        message_id,
+        match_id,
+        red_player_id,
+        &[blue_player_id],
+        InteractionKind::AcceptDraw,
+    )
+    .expect("failed to register draw offer");
+
+    assert!(
+        has_pending_interaction(&conn, match_id, blue_player_id, InteractionKind::AcceptDraw)
+            .expect("failed to look up draw offer")
+    );
+    assert!(
+        !has_pending_interaction(&conn, match_id, red_player_id, InteractionKind::AcceptDraw)
+            .expect("failed to look up draw offer")
+    );
+
+    let error1 = search_interaction(&conn, message_id, blue_player_id, InteractionKind::PlayMove)
+        .err()
+        .expect("No move interaction should have been found");
+    assert_eq!(
+        error1,
+        Error::NotCompleted(NotCompletedReason::NoSuchInteraction)
+    );
+
+    let mut ongoing_match = search_interaction(
+        &conn,
+        message_id,
+        blue_player_id,
+        InteractionKind::AcceptDraw,
+    )
+    .expect("Draw offer not found");
+    ongoing_match.get_board_mut().agree_draw();
+
+    archive_match(&conn, &ongoing_match, Termination::DrawAgreed).expect("failed to archive match");
+
+    let error2 = retrieve_match_by_player(&conn, channel_id, red_player_id, None)
+        .err()
+        .expect("Archived match should no longer be ongoing");
+    assert_eq!(
+        error2,
+        Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)
+    );
+
+    let (result, termination): (Option<i64>, i64) = conn
+        .query_row(
+            "SELECT result, termination FROM finished_matches WHERE match_id = ?1;",
+            params![match_id as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .expect("Archived match not found");
+    assert_eq!(result, Some(0));
+    assert_eq!(termination, Termination::DrawAgreed.to_db());
+
+    drop(conn);
+    fs::remove_file("test5.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn move_history_round_trip() {
+    let _ = fs::remove_file("test6.sqlite");
+    let mut conn = initialize("test6.sqlite").expect("Failed to create database");
+
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let mut board = human_match.board;
+    let moves = vec![3, 3, 2, 6, 0];
+    for k in &moves {
+        board.play_move(*k);
+    }
+    update_match_board(&conn, human_match.match_id, &board, &moves)
+        .expect("failed to update match");
+
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("Match not found");
+    assert_eq!(found.get_move_history(), &moves);
+    assert_eq!(found.get_board(), &board);
+
+    assert_eq!(encode_move_history(&moves), "44371");
+    assert_eq!(decode_move_history("44371"), moves);
+
+    drop(conn);
+    fs::remove_file("test6.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn rematch_offers() {
+    let _ = fs::remove_file("test7.sqlite");
+    let mut conn = initialize("test7.sqlite").expect("Failed to create database");
+
+    let mut human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let mut computer_match =
+        new_computer_match(&mut conn, 1, 1, 13, false, 5, Board::empty_board())
+            .expect("failed to create a game");
+    human_match.board.agree_draw();
+    computer_match.board.agree_draw();
+    let human_match = OngoingMatch::HumanMatch(human_match);
+    let computer_match = OngoingMatch::ComputerMatch(computer_match);
+
+    archive_match(&conn, &human_match, Termination::DrawAgreed).expect("failed to archive");
+    archive_match(&conn, &computer_match, Termination::DrawAgreed).expect("failed to archive");
+    register_rematch_offer(&conn, 100, human_match.get_id()).expect("failed to offer rematch");
+    register_rematch_offer(&conn, 101, computer_match.get_id()).expect("failed to offer rematch");
+
+    // the rematch offers are kept apart from matches created afterwards
+    let new_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        14,
+        41,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    assert!(new_match.match_id > computer_match.get_id());
+    delete_match(&conn, new_match.match_id).expect("failed to delete match");
+
+    let error1 = take_rematch_offer(&conn, 100, 13)
+        .err()
+        .expect("Player 13 did not play in that match");
+    assert_eq!(
+        error1,
+        Error::NotCompleted(NotCompletedReason::NoSuchInteraction)
+    );
+
+    let found_human_match = take_rematch_offer(&conn, 100, 21).expect("Rematch offer not found");
+    assert_eq!(found_human_match, human_match);
+    let error2 = take_rematch_offer(&conn, 100, 12)
+        .err()
+        .expect("Rematch offer should have been withdrawn");
+    assert_eq!(
+        error2,
+        Error::NotCompleted(NotCompletedReason::NoSuchInteraction)
+    );
+
+    let found_computer_match = take_rematch_offer(&conn, 101, 13).expect("Rematch offer not found");
+    assert_eq!(found_computer_match, computer_match);
+
+    drop(conn);
+    fs::remove_file("test7.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn resignation_credits_the_opponent() {
+    let _ = fs::remove_file("test8.sqlite");
+    let mut conn = initialize("test8.sqlite").expect("Failed to create database");
+
+    let mut first_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    first_match.board.resign(Player::Red);
+    archive_match(
+        &conn,
+        &OngoingMatch::HumanMatch(first_match),
+        Termination::Resignation,
+    )
+    .expect("failed to archive match");
+
+    let mut second_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    second_match.board.agree_draw();
+    archive_match(
+        &conn,
+        &OngoingMatch::HumanMatch(second_match),
+        Termination::DrawAgreed,
+    )
+    .expect("failed to archive match");
+
+    // games against the bot don't count
+    let mut bot_match = new_computer_match(&mut conn, 1, 5, 12, false, 5, Board::empty_board())
+        .expect("failed to create a game");
+    bot_match.board.resign(Player::Red);
+    archive_match(
+        &conn,
+        &OngoingMatch::ComputerMatch(bot_match),
+        Termination::Resignation,
+    )
+    .expect("failed to archive match");
+
+    assert_eq!(
+        player_record(&conn, 1, 12).expect("failed to compute record"),
+        PlayerRecord {
+            wins: 0,
+            losses: 1,
+            ties: 1
+        }
+    );
+    assert_eq!(
+        player_record(&conn, 1, 21).expect("failed to compute record"),
+        PlayerRecord {
+            wins: 1,
+            losses: 0,
+            ties: 1
+        }
+    );
+    assert_eq!(
+        player_record(&conn, 2, 12).expect("failed to compute record"),
+        PlayerRecord::default()
+    );
+
+    drop(conn);
+    fs::remove_file("test8.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn matches_are_archived_once() {
+    let _ = fs::remove_file("test21.sqlite");
+    let mut conn = initialize("test21.sqlite").expect("Failed to create database");
+    // the clock scheduler's connection
+    let scheduler_conn = initialize("test21.sqlite").expect("Failed to open database");
+
+    let timed_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::start(TimeControl::Blitz, 0),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+
+    // the clock runs out as red makes a winning move
+    let mut timed_out = timed_match.clone();
+    timed_out.board.resign(Player::Red);
+    archive_match(
+        &scheduler_conn,
+        &OngoingMatch::HumanMatch(timed_out),
+        Termination::Timeout,
+    )
+    .expect("failed to archive match");
+
+    let mut won = timed_match;
+    won.board.resign(Player::Blue);
+    assert_eq!(
+        archive_match(&conn, &OngoingMatch::HumanMatch(won), Termination::Normal),
+        Err(Error::NotCompleted(NotCompletedReason::NoSuchMatch))
+    );
+    assert_eq!(
+        player_record(&conn, 1, 21).expect("failed to compute record"),
+        PlayerRecord {
+            wins: 1,
+            losses: 0,
+            ties: 0
+        }
+    );
+
+    drop(scheduler_conn);
+    drop(conn);
+    fs::remove_file("test21.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn clocks_are_stored_and_found_when_due() {
+    let _ = fs::remove_file("test9.sqlite");
+    let mut conn = initialize("test9.sqlite").expect("Failed to create database");
+
+    let untimed_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let blitz_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        13,
+        31,
+        Clock::start(TimeControl::Blitz, 1000),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let mut correspondence_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        14,
+        41,
+        Clock::start(TimeControl::Correspondence, 1000),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+
+    let found = retrieve_match_by_id(&conn, untimed_match.match_id).expect("Match not found");
+    assert_eq!(found, OngoingMatch::HumanMatch(untimed_match));
+
+    let due = retrieve_matches_due(&conn, 2000).expect("failed to look up due matches");
+    assert_eq!(due, vec![OngoingMatch::HumanMatch(blitz_match)]);
+
+    correspondence_match
+        .clock
+        .pass_turn(Player::Red, Player::Blue, 1500);
+    correspondence_match.clock.warned = true;
+    update_match_clock(
+        &conn,
+        correspondence_match.match_id,
+        &correspondence_match.clock,
+    )
+    .expect("failed to update clock");
+    let found =
+        retrieve_match_by_id(&conn, correspondence_match.match_id).expect("Match not found");
+    assert_eq!(found, OngoingMatch::HumanMatch(correspondence_match));
+
+    drop(conn);
+    fs::remove_file("test9.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn inactive_matches_are_found() {
+    let _ = fs::remove_file("test10.sqlite");
+    let mut conn = initialize("test10.sqlite").expect("Failed to create database");
+
+    let start = unix_time();
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let computer_match = new_computer_match(&mut conn, 1, 1, 13, true, 5, Board::empty_board())
+        .expect("failed to create a game");
+
+    let inactive = retrieve_matches_inactive_since(&conn, start - 60)
+        .expect("failed to look up inactive matches");
+    assert_eq!(inactive, vec![]);
+
+    let inactive = retrieve_matches_inactive_since(&conn, unix_time() + 60)
+        .expect("failed to look up inactive matches");
+    assert_eq!(
+        inactive,
+        vec![
+            OngoingMatch::HumanMatch(human_match),
+            OngoingMatch::ComputerMatch(computer_match)
+        ]
+    );
+
+    drop(conn);
+    fs::remove_file("test10.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn guilds_are_assigned_to_old_matches() {
+    let _ = fs::remove_file("test12.sqlite");
+    let mut conn = initialize("test12.sqlite").expect("Failed to create database");
+
+    // matches stored before the guild was known have a guild id of 0
+    let mut old_match = new_human_match(
+        &mut conn,
+        0,
+        3,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    old_match.board.resign(Player::Blue);
+    archive_match(
+        &conn,
+        &OngoingMatch::HumanMatch(old_match),
+        Termination::Resignation,
+    )
+    .expect("failed to archive match");
+    let ongoing_match = new_human_match(
+        &mut conn,
+        0,
+        3,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let other_channel_match = new_human_match(
+        &mut conn,
+        0,
+        4,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+
+    assert_eq!(
+        player_record(&conn, 7, 12).expect("failed to compute record"),
+        PlayerRecord::default()
+    );
+
+    assert_eq!(
+        channels_without_guild(&conn).expect("failed to find channels"),
+        vec![3, 4]
+    );
+    assign_guild(&conn, 3, 7).expect("failed to assign guild");
+    assert_eq!(
+        channels_without_guild(&conn).expect("failed to find channels"),
+        vec![4]
+    );
+
+    let found = retrieve_match_by_id(&conn, ongoing_match.match_id).expect("failed to find match");
+    assert_eq!(found.get_guild_id(), 7);
+    let found =
+        retrieve_match_by_id(&conn, other_channel_match.match_id).expect("failed to find match");
+    assert_eq!(found.get_guild_id(), 0);
+    assert_eq!(
+        player_record(&conn, 7, 12).expect("failed to compute record"),
+        PlayerRecord {
+            wins: 1,
+            losses: 0,
+            ties: 0
+        }
+    );
+
+    drop(conn);
+    fs::remove_file("test12.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn corrupted_boards_are_reported() {
+    let _ = fs::remove_file("test13.sqlite");
+    let mut conn = initialize("test13.sqlite").expect("Failed to create database");
+
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    // both players get a piece in the same slot
+    conn.execute(
+        "UPDATE matches SET red_pieces = red_pieces | 1, blue_pieces = blue_pieces | 1
+        WHERE match_id = ?1;",
+        params![human_match.match_id as i64],
+    )
+    .expect("failed to corrupt the board");
+
+    assert_eq!(
+        retrieve_match_by_id(&conn, human_match.match_id),
+        Err(Error::CorruptedMatch(
+            human_match.match_id,
+            board::PositionError::OverlappingPieces
+        ))
+    );
+    assert_eq!(
+        retrieve_match_by_player(&conn, 1, 21, None),
+        Err(Error::CorruptedMatch(
+            human_match.match_id,
+            board::PositionError::OverlappingPieces
+        ))
+    );
+
+    // sweeps leave it alone
+    assert_eq!(
+        retrieve_matches_inactive_since(&conn, unix_time() + 60),
+        Ok(vec![])
+    );
+
+    // the player's other games can still be reached
+    let healthy_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        13,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    assert_eq!(
+        retrieve_match_by_player(&conn, 1, 21, Some(healthy_match.match_id)),
+        Ok(OngoingMatch::HumanMatch(healthy_match.clone()))
+    );
+    assert_eq!(
+        retrieve_match_by_player(&conn, 1, 21, None),
+        Err(Error::NotCompleted(NotCompletedReason::SeveralMatches))
+    );
+    assert_eq!(
+        retrieve_match_by_player(&conn, 1, 21, Some(human_match.match_id)),
+        Err(Error::CorruptedMatch(
+            human_match.match_id,
+            board::PositionError::OverlappingPieces
+        ))
+    );
+    assert_eq!(
+        retrieve_matches_by_player(&conn, 1, 21),
+        Ok(vec![OngoingMatch::HumanMatch(healthy_match.clone())])
+    );
+    assert_eq!(
+        retrieve_match_ids_by_player(&conn, 1, 21),
+        Ok(vec![human_match.match_id, healthy_match.match_id])
+    );
+
+    drop(conn);
+    fs::remove_file("test13.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn board_sizes_are_stored() {
+    let _ = fs::remove_file("test14.sqlite");
+    let mut conn = initialize("test14.sqlite").expect("Failed to create database");
+
+    let size = BoardSize::new(9, 8).expect("a valid size");
+    let rules = Rules {
+        size,
+        ..Rules::STANDARD
+    };
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board_with_rules(rules),
+    )
+    .expect("failed to create a game");
+    let computer_match = new_computer_match(
+        &mut conn,
+        1,
+        1,
+        13,
+        true,
+        5,
+        Board::empty_board_with_rules(rules),
+    )
+    .expect("failed to create a game");
+    assert_eq!(human_match.board.size(), size);
+    assert_eq!(
+        retrieve_match_by_id(&conn, computer_match.match_id)
+            .expect("failed to retrieve match")
+            .get_board(),
+        &Board::empty_board_of_size(size)
+    );
+
+    // the last column takes the bits past the first 64
+    let moves = vec![8, 8, 8, 8, 8, 8, 8, 8, 0, 1, 0, 1, 0, 1];
+    let mut board = human_match.board.clone();
+    for &column in moves.iter() {
+        board.play_move(column);
+    }
+    update_match_board(&conn, human_match.match_id, &board, &moves)
+        .expect("failed to update match");
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &board);
+
+    // red connects four, and the game over bit is past 64 too
+    let mut finished = found.clone();
+    finished.get_board_mut().play_move(0);
+    archive_match(&conn, &finished, Termination::Normal).expect("failed to archive");
+    let archive_id: i64 = conn
+        .query_row(
+            "SELECT MAX(archive_id) FROM finished_matches;",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .expect("failed to find the archived match");
+    let archived = retrieve_archived_match(&conn, archive_id as u64).expect("failed to retrieve");
+    assert_eq!(archived.get_board(), finished.get_board());
+    assert_eq!(
+        archived.get_board().game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Red))
+    );
+
+    drop(conn);
+    fs::remove_file("test14.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn line_lengths_are_stored() {
+    let _ = fs::remove_file("test15.sqlite");
+    let mut conn = initialize("test15.sqlite").expect("Failed to create database");
+
+    let rules = Rules::new(BoardSize::new(9, 7).expect("a valid size"), 5).expect("valid rules");
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board_with_rules(rules),
+    )
+    .expect("failed to create a game");
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board().rules(), rules);
+
+    // four in a row doesn't end the game, five does
+    let mut finished = found.clone();
+    for &column in [0, 0, 1, 1, 2, 2, 3, 3].iter() {
+        finished.get_board_mut().play_move(column);
+    }
+    assert_eq!(
+        finished.get_board().game_status(),
+        GameStatus::Turn(Player::Red)
+    );
+    finished.get_board_mut().play_move(4);
+    archive_match(&conn, &finished, Termination::Normal).expect("failed to archive");
+    let archive_id: i64 = conn
+        .query_row(
+            "SELECT MAX(archive_id) FROM finished_matches;",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .expect("failed to find the archived match");
+    let archived = retrieve_archived_match(&conn, archive_id as u64).expect("failed to retrieve");
+    assert_eq!(archived.get_board(), finished.get_board());
+    assert_eq!(archived.get_board().winning_line().len(), 5);
+
+    // a line that can't fit on the board
+    let computer_match = new_computer_match(&mut conn, 1, 1, 13, true, 5, Board::empty_board())
+        .expect("failed to create a game");
+    conn.execute(
+        "UPDATE matches SET line_length = 8 WHERE match_id = ?1;",
+        params![computer_match.match_id as i64],
+    )
+    .expect("failed to corrupt the rules");
+    assert_eq!(
+        retrieve_match_by_id(&conn, computer_match.match_id),
+        Err(Error::CorruptedMatch(
+            computer_match.match_id,
+            board::PositionError::InvalidLineLength
+        ))
+    );
+
+    drop(conn);
+    fs::remove_file("test15.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn pops_are_stored() {
+    let _ = fs::remove_file("test16.sqlite");
+    let mut conn = initialize("test16.sqlite").expect("Failed to create database");
+
+    let rules = Rules {
+        variant: Variant::PopOut,
+        ..Rules::STANDARD
+    };
+    let computer_match = new_computer_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        true,
+        5,
+        Board::empty_board_with_rules(rules),
+    )
+    .expect("failed to create a game");
+    let moves = [3, 4, board::pop_move(3)];
+    let mut board = computer_match.board.clone();
+    for &m in moves.iter() {
+        board.play_move(m);
+    }
+    update_match_board(&conn, computer_match.match_id, &board, &moves)
+        .expect("failed to update the game");
+
+    let found = retrieve_match_by_id(&conn, computer_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &board);
+    assert_eq!(found.get_board().rules(), rules);
+    assert_eq!(found.get_move_history(), &moves.to_vec());
+
+    archive_match(&conn, &found, Termination::Resignation).expect("failed to archive");
+    let archive_id: i64 = conn
+        .query_row(
+            "SELECT MAX(archive_id) FROM finished_matches;",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .expect("failed to find the archived match");
+    let archived = retrieve_archived_match(&conn, archive_id as u64).expect("failed to retrieve");
+    assert_eq!(archived.get_board(), &board);
+    assert_eq!(archived.get_move_history(), &moves.to_vec());
+
+    drop(conn);
+    fs::remove_file("test16.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn variants_are_stored() {
+    let _ = fs::remove_file("test17.sqlite");
+    let mut conn = initialize("test17.sqlite").expect("Failed to create database");
+
+    let rules = Variant::FiveInARow.usual_rules();
+    let human_match = new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board_with_rules(rules),
+    )
+    .expect("failed to create a game");
+    assert_eq!(human_match.board, Board::empty_board_with_rules(rules));
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &human_match.board);
+
+    conn.execute(
+        "UPDATE matches SET variant = 9 WHERE match_id = ?1;",
+        params![human_match.match_id as i64],
+    )
+    .expect("failed to corrupt the variant");
+    assert_eq!(
+        retrieve_match_by_id(&conn, human_match.match_id),
+        Err(Error::CorruptedMatch(
+            human_match.match_id,
+            board::PositionError::UnknownVariant
+        ))
+    );
+
+    drop(conn);
+    fs::remove_file("test17.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn blockers_are_stored() {
+    let _ = fs::remove_file("test18.sqlite");
+    let mut conn = initialize("test18.sqlite").expect("Failed to create database");
+
+    // the last column of a big board keeps its blocker in the _high column
+    let rules = Rules {
+        size: BoardSize::new(9, 8).expect("a valid size"),
+        ..Rules::STANDARD
+    };
+    let mut start = Board::empty_board_with_rules(rules);
+    assert!(start.add_blocker(2));
+    assert!(start.add_blocker(8));
+    let human_match = new_human_match(&mut conn, 1, 1, 12, 21, Clock::untimed(), start.clone())
+        .expect("failed to create a game");
+    let computer_match = new_computer_match(&mut conn, 1, 1, 13, false, 5, start.clone())
+        .expect("failed to create a game");
+    assert_eq!(
+        retrieve_match_by_id(&conn, computer_match.match_id)
+            .expect("failed to retrieve")
+            .get_board(),
+        &start
+    );
+
+    let moves = [2, 8, 2];
+    let mut board = start.clone();
+    for &m in moves.iter() {
+        board.play_move(m);
+    }
+    update_match_board(&conn, human_match.match_id, &board, &moves)
+        .expect("failed to update the game");
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &board);
+    assert_eq!(found.get_board().starting_position(), start);
+
+    archive_match(&conn, &found, Termination::Resignation).expect("failed to archive");
+    let archive_id: i64 = conn
+        .query_row(
+            "SELECT MAX(archive_id) FROM finished_matches;",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .expect("failed to find the archived match");
+    let archived = retrieve_archived_match(&conn, archive_id as u64).expect("failed to retrieve");
+    assert_eq!(archived.get_board(), &board);
+
+    // a blocker in the air is a corrupted match
+    conn.execute(
+        "UPDATE matches SET blocked = ?2 WHERE match_id = ?1;",
+        params![computer_match.match_id as i64, 1i64 << 9],
+    )
+    .expect("failed to corrupt the blockers");
+    assert_eq!(
+        retrieve_match_by_id(&conn, computer_match.match_id),
+        Err(Error::CorruptedMatch(
+            computer_match.match_id,
+            board::PositionError::FloatingPiece { column: 1 }
+        ))
+    );
+
+    drop(conn);
+    fs::remove_file("test18.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn group_matches_are_stored() {
+    let _ = fs::remove_file("test19.sqlite");
+    let mut conn = initialize("test19.sqlite").expect("Failed to create database");
+
+    let start = Board::empty_board_with_rules(Rules::usual_for_players(3));
+    let new_game = |conn: &mut Connection, channel_id, player_ids: &[u64]| {
+        new_group_match(
+            conn,
+            1,
+            channel_id,
+            player_ids,
+            Clock::untimed(),
+            start.clone(),
+        )
+    };
+    for player_ids in [&[12, 21][..], &[12, 21, 12], &[12, 21, 31, 41]].iter() {
+        assert_eq!(
+            new_game(&mut conn, 1, player_ids),
+            Err(Error::NotCompleted(NotCompletedReason::InvalidPlayerCount))
+        );
+    }
+    let mut group_match = new_game(&mut conn, 1, &[12, 21, 31]).expect("failed to create a game");
+    // the same players can't have two games together in a channel, whatever
+    // their seats, but some of them can, and all of them elsewhere
+    assert_eq!(
+        new_game(&mut conn, 1, &[31, 12, 21]),
+        Err(Error::NotCompleted(
+            NotCompletedReason::AlreadyPlayingEachOther
+        ))
+    );
+    new_human_match(
+        &mut conn,
+        1,
+        1,
+        12,
+        21,
+        Clock::untimed(),
+        Board::empty_board(),
+    )
+    .expect("failed to create a game");
+    let mut other_match = new_game(&mut conn, 2, &[12, 21, 31]).expect("failed to create a game");
+
+    // blue drops out, and the others play on
+    for &column in [4, 4, 3].iter() {
+        group_match.board.play_move(column);
+        group_match.move_history.push(column);
+    }
+    group_match.board.resign(Player::Blue);
+    group_match.clock.green_time_left = 123;
+    update_match_board(
+        &conn,
+        group_match.match_id,
+        &group_match.board,
+        &group_match.move_history,
+    )
+    .expect("failed to update the game");
+    update_match_clock(&conn, group_match.match_id, &group_match.clock)
+        .expect("failed to update the clock");
+    assert_eq!(
+        retrieve_match_by_player(&conn, 1, 31, None),
+        Ok(OngoingMatch::HumanMatch(group_match.clone()))
+    );
+    assert_eq!(
+        retrieve_match_ids_by_player(&conn, 1, 21),
+        Ok(vec![group_match.match_id, group_match.match_id + 1])
+    );
+
+    // offers wait for every player they are made to
+    register_offer(
+        &conn,
+        5,
+        group_match.match_id,
+        31,
+        &[12, 21],
+        InteractionKind::AcceptDraw,
+    )
+    .expect("failed to register draw offer");
+    assert_eq!(
+        accept_offer(&conn, group_match.match_id, 21, InteractionKind::AcceptDraw),
+        Ok(31)
+    );
+    assert_eq!(
+        pending_offer(&conn, group_match.match_id, InteractionKind::AcceptDraw),
+        Ok(vec![12])
+    );
+    assert_eq!(
+        accept_offer(&conn, group_match.match_id, 21, InteractionKind::AcceptDraw),
+        Err(Error::NotCompleted(NotCompletedReason::NoSuchInteraction))
+    );
+
+    // a player who dropped out loses, even when the others tie
+    group_match.board.agree_draw();
+    archive_match(
+        &conn,
+        &OngoingMatch::HumanMatch(group_match.clone()),
+        Termination::DrawAgreed,
+    )
+    .expect("failed to archive the game");
+    other_match.board.resign(Player::Red);
+    other_match.board.resign(Player::Blue);
+    assert_eq!(
+        other_match.board.game_status(),
+        GameStatus::GameOver(GameResult::Winner(Player::Green))
+    );
+    archive_match(
+        &conn,
+        &OngoingMatch::HumanMatch(other_match),
+        Termination::Resignation,
+    )
+    .expect("failed to archive the game");
+    let record = |player_id| player_record(&conn, 1, player_id).expect("failed to get record");
+    assert_eq!(
+        (record(12), record(21), record(31)),
+        (
+            PlayerRecord {
+                wins: 0,
+                losses: 1,
+                ties: 1
+            },
+            PlayerRecord {
+                wins: 0,
+                losses: 2,
+                ties: 0
+            },
+            PlayerRecord {
+                wins: 1,
+                losses: 0,
+                ties: 1
+            }
+        )
+    );
+
+    // finished games are read back with their players, but not their clocks
+    register_rematch_offer(&conn, 88, group_match.match_id).expect("failed to offer rematch");
+    assert_eq!(
+        take_rematch_offer(&conn, 88, 31),
+        Ok(OngoingMatch::HumanMatch(HumanMatch {
+            clock: Clock::untimed(),
+            ..group_match
+        }))
+    );
+
+    // yellow can't play without green
+    let corrupted_match = new_game(&mut conn, 3, &[12, 21, 31]).expect("failed to create a game");
+    conn.execute(
+        "UPDATE matches SET green_player_id = NULL, yellow_player_id = 41 WHERE match_id = ?1;",
+        params![corrupted_match.match_id as i64],
+    )
+    .expect("failed to corrupt the players");
+    assert_eq!(
+        retrieve_match_by_id(&conn, corrupted_match.match_id),
+        Err(Error::CorruptedMatch(
+            corrupted_match.match_id,
+            board::PositionError::InvalidPlayerCount
+        ))
+    );
+
+    drop(conn);
+    fs::remove_file("test19.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn set_up_games_are_stored() {
+    let _ = fs::remove_file("test20.sqlite");
+    let mut conn = initialize("test20.sqlite").expect("Failed to create database");
+
+    let mut start = Board::empty_board();
+    assert!(start.add_blocker(3));
+    start.add_handicap(Player::Red, 2);
+    let computer_match = new_computer_match(&mut conn, 1, 1, 12, true, 9, start.clone())
+        .expect("failed to create a game");
+    let found = retrieve_match_by_id(&conn, computer_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &start);
+
+    let mut opening = Board::empty_board();
+    opening.play_opening("445").expect("a playable opening");
+    let human_match = new_human_match(&mut conn, 1, 1, 12, 21, Clock::untimed(), opening.clone())
+        .expect("failed to create a game");
+    let mut board = opening.clone();
+    board.play_move(4);
+    update_match_board(&conn, human_match.match_id, &board, &[4])
+        .expect("failed to update the game");
+    let found = retrieve_match_by_id(&conn, human_match.match_id).expect("failed to retrieve");
+    assert_eq!(found.get_board(), &board);
+    assert_eq!(found.get_board().starting_position(), opening);
+
+    archive_match(&conn, &found, Termination::Resignation).expect("failed to archive");
+    let archive_id: i64 = conn
+        .query_row(
+            "SELECT MAX(archive_id) FROM finished_matches;",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .expect("failed to find the archived match");
+    let archived = retrieve_archived_match(&conn, archive_id as u64).expect("failed to retrieve");
+    assert_eq!(archived.get_board(), &board);
+
+    // without the slots they were set up in, the pieces can't come from a game
+    conn.execute(
+        "UPDATE matches SET preset = 0 WHERE match_id = ?1;",
+        params![computer_match.match_id as i64],
+    )
+    .expect("failed to corrupt the set up");
+    assert_eq!(
+        retrieve_match_by_id(&conn, computer_match.match_id),
+        Err(Error::CorruptedMatch(
+            computer_match.match_id,
+            board::PositionError::ImpossiblePieceCount { red: 2, blue: 0 }
+        ))
+    );
+
+    drop(conn);
+    fs::remove_file("test20.sqlite").expect("failed to remove temp database");
+}
@@ -0,0 +1,117 @@
+use super::board;
+use super::board::{Board, GameResult, GameStatus, Rules, Variant};
+use super::engine::{Engine, EngineError};
+
+// Score of a win found with no moves left to search. Wins found earlier in the
+// search score higher, so that the quickest win is preferred.
+const WIN: i32 = 1000;
+
+// Looks ahead the given number of moves (counting both players) with an
+// alpha-beta search, and plays the move with the best forced outcome. Positions
+// beyond the horizon count as ties, so a shallow search only sees tactics.
+pub fn solver_move(b: &Board, depth: u8) -> Result<u8, EngineError> {
+    search(b, depth).map(|(column, _)| column)
+}
+
+// Expected score of the player to move: 1 or 0 if the search finds a forced
+// result, and 1/2 if it can't see that far
+pub fn solver_evaluate(b: &Board, depth: u8) -> Result<f64, EngineError> {
+    search(b, depth).map(|(_, score)| match score {
+        score if score > 0 => 1.0,
+        score if score < 0 => 0.0,
+        _ => 0.5,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Solver {
+    pub depth: u8,
+}
+
+impl Engine for Solver {
+    fn best_move(&self, board: &Board, _moves: &[u8]) -> Result<u8, EngineError> {
+        solver_move(board, self.depth)
+    }
+
+    fn evaluate(&self, board: &Board, _moves: &[u8]) -> Result<f64, EngineError> {
+        solver_evaluate(board, self.depth)
+    }
+}
+
+// Moves in the order they are searched, from the middle out. Central columns
+// take part in more lines, so trying them first makes the pruning more
+// effective. On the standard board this is 3, 2, 4, 1, 5, 0, 6. Pops, if the
+// game has them, come after the drops in the same order.
+fn move_order(rules: Rules) -> Vec<u8> {
+    let width = rules.size.width;
+    let mut columns: Vec<u8> = (0..width).collect();
+    columns.sort_by_key(|&c| ((2 * i32::from(c) - i32::from(width) + 1).abs(), c));
+    if rules.variant == Variant::PopOut {
+        let pops: Vec<u8> = columns.iter().map(|&c| board::pop_move(c)).collect();
+        columns.extend(pops);
+    }
+    columns
+}
+
+// The best move and its score
+fn search(b: &Board, depth: u8) -> Result<(u8, i32), EngineError> {
+    if let GameStatus::GameOver(_) = b.game_status() {
+        return Err(EngineError::GameOver);
+    }
+
+    let move_order = move_order(b.rules());
+    let mut best_move = None;
+    let mut alpha = -WIN - i32::from(depth) - 1;
+    for &column in move_order.iter().filter(|&&c| b.is_move_legal(c)) {
+        let score = score_move(
+            b,
+            &move_order,
+            column,
+            depth.max(1),
+            alpha,
+            WIN + i32::from(depth) + 1,
+        );
+        if best_move.is_none() || score > alpha {
+            alpha = score;
+            best_move = Some(column);
+        }
+    }
+
+    best_move
+        .map(|column| (column, alpha))
+        .ok_or(EngineError::NoAnswer)
+}
+
+// Score, for the player about to move, of playing the given move. A pop can
+// also make a line for the opponent.
+fn score_move(b: &Board, move_order: &[u8], column: u8, depth: u8, alpha: i32, beta: i32) -> i32 {
+    let mut child = b.clone();
+    child.play_move(column);
+    match child.game_status() {
+        GameStatus::GameOver(GameResult::Winner(winner))
+            if b.game_status() == GameStatus::Turn(winner) =>
+        {
+            WIN + i32::from(depth)
+        }
+        GameStatus::GameOver(GameResult::Winner(_)) => -WIN - i32::from(depth),
+        GameStatus::GameOver(GameResult::Tie) => 0,
+        GameStatus::Turn(_) if depth <= 1 => 0,
+        GameStatus::Turn(_) => -negamax(&child, move_order, depth - 1, -beta, -alpha),
+    }
+}
+
+fn negamax(b: &Board, move_order: &[u8], depth: u8, mut alpha: i32, beta: i32) -> i32 {
+    let mut best = i32::MIN;
+    for &column in move_order.iter().filter(|&&c| b.is_move_legal(c)) {
+        let score = score_move(b, move_order, column, depth, alpha, beta);
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,63 @@
+use super::*;
+
+fn board_after(moves: &[u8]) -> Board {
+    let mut b = Board::empty_board();
+    for &column in moves {
+        b.play_move(column);
+    }
+    b
+}
+
+#[test]
+fn takes_an_immediate_win() {
+    // red has three in the first column
+    let b = board_after(&[0, 1, 0, 1, 0, 6]);
+    assert_eq!(solver_move(&b, 1), Ok(0));
+    assert_eq!(solver_move(&b, 6), Ok(0));
+}
+
+#[test]
+fn blocks_an_immediate_loss() {
+    // blue has three in the second column and red must block it
+    let b = board_after(&[0, 1, 6, 1, 6, 1]);
+    assert_eq!(solver_move(&b, 2), Ok(1));
+}
+
+#[test]
+fn sets_up_a_double_threat() {
+    // red has two in a row on 2 and 3, with both ends open. Playing on 1 or 4
+    // makes an open three that can't be stopped.
+    let b = board_after(&[2, 2, 3, 3]);
+    let column = solver_move(&b, 5).expect("the game isn't over");
+    assert!(column == 1 || column == 4);
+}
+
+#[test]
+fn finished_games_have_no_moves() {
+    let b = board_after(&[0, 1, 0, 1, 0, 1, 0]);
+    assert_eq!(solver_move(&b, 4), Err(EngineError::GameOver));
+}
+
+#[test]
+fn finds_winning_pops() {
+    // popping red's piece out of the first column drops red's next piece
+    // beside three others
+    let rules = Rules {
+        variant: Variant::PopOut,
+        ..Rules::STANDARD
+    };
+    let b = Board::from_moves_on(rules, "1223344117").unwrap();
+    assert_eq!(solver_move(&b, 1), Ok(board::pop_move(0)));
+}
+
+#[test]
+fn finds_wins_around_cylinders() {
+    let rules = Variant::Cylinder.usual_rules();
+    // red's bottom row only makes four by going around
+    let b = Board::from_moves_on(rules, "667711").unwrap();
+    assert_eq!(solver_evaluate(&b, 1), Ok(1.0));
+    assert_eq!(
+        solver_evaluate(&board_after(&[5, 5, 6, 6, 0, 0]), 1),
+        Ok(0.5)
+    );
+}
@@ -0,0 +1,2 @@
+// The game itself, shared by the Discord bot and the terminal client
+pub mod connect4;
@@ -1,38 +1,70 @@
-mod connect4;
 mod protocol;
+mod scheduler;
+mod transport;
 
+use discord_c4::connect4;
+
+use connect4::external_engine::ExternalEngine;
+use connect4::monte_carlo_ai;
 use connect4::persistency;
 
-use discord::model::{Event, ReactionEmoji, Channel};
-use discord::Discord;
+use discord::model::Event;
+use discord::{ChannelRef, Discord, State};
 
 use std::env;
+use std::str::FromStr;
+use std::thread;
 
-use protocol::Request;
-use protocol::COLUMN_EMOJI;
+const DATABASE_PATH: &str = "./prod_env.sqlite";
 
 // invite through https://discord.com/api/oauth2/authorize?client_id=805143667392118794&scope=bot&permissions=75840
 
 fn main() {
-    let discord = Discord::from_bot_token(
-        &env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN not set in environment"),
-    )
-    .expect("login failed");
+    let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN not set in environment");
+    let discord = Discord::from_bot_token(&token).expect("login failed");
+
+    println!("making a database at {}", DATABASE_PATH);
+    let mut conn = persistency::initialize(DATABASE_PATH).expect("failed to initialize database");
+    protocol::backfill_guilds(&conn, &discord);
+
+    // An external engine can play for the bot at one level, the highest one
+    // unless told otherwise
+    if let Ok(command_line) = env::var("C4_EXTERNAL_ENGINE") {
+        let engine =
+            ExternalEngine::from_command_line(&command_line).expect("C4_EXTERNAL_ENGINE is empty");
+        let ai_level = match env::var("C4_EXTERNAL_ENGINE_LEVEL") {
+            Ok(level) => u8::from_str(&level).expect("C4_EXTERNAL_ENGINE_LEVEL is not a level"),
+            Err(_) => monte_carlo_ai::MAX_LEVEL,
+        };
+        println!("playing at level {} with {}", ai_level, command_line);
+        protocol::register_external_engine(ai_level, engine)
+            .expect("external engine registered twice");
+    }
 
-    println!("making a database at ./prod_env.sqlite");
-    let mut conn =
-        persistency::initialize("./prod_env.sqlite").expect("failed to initialize database");
+    let scheduler_discord = Discord::from_bot_token(&token).expect("login failed");
+    thread::spawn(move || scheduler::run_clock_scheduler(scheduler_discord, DATABASE_PATH));
 
-    let (mut connection, _) = discord.connect().expect("connect failed");
+    let (mut connection, ready) = discord.connect().expect("connect failed");
+    // the gateway keeps the guild of each channel up to date, no need to ask
+    let mut state = State::new(ready);
     let bot_id = discord.get_current_user().expect("failed to find self").id;
     println!("Logged in and ready! My id is: {}", bot_id.0);
 
     loop {
-        match connection.recv_event() {
+        let event = connection.recv_event();
+        if let Ok(event) = &event {
+            state.update(event);
+        }
+        match event {
             Ok(Event::MessageCreate(message)) => {
-                if let Result::Ok(Channel::Public(_)) = discord.get_channel(message.channel_id) {
+                if let Some(ChannelRef::Public(_, channel)) = state.find_channel(message.channel_id)
+                {
                     println!("message sent with content: {}", message.content);
-                    let request = protocol::parse_request(&message, &bot_id);
+                    let request = protocol::authorize_request(
+                        &discord,
+                        channel.server_id,
+                        protocol::parse_request(&message, channel.server_id, &bot_id),
+                    );
                     println!("Understood request : {:?}", request);
                     let responses = protocol::process_request(&mut conn, &request);
                     println!("Replying with {:?}", responses);
@@ -46,30 +78,14 @@ fn main() {
             }
             Ok(Event::ReactionAdd(reaction)) => {
                 if reaction.user_id.0 != bot_id.0 {
-                    match &reaction.emoji {
-                        ReactionEmoji::Custom { .. } => {}
-                        ReactionEmoji::Unicode(u) => {
-                            // println!("Unicode reaction added: {}", u);
-                            for (i, emoji) in COLUMN_EMOJI.iter().enumerate() {
-                                if u == emoji {
-                                    let responses = protocol::process_request(
-                                        &mut conn,
-                                        &Request::RespondToInteraction(
-                                            reaction.user_id,
-                                            reaction.message_id,
-                                            i as u8,
-                                        ),
-                                    );
-                                    protocol::communicate_responses(
-                                        &mut conn,
-                                        &discord,
-                                        reaction.channel_id,
-                                        &responses,
-                                    );
-                                }
-                            }
-                        }
-                    }
+                    let request = protocol::parse_reaction(&reaction);
+                    let responses = protocol::process_request(&mut conn, &request);
+                    protocol::communicate_responses(
+                        &mut conn,
+                        &discord,
+                        reaction.channel_id,
+                        &responses,
+                    );
                 }
             }
             Ok(_) => {}
@@ -1,19 +1,73 @@
-use crate::connect4::board::{Board, GameResult, GameStatus, Player};
+use crate::connect4::board;
+use crate::connect4::board::{
+    Board, BoardSize, GameResult, GameStatus, Glyphs, MovesError, Player, Rules, Variant,
+};
+use crate::connect4::clock::{unix_time, Clock, TimeControl};
+use crate::connect4::engine::{Engine, MonteCarlo};
+use crate::connect4::external_engine::ExternalEngine;
 use crate::connect4::monte_carlo_ai;
 use crate::connect4::persistency;
-use crate::connect4::persistency::{Error, NotCompletedReason, OngoingMatch};
+use crate::connect4::persistency::{
+    Error, HumanMatch, InteractionKind, NotCompletedReason, OngoingMatch, PlayerRecord, Termination,
+};
 
-use discord::builders::{EmbedBuilder, EmbedFieldsBuilder};
-use discord::model::{Channel, ChannelId, Message, MessageId, ReactionEmoji, UserId};
-use discord::Discord;
+use crate::transport::{ChatTransport, Embed};
+
+use discord::model::{ChannelId, Message, MessageId, Reaction, ReactionEmoji, ServerId, UserId};
 
 use rusqlite::Connection;
 
 use rand;
 
 use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Enough for the widest board. Narrower boards use the first few.
+pub const COLUMN_EMOJI: [&str; 9] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣"];
+const COLUMN_LABELS: [&str; 9] = [
+    ":one:", ":two:", ":three:", ":four:", ":five:", ":six:", ":seven:", ":eight:", ":nine:",
+];
+// Reactions for popping a piece out of the bottom of a column, in PopOut games
+pub const POP_EMOJI: [&str; 9] = ["🇦", "🇧", "🇨", "🇩", "🇪", "🇫", "🇬", "🇭", "🇮"];
+const POP_LABELS: [&str; 9] = [
+    ":regional_indicator_a:",
+    ":regional_indicator_b:",
+    ":regional_indicator_c:",
+    ":regional_indicator_d:",
+    ":regional_indicator_e:",
+    ":regional_indicator_f:",
+    ":regional_indicator_g:",
+    ":regional_indicator_h:",
+    ":regional_indicator_i:",
+];
+pub const DRAW_EMOJI: &str = "🤝";
+pub const TAKEBACK_EMOJI: &str = "↩️";
+pub const REMATCH_EMOJI: &str = "🔁";
+
+// The pieces of each player, in the order they move, and the colour of the game
+// on their turn. red = ce4147, blue = 6cace9, green = 78b159, yellow = fdcb58
+const PLAYER_PIECES: [&str; Rules::MAX_PLAYERS as usize] = [
+    ":red_circle:",
+    ":blue_circle:",
+    ":green_circle:",
+    ":yellow_circle:",
+];
+const PLAYER_COLORS: [u64; Rules::MAX_PLAYERS as usize] = [0xce4147, 0x6cace9, 0x78b159, 0xfdcb58];
+
+// The bot accepts a draw when its expected score is below this
+const DRAW_ACCEPTANCE_THRESHOLD: f64 = 0.45;
 
-pub const COLUMN_EMOJI: [&str; 7] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣"];
+// The highest level the built-in bot plays at in chat. Its search costs what
+// the bot's always did before there were levels, which is all the event loop
+// can spare for a move.
+const MAX_CHAT_LEVEL: u8 = 5;
+
+// Bot level at which an external engine plays instead of the built-in one, if
+// one was registered
+static EXTERNAL_ENGINE: OnceLock<(u8, ExternalEngine)> = OnceLock::new();
+
+// Games in which nobody has moved for this long are ended as abandoned
+const ABANDONMENT_PERIOD: i64 = 7 * 24 * 60 * 60;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct MatchId(pub u64);
@@ -32,16 +86,69 @@ pub enum PlayOrder {
     Random,
 }
 
+// Pieces put on the board before a game starts, on top of any blockers: a
+// handicap for the weaker player, or the moves of an opening
+#[derive(Debug, PartialEq, Clone)]
+pub enum Opening {
+    None,
+    Handicap(u32),
+    Moves(String),
+}
+
+// Neutral pieces put at the bottom of the board before a game starts
+#[derive(Debug, PartialEq, Clone)]
+pub enum Blockers {
+    None,
+    Random(u32),
+    InColumns(Vec<u8>),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Request {
     Ignore,
     Help(HelpTopic),
-    Challenge(ChannelId, UserId, UserId, PlayOrder),
-    ChallengeBot(ChannelId, UserId, u8, PlayOrder),
-    PlayMove(ChannelId, UserId, u8),
+    Challenge(
+        ServerId,
+        ChannelId,
+        UserId,
+        UserId,
+        PlayOrder,
+        TimeControl,
+        Rules,
+        Blockers,
+        Opening,
+    ),
+    ChallengeBot(
+        ServerId,
+        ChannelId,
+        UserId,
+        u8,
+        PlayOrder,
+        Rules,
+        Blockers,
+        Opening,
+    ),
+    // The players in the order they move, starting with the challenger
+    ChallengeGroup(
+        ServerId,
+        ChannelId,
+        Vec<UserId>,
+        TimeControl,
+        Rules,
+        Blockers,
+    ),
+    PlayMove(ChannelId, UserId, u8, Option<MatchId>),
     RespondToInteraction(UserId, MessageId, u8),
-    SeeGame(ChannelId, UserId),
-    Resign(ChannelId, UserId),
+    SeeGame(ChannelId, UserId, Option<MatchId>),
+    Resign(ChannelId, UserId, Option<MatchId>),
+    SeeStats(ServerId, UserId),
+    OfferDraw(ChannelId, UserId, Option<MatchId>),
+    AcceptDraw(UserId, MessageId),
+    Takeback(ChannelId, UserId, Option<MatchId>),
+    ApproveTakeback(UserId, MessageId),
+    Rematch(UserId, MessageId),
+    AdminCancel(ChannelId, UserId, UserId, Option<MatchId>),
+    Unauthorized(UserId),
 }
 
 #[derive(Debug)]
@@ -50,6 +157,14 @@ pub enum UserError {
     PlayerNotPlaying,
     NotYourTurn,
     IllegalMove,
+    NothingToTakeBack,
+    NotAModerator,
+    SeveralMatches(Vec<MatchId>),
+    NoSuchMatch,
+    CorruptedMatch(MatchId),
+    NoRematchOffered,
+    InvalidOpening(MovesError),
+    FinishedOpening,
 }
 
 #[derive(Debug)]
@@ -58,52 +173,380 @@ pub enum Response {
     ShowHelp(HelpTopic),
     ShowError(UserId, UserError),
     BotPlaysMove(MatchId),
+    // The offer goes to every other player still in the game
+    OfferDraw(MatchId, UserId, Vec<UserId>),
+    DrawDeclined(UserId),
+    // Along with the number of moves to take back, the requesting player's and
+    // those played since
+    OfferTakeback(MatchId, UserId, Vec<UserId>, usize),
+    ShowGameOver(OngoingMatch, Termination),
+    ShowStats(UserId, PlayerRecord),
+    TimeWarning(UserId, i64),
+    NotifyEnd(Vec<UserId>, Termination),
+}
+
+pub fn parse_request(message: &Message, server_id: ServerId, bot_id: &UserId) -> Request {
+    let mentions: Vec<UserId> = message.mentions.iter().map(|user| user.id).collect();
+    parse_command(
+        &message.content,
+        server_id,
+        message.channel_id,
+        message.author.id,
+        &mentions,
+        bot_id,
+    )
 }
 
-pub fn parse_request(message: &Message, bot_id: &UserId) -> Request {
-    if !message.content.starts_with("!c4 ") {
+// Understands a command independently of the chat it was written in
+pub fn parse_command(
+    content: &str,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    author_id: UserId,
+    mentions: &[UserId],
+    bot_id: &UserId,
+) -> Request {
+    let selector = parse_game_selector(content);
+
+    if !content.starts_with("!c4 ") {
         Request::Ignore
     } else {
-        if message.content.starts_with("!c4 challenge") {
-            if let Some(other_player) = message.mentions.get(0) {
-                if other_player.id == *bot_id {
+        if content.starts_with("!c4 challenge") {
+            // everyone mentioned plays, apart from the challenger and the bot
+            let mut others: Vec<UserId> = vec![];
+            for mention in mentions.iter() {
+                if mention != &author_id && mention != bot_id && !others.contains(mention) {
+                    others.push(*mention);
+                }
+            }
+            if others.len() >= 2 && mentions.first() != Some(bot_id) {
+                if others.len() < usize::from(Rules::MAX_PLAYERS) {
+                    let mut players = vec![author_id];
+                    players.append(&mut others);
+                    let rules = parse_group_rules(content, players.len() as u8);
+                    Request::ChallengeGroup(
+                        server_id,
+                        channel_id,
+                        players,
+                        parse_time_control(content),
+                        rules,
+                        parse_blockers(content),
+                    )
+                } else {
+                    Request::Help(HelpTopic::Challenge)
+                }
+            } else if let Some(other_player) = mentions.get(0) {
+                if other_player == bot_id {
                     Request::ChallengeBot(
-                        message.channel_id,
-                        message.author.id,
-                        5,
+                        server_id,
+                        channel_id,
+                        author_id,
+                        parse_ai_level(content),
                         PlayOrder::Random,
+                        parse_rules(content),
+                        parse_blockers(content),
+                        parse_opening(content),
                     )
                 } else {
                     Request::Challenge(
-                        message.channel_id,
-                        message.author.id,
-                        other_player.id,
+                        server_id,
+                        channel_id,
+                        author_id,
+                        *other_player,
                         PlayOrder::GoFirst,
+                        parse_time_control(content),
+                        parse_rules(content),
+                        parse_blockers(content),
+                        parse_opening(content),
                     )
                 }
             } else {
                 Request::Help(HelpTopic::Challenge)
             }
-        } else if let Some(move_no) = message.content.strip_prefix("!c4 play ") {
-            if let Ok(n) = u8::from_str(move_no.trim()) {
-                if n > 7 || n < 1 {
+        } else if let Some(move_no) = content.strip_prefix("!c4 play ") {
+            let move_no = move_no.split_whitespace().next().unwrap_or("");
+            if let Ok(n) = u8::from_str(move_no) {
+                if n as usize > COLUMN_EMOJI.len() || n < 1 {
                     Request::Help(HelpTopic::Play)
                 } else {
-                    Request::PlayMove(message.channel_id, message.author.id, n - 1)
+                    Request::PlayMove(channel_id, author_id, n - 1, selector)
                 }
             } else {
                 Request::Help(HelpTopic::Play)
             }
-        } else if message.content.starts_with("!c4 resign") {
-            Request::Resign(message.channel_id, message.author.id)
-        } else if message.content.starts_with("!c4 see") {
-            Request::SeeGame(message.channel_id, message.author.id)
+        } else if let Some(move_no) = content.strip_prefix("!c4 pop ") {
+            let move_no = move_no.split_whitespace().next().unwrap_or("");
+            match u8::from_str(move_no) {
+                Ok(n) if n >= 1 && n as usize <= POP_EMOJI.len() => {
+                    Request::PlayMove(channel_id, author_id, board::pop_move(n - 1), selector)
+                }
+                _ => Request::Help(HelpTopic::Play),
+            }
+        } else if content.starts_with("!c4 resign") {
+            Request::Resign(channel_id, author_id, selector)
+        } else if content.starts_with("!c4 undo") {
+            Request::Takeback(channel_id, author_id, selector)
+        } else if content.starts_with("!c4 draw") {
+            Request::OfferDraw(channel_id, author_id, selector)
+        } else if content.starts_with("!c4 stats") {
+            Request::SeeStats(server_id, author_id)
+        } else if content.starts_with("!c4 admin cancel") {
+            if let Some(target) = mentions.get(0) {
+                Request::AdminCancel(channel_id, author_id, *target, selector)
+            } else {
+                Request::Help(HelpTopic::General)
+            }
+        } else if content.starts_with("!c4 see") {
+            Request::SeeGame(channel_id, author_id, selector)
         } else {
             Request::Help(HelpTopic::General)
         }
     }
 }
 
+// Games are picked by their number, as in `!c4 play 4 #12`
+fn parse_game_selector(content: &str) -> Option<MatchId> {
+    content
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('#'))
+        .find_map(|number| u64::from_str(number).ok())
+        .map(MatchId)
+}
+
+// The bot plays at level 5 unless asked otherwise, as in `!c4 challenge @bot
+// level 3`. Higher levels are only there for an external engine playing at one.
+fn parse_ai_level(content: &str) -> u8 {
+    let external_level = EXTERNAL_ENGINE.get().map(|(level, _)| *level);
+    let words: Vec<&str> = content.split_whitespace().collect();
+    words
+        .windows(2)
+        .find(|pair| pair[0] == "level")
+        .and_then(|pair| u8::from_str(pair[1]).ok())
+        .filter(|&level| {
+            (monte_carlo_ai::MIN_LEVEL..=MAX_CHAT_LEVEL).contains(&level)
+                || Some(level) == external_level
+        })
+        .unwrap_or(MAX_CHAT_LEVEL)
+}
+
+// Games are connect four on the standard 7x6 board unless a variant, another
+// size or another line length is given, as in `!c4 challenge @player 9x7
+// connect 5` or `!c4 challenge @player popout`. Variants have their usual size
+// and line length, and lines that don't fit on the board are left at the usual
+// length, or four.
+fn parse_rules(content: &str) -> Rules {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let usual = words
+        .iter()
+        .find_map(|word| Variant::from_str(word).ok())
+        .unwrap_or(Variant::Standard)
+        .usual_rules();
+    let size = words
+        .iter()
+        .find_map(|word| BoardSize::from_str(word).ok())
+        .unwrap_or(usual.size);
+    let rules = words
+        .windows(2)
+        .find(|pair| pair[0] == "connect")
+        .and_then(|pair| u8::from_str(pair[1]).ok())
+        .and_then(|line_length| Rules::new(size, line_length))
+        .or_else(|| Rules::new(size, usual.line_length))
+        .unwrap_or(Rules {
+            size,
+            ..Rules::STANDARD
+        });
+    Rules {
+        variant: usual.variant,
+        ..rules
+    }
+}
+
+// Group games are played on a board with room for everyone, unless another size
+// or line length is given. Only the variants made for more than two can be
+// played in groups, the others give way to standard rules.
+fn parse_group_rules(content: &str, players: u8) -> Rules {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let usual = Rules::usual_for_players(players);
+    let variant = words
+        .iter()
+        .find_map(|word| Variant::from_str(word).ok())
+        .filter(|variant| variant.allows_groups())
+        .unwrap_or(Variant::Standard);
+    let size = words
+        .iter()
+        .find_map(|word| BoardSize::from_str(word).ok())
+        .unwrap_or(usual.size);
+    let rules = words
+        .windows(2)
+        .find(|pair| pair[0] == "connect")
+        .and_then(|pair| u8::from_str(pair[1]).ok())
+        .and_then(|line_length| Rules::new(size, line_length))
+        .or_else(|| Rules::new(size, usual.line_length))
+        .unwrap_or(Rules {
+            size,
+            ..Rules::STANDARD
+        });
+    Rules { variant, ..rules }
+        .with_players(players)
+        .unwrap_or(usual)
+}
+
+// Blockers are asked for by number, as in `!c4 challenge @player blockers 3`,
+// or by column, as in `!c4 challenge @player blocked 2,6`
+fn parse_blockers(content: &str) -> Blockers {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let number = words
+        .windows(2)
+        .find(|pair| pair[0] == "blockers")
+        .and_then(|pair| u32::from_str(pair[1]).ok())
+        .filter(|&count| count > 0);
+    let columns = words
+        .windows(2)
+        .find(|pair| pair[0] == "blocked")
+        .map(|pair| {
+            pair[1]
+                .split(',')
+                .filter_map(|column| u8::from_str(column).ok())
+                .filter(|&column| column >= 1)
+                .map(|column| column - 1)
+                .collect::<Vec<u8>>()
+        })
+        .filter(|columns| !columns.is_empty());
+    match (number, columns) {
+        (_, Some(columns)) => Blockers::InColumns(columns),
+        (Some(count), None) => Blockers::Random(count.min(Board::MAX_BLOCKERS)),
+        (None, None) => Blockers::None,
+    }
+}
+
+// Games start from an opening given as its moves, as in `!c4 challenge @player
+// from 4453`, or with up to three handicap pieces, as in `!c4 challenge @player
+// handicap 2`
+fn parse_opening(content: &str) -> Opening {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let moves = words
+        .windows(2)
+        .find(|pair| pair[0] == "from")
+        .map(|pair| pair[1].to_string());
+    let handicap = words
+        .windows(2)
+        .find(|pair| pair[0] == "handicap")
+        .and_then(|pair| u32::from_str(pair[1]).ok())
+        .filter(|&count| count > 0);
+    match (moves, handicap) {
+        (Some(moves), _) => Opening::Moves(moves),
+        (None, Some(count)) => Opening::Handicap(count.min(Board::MAX_HANDICAP)),
+        (None, None) => Opening::None,
+    }
+}
+
+// Blockers that don't fit in their column are left out, and PopOut games are
+// played without an opening. The weaker player is the one who gets the
+// handicap.
+fn starting_board(
+    rules: Rules,
+    blockers: &Blockers,
+    opening: &Opening,
+    weaker_player: Player,
+) -> Result<Board, UserError> {
+    let mut board = Board::empty_board_with_rules(rules);
+    match blockers {
+        Blockers::None => {}
+        Blockers::Random(count) => board.add_random_blockers(*count, &mut rand::thread_rng()),
+        Blockers::InColumns(columns) => {
+            for &column in columns.iter() {
+                board.add_blocker(column);
+            }
+        }
+    }
+    match opening {
+        Opening::None => {}
+        Opening::Handicap(count) => board.add_handicap(weaker_player, *count),
+        Opening::Moves(moves) => board
+            .play_opening(moves)
+            .map_err(UserError::InvalidOpening)?,
+    }
+    match board.game_status() {
+        GameStatus::Turn(_) => Ok(board),
+        GameStatus::GameOver(_) => Err(UserError::FinishedOpening),
+    }
+}
+
+fn parse_time_control(content: &str) -> TimeControl {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    if words.contains(&"blitz") {
+        TimeControl::Blitz
+    } else if words.contains(&"rapid") {
+        TimeControl::Rapid
+    } else if words.contains(&"correspondence") {
+        TimeControl::Correspondence
+    } else {
+        TimeControl::Untimed
+    }
+}
+
+pub fn parse_reaction(reaction: &Reaction) -> Request {
+    match &reaction.emoji {
+        ReactionEmoji::Custom { .. } => Request::Ignore,
+        ReactionEmoji::Unicode(u) => parse_reaction_emoji(reaction.user_id, reaction.message_id, u),
+    }
+}
+
+pub fn parse_reaction_emoji(user_id: UserId, message_id: MessageId, emoji: &str) -> Request {
+    if emoji == DRAW_EMOJI {
+        Request::AcceptDraw(user_id, message_id)
+    } else if emoji == TAKEBACK_EMOJI {
+        Request::ApproveTakeback(user_id, message_id)
+    } else if emoji == REMATCH_EMOJI {
+        Request::Rematch(user_id, message_id)
+    } else if let Some(i) = COLUMN_EMOJI.iter().position(|column| emoji == *column) {
+        Request::RespondToInteraction(user_id, message_id, i as u8)
+    } else if let Some(i) = POP_EMOJI.iter().position(|column| emoji == *column) {
+        Request::RespondToInteraction(user_id, message_id, board::pop_move(i as u8))
+    } else {
+        Request::Ignore
+    }
+}
+
+// Looks up the guild of the matches stored before guild ids were. A channel that
+// can't be found anymore keeps its matches without one.
+pub fn backfill_guilds(conn: &Connection, transport: &dyn ChatTransport) {
+    let channel_ids =
+        persistency::channels_without_guild(conn).expect("DB error when finding channels");
+    for channel_id in channel_ids {
+        match transport.guild_of_channel(ChannelId(channel_id)) {
+            Ok(server_id) => persistency::assign_guild(conn, channel_id, server_id.0)
+                .expect("DB error when assigning guild"),
+            Err(e) => println!(
+                "could not find the guild of channel {}: {:?}",
+                channel_id, e
+            ),
+        }
+    }
+}
+
+// Turns admin requests from users without the Manage Messages permission into
+// Unauthorized. Everything else goes through untouched.
+pub fn authorize_request(
+    transport: &dyn ChatTransport,
+    server_id: ServerId,
+    request: Request,
+) -> Request {
+    match request {
+        Request::AdminCancel(_, moderator_id, _, _) => {
+            if transport
+                .is_moderator(server_id, moderator_id)
+                .unwrap_or(false)
+            {
+                request
+            } else {
+                Request::Unauthorized(moderator_id)
+            }
+        }
+        other_request => other_request,
+    }
+}
+
 pub fn process_request(conn: &mut Connection, request: &Request) -> Vec<Response> {
     match request {
         Request::Ignore => {
@@ -112,35 +555,90 @@ pub fn process_request(conn: &mut Connection, request: &Request) -> Vec<Response
         Request::Help(help_topic) => {
             vec![Response::ShowHelp(*help_topic)]
         }
-        Request::Challenge(channel, challenger, challenged, play_order) => challenge_human(
-            conn,
+        Request::Challenge(
+            server_id,
             channel,
             challenger,
             challenged,
-            decide_random_order(*play_order),
-        ),
-        Request::ChallengeBot(channel_id, player_id, ai_level, play_order) => {
-            match decide_random_order(*play_order) {
-                PlayOrder::GoFirst => {
-                    challenge_bot_go_first(conn, channel_id, player_id, *ai_level)
-                }
-                PlayOrder::GoSecond => {
-                    challenge_bot_go_second(conn, channel_id, player_id, *ai_level)
-                }
+            play_order,
+            time_control,
+            rules,
+            blockers,
+            opening,
+        ) => {
+            // the player challenged plays blue, and takes any handicap
+            match starting_board(*rules, blockers, opening, Player::Blue) {
+                Err(error) => vec![Response::ShowError(*challenger, error)],
+                Ok(start) => challenge_human(
+                    conn,
+                    server_id,
+                    channel,
+                    challenger,
+                    challenged,
+                    decide_random_order(*play_order),
+                    *time_control,
+                    start,
+                ),
+            }
+        }
+        Request::ChallengeBot(
+            server_id,
+            channel_id,
+            player_id,
+            ai_level,
+            play_order,
+            rules,
+            blockers,
+            opening,
+        ) => {
+            let player_is_red = match decide_random_order(*play_order) {
+                PlayOrder::GoFirst => true,
+                PlayOrder::GoSecond => false,
                 PlayOrder::Random => panic!("The impossible has happened"),
+            };
+            let player = if player_is_red {
+                Player::Red
+            } else {
+                Player::Blue
+            };
+            // against the bot, the handicap is the player's
+            match starting_board(*rules, blockers, opening, player) {
+                Err(error) => vec![Response::ShowError(*player_id, error)],
+                Ok(start) => challenge_bot(
+                    conn,
+                    server_id,
+                    channel_id,
+                    player_id,
+                    player_is_red,
+                    *ai_level,
+                    start,
+                ),
             }
         }
-        Request::PlayMove(channel_id, player_id, move_no) => {
-            let found_match =
-                persistency::retrieve_match_by_player(conn, channel_id.0, player_id.0);
-            match found_match {
-                Err(Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)) => {
-                    vec![Response::ShowError(*player_id, UserError::PlayerNotPlaying)]
-                }
-
-                Err(_) => {
-                    panic!("Unknown error retrieving match")
-                }
+        Request::ChallengeGroup(
+            server_id,
+            channel_id,
+            player_ids,
+            time_control,
+            rules,
+            blockers,
+        ) => {
+            // there are no handicaps or openings for more than two
+            match starting_board(*rules, blockers, &Opening::None, Player::Red) {
+                Err(error) => vec![Response::ShowError(player_ids[0], error)],
+                Ok(start) => challenge_group(
+                    conn,
+                    server_id,
+                    channel_id,
+                    player_ids,
+                    *time_control,
+                    start,
+                ),
+            }
+        }
+        Request::PlayMove(channel_id, player_id, move_no, selector) => {
+            match find_players_match(conn, channel_id, player_id, *selector) {
+                Err(error) => vec![Response::ShowError(*player_id, error)],
                 Ok(OngoingMatch::HumanMatch(human_match)) => {
                     process_move_vs_human(conn, human_match, *player_id, *move_no)
                 }
@@ -151,43 +649,599 @@ pub fn process_request(conn: &mut Connection, request: &Request) -> Vec<Response
             }
         }
         Request::RespondToInteraction(player_id, message_id, move_no) => {
-            let found_interaction =
-                persistency::search_interaction(&conn, message_id.0, player_id.0);
+            let found_interaction = persistency::search_interaction(
+                conn,
+                message_id.0,
+                player_id.0,
+                InteractionKind::PlayMove,
+            );
             match found_interaction {
+                Err(Error::CorruptedMatch(match_id, _)) => vec![Response::ShowError(
+                    *player_id,
+                    UserError::CorruptedMatch(MatchId(match_id)),
+                )],
                 Err(_) => vec![],
                 Ok(ongoing_match) => process_request(
                     conn,
                     &Request::PlayMove(
-                        ChannelId(ongoing_match.get_server_id()),
+                        ChannelId(ongoing_match.get_channel_id()),
                         *player_id,
                         *move_no,
+                        Some(MatchId(ongoing_match.get_id())),
                     ),
                 ),
             }
         }
-        Request::SeeGame(_channel, _player_id) => {
+        Request::SeeGame(_channel, _player_id, _selector) => {
             vec![]
         }
-        Request::Resign(channel_id, player_id) => {
-            let found_match =
-                persistency::retrieve_match_by_player(conn, channel_id.0, player_id.0);
-            match found_match {
-                Err(Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)) => {
-                    vec![Response::ShowError(*player_id, UserError::PlayerNotPlaying)]
+        Request::Resign(channel_id, player_id, selector) => {
+            match find_players_match(conn, channel_id, player_id, *selector) {
+                Err(error) => vec![Response::ShowError(*player_id, error)],
+                Ok(ongoing_match) => resign(conn, ongoing_match, *player_id),
+            }
+        }
+        Request::SeeStats(server_id, player_id) => {
+            let record = persistency::player_record(conn, server_id.0, player_id.0)
+                .expect("DB error when computing stats");
+            vec![Response::ShowStats(*player_id, record)]
+        }
+        Request::OfferDraw(channel_id, player_id, selector) => {
+            match find_players_match(conn, channel_id, player_id, *selector) {
+                Err(error) => vec![Response::ShowError(*player_id, error)],
+                Ok(OngoingMatch::HumanMatch(human_match)) => {
+                    offer_draw_vs_human(conn, human_match, *player_id)
+                }
+
+                Ok(OngoingMatch::ComputerMatch(computer_match)) => {
+                    offer_draw_vs_computer(conn, computer_match, *player_id)
+                }
+            }
+        }
+        Request::AcceptDraw(player_id, message_id) => {
+            let found_interaction = persistency::search_interaction(
+                conn,
+                message_id.0,
+                player_id.0,
+                InteractionKind::AcceptDraw,
+            );
+            match found_interaction {
+                Ok(OngoingMatch::HumanMatch(human_match)) => {
+                    let pending =
+                        accept_offer(conn, &human_match, *player_id, InteractionKind::AcceptDraw);
+                    match pending {
+                        // the others agreed to it too
+                        Ok((_, pending)) if pending.is_empty() => {
+                            conclude_agreed_draw(conn, OngoingMatch::HumanMatch(human_match))
+                        }
+                        _ => vec![],
+                    }
+                }
+                _ => vec![],
+            }
+        }
+        Request::Takeback(channel_id, player_id, selector) => {
+            match find_players_match(conn, channel_id, player_id, *selector) {
+                Err(error) => vec![Response::ShowError(*player_id, error)],
+                Ok(OngoingMatch::HumanMatch(human_match)) => {
+                    request_takeback_vs_human(human_match, *player_id)
                 }
 
-                Err(_) => {
-                    panic!("Unknown error retrieving match")
+                Ok(OngoingMatch::ComputerMatch(computer_match)) => {
+                    // games against the bot don't count in the stats, so no
+                    // approval is needed
+                    let player = if computer_match.player_is_red {
+                        Player::Red
+                    } else {
+                        Player::Blue
+                    };
+                    take_back(
+                        conn,
+                        OngoingMatch::ComputerMatch(computer_match),
+                        player,
+                        *player_id,
+                    )
+                }
+            }
+        }
+        Request::ApproveTakeback(player_id, message_id) => {
+            let found_interaction = persistency::search_interaction(
+                conn,
+                message_id.0,
+                player_id.0,
+                InteractionKind::ApproveTakeback,
+            );
+            match found_interaction {
+                Ok(OngoingMatch::HumanMatch(human_match)) => {
+                    let approval = accept_offer(
+                        conn,
+                        &human_match,
+                        *player_id,
+                        InteractionKind::ApproveTakeback,
+                    );
+                    // the move is taken back once everyone asked has allowed it
+                    match approval {
+                        Ok((requester_id, pending)) if pending.is_empty() => {
+                            match human_match.player_of(requester_id.0) {
+                                Some(requester) => take_back(
+                                    conn,
+                                    OngoingMatch::HumanMatch(human_match),
+                                    requester,
+                                    requester_id,
+                                ),
+                                None => vec![],
+                            }
+                        }
+                        _ => vec![],
+                    }
+                }
+                _ => vec![],
+            }
+        }
+        Request::Rematch(player_id, message_id) => {
+            // players swap colours, unless the game was set up for one of them
+            match persistency::take_rematch_offer(conn, message_id.0, player_id.0) {
+                Err(Error::NotCompleted(NotCompletedReason::NoSuchInteraction)) => {
+                    vec![Response::ShowError(*player_id, UserError::NoRematchOffered)]
+                }
+                Err(Error::CorruptedMatch(match_id, position_error)) => {
+                    println!(
+                        "Archived match {} is corrupted: {}",
+                        match_id, position_error
+                    );
+                    vec![Response::ShowError(
+                        *player_id,
+                        UserError::CorruptedMatch(MatchId(match_id)),
+                    )]
+                }
+                Err(_) => panic!("DB error when taking rematch offer"),
+                // in group games everyone moves up a seat, and the first
+                // player moves last
+                Ok(OngoingMatch::HumanMatch(h)) if h.board.rules().players > 2 => {
+                    let mut player_ids: Vec<UserId> =
+                        h.player_ids().into_iter().map(UserId).collect();
+                    player_ids.rotate_left(1);
+                    challenge_group(
+                        conn,
+                        &ServerId(h.guild_id),
+                        &ChannelId(h.channel_id),
+                        &player_ids,
+                        h.clock.time_control,
+                        h.board.starting_position(),
+                    )
+                }
+                Ok(OngoingMatch::HumanMatch(h)) => {
+                    let (red_player_id, blue_player_id) = if h.board.preset() == 0 {
+                        (h.blue_player_id, h.red_player_id)
+                    } else {
+                        (h.red_player_id, h.blue_player_id)
+                    };
+                    challenge_human(
+                        conn,
+                        &ServerId(h.guild_id),
+                        &ChannelId(h.channel_id),
+                        &UserId(red_player_id),
+                        &UserId(blue_player_id),
+                        PlayOrder::GoFirst,
+                        h.clock.time_control,
+                        h.board.starting_position(),
+                    )
+                }
+                Ok(OngoingMatch::ComputerMatch(c)) => challenge_bot(
+                    conn,
+                    &ServerId(c.guild_id),
+                    &ChannelId(c.channel_id),
+                    &UserId(c.player_id),
+                    c.player_is_red == (c.board.preset() != 0),
+                    c.ai_level,
+                    c.board.starting_position(),
+                ),
+            }
+        }
+        Request::AdminCancel(channel_id, _moderator_id, target_id, selector) => {
+            match find_players_match(conn, channel_id, target_id, *selector) {
+                // a corrupted match can't be archived, so it's thrown away
+                Err(UserError::CorruptedMatch(match_id)) => {
+                    persistency::delete_match(conn, match_id.0)
+                        .expect("DB error when deleting match");
+                    vec![Response::NotifyEnd(
+                        vec![*target_id],
+                        Termination::Cancelled,
+                    )]
                 }
+                Err(error) => vec![Response::ShowError(*target_id, error)],
                 Ok(ongoing_match) => {
-                    persistency::delete_match(conn, ongoing_match.get_id());
-                    vec![]
+                    end_without_result(conn, ongoing_match, Termination::Cancelled)
                 }
             }
         }
+        Request::Unauthorized(user_id) => {
+            vec![Response::ShowError(*user_id, UserError::NotAModerator)]
+        }
+    }
+}
+
+// The match a command is about, or why it can't be told which one that is
+fn find_players_match(
+    conn: &Connection,
+    channel_id: &ChannelId,
+    player_id: &UserId,
+    selector: Option<MatchId>,
+) -> Result<OngoingMatch, UserError> {
+    let found_match = persistency::retrieve_match_by_player(
+        conn,
+        channel_id.0,
+        player_id.0,
+        selector.map(|match_id| match_id.0),
+    );
+    match found_match {
+        Err(Error::NotCompleted(NotCompletedReason::PlayerHasNoMatches)) => {
+            Err(UserError::PlayerNotPlaying)
+        }
+        Err(Error::NotCompleted(NotCompletedReason::NoSuchMatch)) => Err(UserError::NoSuchMatch),
+        Err(Error::CorruptedMatch(match_id, position_error)) => {
+            println!("Match {} is corrupted: {}", match_id, position_error);
+            Err(UserError::CorruptedMatch(MatchId(match_id)))
+        }
+        Err(Error::NotCompleted(NotCompletedReason::SeveralMatches)) => {
+            let match_ids =
+                persistency::retrieve_match_ids_by_player(conn, channel_id.0, player_id.0)
+                    .expect("DB error when listing matches");
+            Err(UserError::SeveralMatches(
+                match_ids.into_iter().map(MatchId).collect(),
+            ))
+        }
+        Err(_) => {
+            panic!("Unknown error retrieving match")
+        }
+        Ok(found_match) => Ok(found_match),
+    }
+}
+
+// The other players still in the game, whom the player has to ask before a
+// draw or a takeback
+fn other_players_in_play(human_match: &HumanMatch, player: Player) -> Vec<UserId> {
+    let board = &human_match.board;
+    board
+        .players()
+        .filter(|&p| p != player && board.is_in_play(p))
+        .filter_map(|p| human_match.player_id(p))
+        .map(UserId)
+        .collect()
+}
+
+// Accepts an offer made to the player. Returns who made it, along with the
+// players who still have to accept it.
+fn accept_offer(
+    conn: &Connection,
+    human_match: &HumanMatch,
+    player_id: UserId,
+    kind: InteractionKind,
+) -> Result<(UserId, Vec<UserId>), Error> {
+    let offered_by = persistency::accept_offer(conn, human_match.match_id, player_id.0, kind)?;
+    let pending = persistency::pending_offer(conn, human_match.match_id, kind)?;
+    Ok((
+        UserId(offered_by),
+        pending.into_iter().map(UserId).collect(),
+    ))
+}
+
+// How many moves have to be undone so that the given player can play their
+// last move again, if there is such a move. Those are their move and the ones
+// played since.
+fn plies_to_take_back(ongoing_match: &OngoingMatch, player: Player) -> Option<usize> {
+    let mut board = ongoing_match.get_board().clone();
+    if let GameStatus::GameOver(_) = board.game_status() {
+        return None;
+    }
+
+    for (plies, &m) in ongoing_match.get_move_history().iter().rev().enumerate() {
+        board.undo_move(m).ok()?;
+        if board.game_status() == GameStatus::Turn(player) {
+            return Some(plies + 1);
+        }
+    }
+    None
+}
+
+fn request_takeback_vs_human(human_match: HumanMatch, player_id: UserId) -> Vec<Response> {
+    let player = match human_match.player_of(player_id.0) {
+        None => return vec![Response::ShowError(player_id, UserError::PlayerNotPlaying)],
+        Some(player) => player,
+    };
+    let asked_players = other_players_in_play(&human_match, player);
+
+    match plies_to_take_back(&OngoingMatch::HumanMatch(human_match.clone()), player) {
+        None => vec![Response::ShowError(player_id, UserError::NothingToTakeBack)],
+        Some(plies) => vec![Response::OfferTakeback(
+            MatchId(human_match.match_id),
+            player_id,
+            asked_players,
+            plies,
+        )],
+    }
+}
+
+fn take_back(
+    conn: &Connection,
+    mut ongoing_match: OngoingMatch,
+    player: Player,
+    player_id: UserId,
+) -> Vec<Response> {
+    let plies = match plies_to_take_back(&ongoing_match, player) {
+        None => return vec![Response::ShowError(player_id, UserError::NothingToTakeBack)],
+        Some(plies) => plies,
+    };
+
+    let previous_turn = match ongoing_match.get_board().game_status() {
+        GameStatus::Turn(p) => p,
+        GameStatus::GameOver(_) => panic!("took back a move of a finished game"),
+    };
+
+    let mut move_history = ongoing_match.get_move_history().clone();
+    for _ in 0..plies {
+        let column = move_history.pop().expect("move history too short");
+        ongoing_match
+            .get_board_mut()
+            .undo_move(column)
+            .expect("move history out of sync with the board");
+    }
+    match &mut ongoing_match {
+        OngoingMatch::HumanMatch(h) => {
+            h.move_history = move_history;
+            if let GameStatus::Turn(next_turn) = h.board.game_status() {
+                h.clock.pass_turn(previous_turn, next_turn, unix_time());
+                persistency::update_match_clock(conn, h.match_id, &h.clock)
+                    .expect("Error updating game clock");
+            }
+        }
+        OngoingMatch::ComputerMatch(c) => c.move_history = move_history,
+    };
+
+    persistency::update_match_board(
+        conn,
+        ongoing_match.get_id(),
+        ongoing_match.get_board(),
+        ongoing_match.get_move_history(),
+    )
+    .expect("Error updating game state");
+
+    vec![Response::ShowGame(ongoing_match, true, None)]
+}
+
+// Offering a draw accepts one offered to the player. The game is drawn once
+// everyone still in it agrees.
+fn offer_draw_vs_human(
+    conn: &Connection,
+    human_match: HumanMatch,
+    player_id: UserId,
+) -> Vec<Response> {
+    let player = match human_match.player_of(player_id.0) {
+        None => return vec![Response::ShowError(player_id, UserError::PlayerNotPlaying)],
+        Some(player) => player,
+    };
+
+    let offer_pending = persistency::has_pending_interaction(
+        conn,
+        human_match.match_id,
+        player_id.0,
+        InteractionKind::AcceptDraw,
+    )
+    .expect("DB error when looking up draw offers");
+
+    let offered_players = if offer_pending {
+        let (_, pending) = accept_offer(conn, &human_match, player_id, InteractionKind::AcceptDraw)
+            .expect("DB error when accepting draw");
+        pending
+    } else {
+        other_players_in_play(&human_match, player)
+    };
+
+    if offered_players.is_empty() {
+        conclude_agreed_draw(conn, OngoingMatch::HumanMatch(human_match))
+    } else {
+        vec![Response::OfferDraw(
+            MatchId(human_match.match_id),
+            player_id,
+            offered_players,
+        )]
     }
 }
 
+fn offer_draw_vs_computer(
+    conn: &Connection,
+    computer_match: persistency::ComputerMatch,
+    player_id: UserId,
+) -> Vec<Response> {
+    let score_to_move = engine_at_level(computer_match.ai_level, &computer_match.board)
+        .evaluate(&computer_match.board, &computer_match.move_history)
+        .or_else(|_| {
+            built_in_engine(computer_match.ai_level)
+                .evaluate(&computer_match.board, &computer_match.move_history)
+        })
+        .expect("AI failure :(");
+
+    let bot_score = if check_player_turn_vs_bot(&computer_match, player_id) {
+        1.0 - score_to_move
+    } else {
+        score_to_move
+    };
+
+    if bot_score < DRAW_ACCEPTANCE_THRESHOLD {
+        conclude_agreed_draw(conn, OngoingMatch::ComputerMatch(computer_match))
+    } else {
+        vec![Response::DrawDeclined(player_id)]
+    }
+}
+
+fn resign(conn: &Connection, ongoing_match: OngoingMatch, player_id: UserId) -> Vec<Response> {
+    let player = match &ongoing_match {
+        OngoingMatch::HumanMatch(h) => match h.player_of(player_id.0) {
+            None => return vec![Response::ShowError(player_id, UserError::PlayerNotPlaying)],
+            Some(player) => player,
+        },
+        OngoingMatch::ComputerMatch(c) => {
+            if c.player_is_red {
+                Player::Red
+            } else {
+                Player::Blue
+            }
+        }
+    };
+
+    drop_out(conn, ongoing_match, player, Termination::Resignation)
+}
+
+// Ends the game in favour of the opponent of the player whose time ran out, or
+// has the others play on without them in a group game
+fn forfeit_on_time(conn: &Connection, ongoing_match: OngoingMatch) -> Vec<Response> {
+    match ongoing_match.get_board().game_status() {
+        GameStatus::Turn(player) => drop_out(conn, ongoing_match, player, Termination::Timeout),
+        GameStatus::GameOver(_) => {
+            if !archive_ongoing_match(conn, &ongoing_match, Termination::Timeout) {
+                return vec![];
+            }
+            vec![Response::ShowGameOver(ongoing_match, Termination::Timeout)]
+        }
+    }
+}
+
+// The player leaves the game. With two players that ends it, while in a group
+// game the others play on until only one is left.
+fn drop_out(
+    conn: &Connection,
+    mut ongoing_match: OngoingMatch,
+    player: Player,
+    termination: Termination,
+) -> Vec<Response> {
+    let player_to_move = ongoing_match.get_board().game_status();
+    ongoing_match.get_board_mut().resign(player);
+
+    match (ongoing_match, player_to_move) {
+        (OngoingMatch::HumanMatch(mut h), GameStatus::Turn(player_to_move))
+            if matches!(h.board.game_status(), GameStatus::Turn(_)) =>
+        {
+            persistency::update_match_board(conn, h.match_id, &h.board, &h.move_history)
+                .expect("Error updating game state");
+            if let GameStatus::Turn(next_turn) = h.board.game_status() {
+                h.clock.pass_turn(player_to_move, next_turn, unix_time());
+            }
+            persistency::update_match_clock(conn, h.match_id, &h.clock)
+                .expect("Error updating game clock");
+            vec![Response::ShowGame(OngoingMatch::HumanMatch(h), true, None)]
+        }
+        (ongoing_match, _) => {
+            if !archive_ongoing_match(conn, &ongoing_match, termination) {
+                return vec![];
+            }
+            vec![Response::ShowGameOver(ongoing_match, termination)]
+        }
+    }
+}
+
+// Archives the match unless it already ended, which happens when its clock runs
+// out while a move is played, as the clock scheduler has its own connection.
+// Whoever gets there second leaves the game alone.
+fn archive_ongoing_match(
+    conn: &Connection,
+    ongoing_match: &OngoingMatch,
+    termination: Termination,
+) -> bool {
+    match persistency::archive_match(conn, ongoing_match, termination) {
+        Ok(()) => true,
+        Err(Error::NotCompleted(NotCompletedReason::NoSuchMatch)) => false,
+        Err(_) => panic!("DB error when archiving match"),
+    }
+}
+
+// Warns the players that are about to run out of time, and ends the games of
+// those that already have. Returns the responses with the channel they are for.
+pub fn check_clocks(conn: &Connection, now: i64) -> Vec<(ChannelId, Response)> {
+    let warning_horizon = now + TimeControl::Correspondence.warning_time();
+    let due_matches = persistency::retrieve_matches_due(conn, warning_horizon)
+        .expect("DB error when looking up clocks");
+
+    let mut responses = vec![];
+    for due_match in due_matches {
+        let mut human_match = match due_match {
+            OngoingMatch::HumanMatch(h) => h,
+            OngoingMatch::ComputerMatch(_) => continue,
+        };
+        let channel_id = ChannelId(human_match.channel_id);
+
+        if human_match.clock.is_expired(now) {
+            for response in forfeit_on_time(conn, OngoingMatch::HumanMatch(human_match)) {
+                responses.push((channel_id, response));
+            }
+        } else if human_match.clock.needs_warning(now) {
+            let player_id = match human_match.board.game_status() {
+                GameStatus::Turn(player) => match human_match.player_id(player) {
+                    Some(player_id) => player_id,
+                    None => continue,
+                },
+                GameStatus::GameOver(_) => continue,
+            };
+            human_match.clock.warned = true;
+            persistency::update_match_clock(conn, human_match.match_id, &human_match.clock)
+                .expect("Error updating game clock");
+            responses.push((
+                channel_id,
+                Response::TimeWarning(UserId(player_id), human_match.clock.deadline.unwrap_or(now)),
+            ));
+        }
+    }
+
+    responses
+}
+
+// Ends a game that is stuck, for example because one of the players left
+fn end_without_result(
+    conn: &Connection,
+    ongoing_match: OngoingMatch,
+    termination: Termination,
+) -> Vec<Response> {
+    if !archive_ongoing_match(conn, &ongoing_match, termination) {
+        return vec![];
+    }
+    let players = match &ongoing_match {
+        OngoingMatch::HumanMatch(h) => h.player_ids().into_iter().map(UserId).collect(),
+        OngoingMatch::ComputerMatch(c) => vec![UserId(c.player_id)],
+    };
+    vec![
+        Response::ShowGameOver(ongoing_match, termination),
+        Response::NotifyEnd(players, termination),
+    ]
+}
+
+// Ends the games in which nobody has played a move for too long. Returns the
+// responses with the channel they are for.
+pub fn sweep_abandoned_matches(conn: &Connection, now: i64) -> Vec<(ChannelId, Response)> {
+    let inactive_matches =
+        persistency::retrieve_matches_inactive_since(conn, now - ABANDONMENT_PERIOD)
+            .expect("DB error when looking up inactive matches");
+
+    let mut responses = vec![];
+    for inactive_match in inactive_matches {
+        let channel_id = ChannelId(inactive_match.get_channel_id());
+        for response in end_without_result(conn, inactive_match, Termination::Abandoned) {
+            responses.push((channel_id, response));
+        }
+    }
+
+    responses
+}
+
+fn conclude_agreed_draw(conn: &Connection, mut ongoing_match: OngoingMatch) -> Vec<Response> {
+    ongoing_match.get_board_mut().agree_draw();
+    if !archive_ongoing_match(conn, &ongoing_match, Termination::DrawAgreed) {
+        return vec![];
+    }
+    vec![Response::ShowGameOver(
+        ongoing_match,
+        Termination::DrawAgreed,
+    )]
+}
+
 fn process_move_vs_human(
     conn: &Connection,
     mut human_match: persistency::HumanMatch,
@@ -195,22 +1249,44 @@ fn process_move_vs_human(
     move_no: u8,
 ) -> Vec<Response> {
     let turn_ok = check_player_turn_vs_human(&human_match, player_id);
+    let now = unix_time();
 
-    if !turn_ok {
+    if human_match.clock.is_expired(now) {
+        forfeit_on_time(conn, OngoingMatch::HumanMatch(human_match))
+    } else if !turn_ok {
         vec![Response::ShowError(player_id, UserError::NotYourTurn)]
     } else if !human_match.board.is_move_legal(move_no) {
         vec![Response::ShowError(player_id, UserError::IllegalMove)]
     } else {
+        let mover = match human_match.board.game_status() {
+            GameStatus::Turn(p) => p,
+            GameStatus::GameOver(_) => panic!("move played in a finished game"),
+        };
         human_match.board.play_move(move_no);
+        human_match.move_history.push(move_no);
 
         let setup_interaction = match human_match.board.game_status() {
             GameStatus::GameOver(_) => {
-                persistency::delete_match(conn, human_match.match_id);
+                if !archive_ongoing_match(
+                    conn,
+                    &OngoingMatch::HumanMatch(human_match.clone()),
+                    Termination::Normal,
+                ) {
+                    return vec![];
+                }
                 false
             }
-            GameStatus::Turn(_) => {
-                persistency::update_match_board(conn, human_match.match_id, &human_match.board)
-                    .expect("Error updating game state");
+            GameStatus::Turn(next_turn) => {
+                persistency::update_match_board(
+                    conn,
+                    human_match.match_id,
+                    &human_match.board,
+                    &human_match.move_history,
+                )
+                .expect("Error updating game state");
+                human_match.clock.pass_turn(mover, next_turn, now);
+                persistency::update_match_clock(conn, human_match.match_id, &human_match.clock)
+                    .expect("Error updating game clock");
                 true
             }
         };
@@ -237,12 +1313,25 @@ fn process_move_vs_computer(
         vec![Response::ShowError(player_id, UserError::IllegalMove)]
     } else {
         computer_match.board.play_move(move_no);
+        computer_match.move_history.push(move_no);
 
-        persistency::update_match_board(conn, computer_match.match_id, &computer_match.board);
+        persistency::update_match_board(
+            conn,
+            computer_match.match_id,
+            &computer_match.board,
+            &computer_match.move_history,
+        )
+        .expect("Error updating game state");
 
         let mut bot_responses = match computer_match.board.game_status() {
             GameStatus::GameOver(_) => {
-                persistency::delete_match(conn, computer_match.match_id);
+                if !archive_ongoing_match(
+                    conn,
+                    &OngoingMatch::ComputerMatch(computer_match.clone()),
+                    Termination::Normal,
+                ) {
+                    return vec![];
+                }
                 vec![]
             }
             GameStatus::Turn(_) => vec![Response::BotPlaysMove(MatchId(computer_match.match_id))],
@@ -261,10 +1350,13 @@ fn process_move_vs_computer(
 
 fn challenge_human(
     conn: &mut Connection,
+    server_id: &ServerId,
     channel: &ChannelId,
     challenger: &UserId,
     challenged: &UserId,
     play_order: PlayOrder,
+    time_control: TimeControl,
+    start: Board,
 ) -> Vec<Response> {
     let mut red_player_id;
     let mut blue_player_id;
@@ -283,18 +1375,19 @@ fn challenge_human(
             panic!("The impossible has happened");
         }
     }
-    let match_id_result =
-        persistency::new_human_match(conn, channel.0, red_player_id, blue_player_id);
+    let match_id_result = persistency::new_human_match(
+        conn,
+        server_id.0,
+        channel.0,
+        red_player_id,
+        blue_player_id,
+        Clock::start(time_control, unix_time()),
+        start,
+    );
     match match_id_result {
-        Err(Error::NotCompleted(NotCompletedReason::RedAlreadyPlaying)) => {
+        Err(Error::NotCompleted(NotCompletedReason::AlreadyPlayingEachOther)) => {
             vec![Response::ShowError(
-                UserId(red_player_id),
-                UserError::PlayerAlreadyPlaying,
-            )]
-        }
-        Err(Error::NotCompleted(NotCompletedReason::BlueAlreadyPlaying)) => {
-            vec![Response::ShowError(
-                UserId(blue_player_id),
+                *challenger,
                 UserError::PlayerAlreadyPlaying,
             )]
         }
@@ -309,25 +1402,35 @@ fn challenge_human(
     }
 }
 
-fn challenge_bot_go_first(
+// The players move in the order they are given, starting with the challenger
+fn challenge_group(
     conn: &mut Connection,
+    server_id: &ServerId,
     channel_id: &ChannelId,
-    player_id: &UserId,
-    ai_level: u8,
+    player_ids: &[UserId],
+    time_control: TimeControl,
+    start: Board,
 ) -> Vec<Response> {
-    let match_id_result =
-        persistency::new_computer_match(conn, channel_id.0, player_id.0, true, ai_level);
+    let ids: Vec<u64> = player_ids.iter().map(|id| id.0).collect();
+    let match_id_result = persistency::new_group_match(
+        conn,
+        server_id.0,
+        channel_id.0,
+        &ids,
+        Clock::start(time_control, unix_time()),
+        start,
+    );
     match match_id_result {
-        Err(Error::NotCompleted(NotCompletedReason::PlayerAlreadyPlaying)) => {
+        Err(Error::NotCompleted(NotCompletedReason::AlreadyPlayingEachOther)) => {
             vec![Response::ShowError(
-                *player_id,
+                player_ids[0],
                 UserError::PlayerAlreadyPlaying,
             )]
         }
         Err(unknown_error) => Err(unknown_error).expect("unknown error encountered"),
-        Ok(computer_match) => {
+        Ok(human_match) => {
             vec![Response::ShowGame(
-                OngoingMatch::ComputerMatch(computer_match),
+                OngoingMatch::HumanMatch(human_match),
                 true,
                 None,
             )]
@@ -335,14 +1438,25 @@ fn challenge_bot_go_first(
     }
 }
 
-fn challenge_bot_go_second(
+// The bot plays first when the start leaves it the move
+fn challenge_bot(
     conn: &mut Connection,
+    server_id: &ServerId,
     channel_id: &ChannelId,
     player_id: &UserId,
+    player_is_red: bool,
     ai_level: u8,
+    start: Board,
 ) -> Vec<Response> {
-    let match_id_result =
-        persistency::new_computer_match(conn, channel_id.0, player_id.0, false, ai_level);
+    let match_id_result = persistency::new_computer_match(
+        conn,
+        server_id.0,
+        channel_id.0,
+        player_id.0,
+        player_is_red,
+        ai_level,
+        start,
+    );
     match match_id_result {
         Err(Error::NotCompleted(NotCompletedReason::PlayerAlreadyPlaying)) => {
             vec![Response::ShowError(
@@ -355,10 +1469,16 @@ fn challenge_bot_go_second(
 
         Ok(initial_bot_match) => {
             let match_id = initial_bot_match.match_id;
-            vec![
-                Response::ShowGame(OngoingMatch::ComputerMatch(initial_bot_match), true, None),
-                Response::BotPlaysMove(MatchId(match_id)),
-            ]
+            let bot_to_move = !check_player_turn_vs_bot(&initial_bot_match, *player_id);
+            let mut responses = vec![Response::ShowGame(
+                OngoingMatch::ComputerMatch(initial_bot_match),
+                true,
+                None,
+            )];
+            if bot_to_move {
+                responses.push(Response::BotPlaysMove(MatchId(match_id)));
+            }
+            responses
         }
     }
 }
@@ -372,16 +1492,25 @@ fn play_bot_move(conn: &Connection, match_id: MatchId) -> Vec<Response> {
         OngoingMatch::ComputerMatch(c) => c,
     };
 
-    let suggested_move = monte_carlo_ai::ai_move(
-        &bot_match_new.board,
-        search_depth_at_level(bot_match_new.ai_level),
-    )
-    .expect("AI failure :(");
+    let suggested_move = engine_at_level(bot_match_new.ai_level, &bot_match_new.board)
+        .best_move(&bot_match_new.board, &bot_match_new.move_history)
+        .or_else(|_| {
+            built_in_engine(bot_match_new.ai_level)
+                .best_move(&bot_match_new.board, &bot_match_new.move_history)
+        })
+        .expect("AI failure :(");
     bot_match_new.board.play_move(suggested_move);
+    bot_match_new.move_history.push(suggested_move);
 
     let responses = match bot_match_new.board.game_status() {
         GameStatus::GameOver(_) => {
-            persistency::delete_match(conn, match_id.0);
+            if !archive_ongoing_match(
+                conn,
+                &OngoingMatch::ComputerMatch(bot_match_new.clone()),
+                Termination::Normal,
+            ) {
+                return vec![];
+            }
             vec![Response::ShowGame(
                 OngoingMatch::ComputerMatch(bot_match_new),
                 true,
@@ -389,8 +1518,13 @@ fn play_bot_move(conn: &Connection, match_id: MatchId) -> Vec<Response> {
             )]
         }
         GameStatus::Turn(_) => {
-            persistency::update_match_board(conn, bot_match_new.match_id, &bot_match_new.board)
-                .expect("DB error when updating match");
+            persistency::update_match_board(
+                conn,
+                bot_match_new.match_id,
+                &bot_match_new.board,
+                &bot_match_new.move_history,
+            )
+            .expect("DB error when updating match");
             vec![Response::ShowGame(
                 OngoingMatch::ComputerMatch(bot_match_new),
                 true,
@@ -404,83 +1538,188 @@ fn play_bot_move(conn: &Connection, match_id: MatchId) -> Vec<Response> {
 
 pub fn communicate_responses(
     conn: &mut Connection,
-    discord: &Discord,
+    transport: &dyn ChatTransport,
     channel_id: ChannelId,
     responses: &Vec<Response>,
 ) {
     for r in responses {
-        communicate_response(conn, discord, channel_id, r);
+        communicate_response(conn, transport, channel_id, r);
     }
 }
 
 pub fn communicate_response(
     conn: &mut Connection,
-    discord: &Discord,
+    transport: &dyn ChatTransport,
     channel_id: ChannelId,
     response: &Response,
 ) {
     match response {
         Response::ShowGame(ongoing_match, prompt_player, last_move) => show_game(
             conn,
-            discord,
+            transport,
             channel_id,
             ongoing_match,
             *prompt_player,
             *last_move,
+            Termination::Normal,
         ),
-        Response::ShowHelp(_help_topic) => show_help(conn, discord, channel_id),
+        Response::ShowHelp(_help_topic) => show_help(conn, transport, channel_id),
         Response::ShowError(user_id, user_error) => {
-            show_error(conn, discord, channel_id, user_id, user_error)
+            show_error(conn, transport, channel_id, user_id, user_error)
         }
         Response::BotPlaysMove(match_id) => {
-            communicate_responses(conn, discord, channel_id, &play_bot_move(conn, *match_id));
+            communicate_responses(conn, transport, channel_id, &play_bot_move(conn, *match_id));
+        }
+        Response::OfferDraw(match_id, offering_player, offered_players) => show_offer(
+            conn,
+            transport,
+            channel_id,
+            *match_id,
+            offering_player,
+            offered_players,
+            InteractionKind::AcceptDraw,
+            &format!(
+                "{}, <@{}> offers a draw. React with {} or type `!c4 draw` to accept, \
+                or play a move to decline.",
+                mentions(offered_players),
+                offering_player.0,
+                DRAW_EMOJI
+            ),
+        ),
+        Response::OfferTakeback(match_id, requesting_player, asked_players, plies) => {
+            // on their own turn, the requesting player wants back your move
+            // too, and you can't move to refuse
+            let text = match (plies, asked_players.len()) {
+                (1, _) => format!(
+                    "{}, <@{}> would like to take back their last move. React with {} to \
+                    allow it, or play a move to refuse.",
+                    mentions(asked_players),
+                    requesting_player.0,
+                    TAKEBACK_EMOJI
+                ),
+                (2, 1) => format!(
+                    "{}, <@{}> would like to take back their last move and your answer to it. \
+                    React with {} to allow it. The request lapses once they play a move.",
+                    mentions(asked_players),
+                    requesting_player.0,
+                    TAKEBACK_EMOJI
+                ),
+                _ => format!(
+                    "{}, <@{}> would like to take back their last move and the moves played \
+                    since. React with {} to allow it. The request lapses with the next move.",
+                    mentions(asked_players),
+                    requesting_player.0,
+                    TAKEBACK_EMOJI
+                ),
+            };
+            show_offer(
+                conn,
+                transport,
+                channel_id,
+                *match_id,
+                requesting_player,
+                asked_players,
+                InteractionKind::ApproveTakeback,
+                &text,
+            )
+        }
+        Response::DrawDeclined(user_id) => {
+            transport
+                .send_message(
+                    channel_id,
+                    &format!("<@{}>, Connect4Bot declines your draw offer.", user_id.0),
+                )
+                .expect("failed to send message");
+        }
+        Response::ShowStats(user_id, record) => {
+            transport
+                .send_message(
+                    channel_id,
+                    &format!(
+                        "<@{}> has {} wins, {} losses and {} ties on this server.",
+                        user_id.0, record.wins, record.losses, record.ties
+                    ),
+                )
+                .expect("failed to send message");
+        }
+        Response::TimeWarning(user_id, deadline) => {
+            transport
+                .send_message(
+                    channel_id,
+                    &format!(
+                        "<@{}>, your time runs out <t:{}:R>. Play a move or you will lose the game!",
+                        user_id.0, deadline
+                    ),
+                )
+                .expect("failed to send message");
+        }
+        Response::NotifyEnd(user_ids, termination) => {
+            let reason = match termination {
+                Termination::Abandoned => "was abandoned after a week without moves",
+                Termination::Cancelled => "was cancelled by a moderator",
+                _ => "is over",
+            };
+            transport
+                .send_message(
+                    channel_id,
+                    &format!("{}, your Connect4 game {}.", mentions(user_ids), reason),
+                )
+                .expect("failed to send message");
+        }
+        Response::ShowGameOver(ongoing_match, termination) => {
+            show_game(
+                conn,
+                transport,
+                channel_id,
+                ongoing_match,
+                false,
+                None,
+                *termination,
+            );
+            if let Some(message_id) = ongoing_match.get_message_id() {
+                remove_column_reactions(transport, channel_id, MessageId(message_id));
+            }
         }
     };
 }
 
 fn show_game(
     conn: &mut Connection,
-    discord: &Discord,
+    transport: &dyn ChatTransport,
     channel_id: ChannelId,
     ongoing_match: &OngoingMatch,
     prompt_player: bool,
     last_move: Option<u8>,
+    termination: Termination,
 ) {
-    let server_id = match discord
-        .get_channel(channel_id)
-        .expect("failed to find channel")
-    {
-        Channel::Public(public_channel) => public_channel.server_id,
-        _ => panic!("game being played in a private chat"),
-    };
+    let server_id = ServerId(ongoing_match.get_guild_id());
 
-    let (player1, player2) = match ongoing_match {
-        OngoingMatch::HumanMatch(h) => {
-            let user1 = discord
-                .get_member(server_id, UserId(h.red_player_id))
-                .expect("failed to find member");
-            let user2 = discord
-                .get_member(server_id, UserId(h.blue_player_id))
-                .expect("failed to find member");
-            (
-                format!(":red_circle: {}", user1.display_name()),
-                format!(":blue_circle: {}", user2.display_name()),
-            )
-        }
+    let mut players: Vec<String> = match ongoing_match {
+        OngoingMatch::HumanMatch(h) => h
+            .player_ids()
+            .into_iter()
+            .zip(PLAYER_PIECES.iter())
+            .map(|(player_id, piece)| {
+                let user = transport
+                    .display_name(server_id, UserId(player_id))
+                    .expect("failed to find member");
+                format!("{} {}", piece, user)
+            })
+            .collect(),
         OngoingMatch::ComputerMatch(c) => {
-            let user = discord
-                .get_member(server_id, UserId(c.player_id))
+            let user = transport
+                .display_name(server_id, UserId(c.player_id))
                 .expect("failed to find member");
             if c.player_is_red {
-                (
-                    format!(":red_circle: {}", user.display_name()),
+                vec![
+                    format!(":red_circle: {}", user),
                     ":blue_circle: Connect4Bot".to_string(),
-                )
+                ]
             } else {
-                (
+                vec![
                     ":red_circle: Connect4Bot".to_string(),
-                    format!(":blue_circle: {}", user.display_name()),
-                )
+                    format!(":blue_circle: {}", user),
+                ]
             }
         }
     };
@@ -490,154 +1729,363 @@ fn show_game(
         OngoingMatch::ComputerMatch(c) => &c.board,
     };
 
-    let mut board_string = board.display(
-        ":red_circle:",
-        ":blue_circle:",
-        ":white_circle:",
-        "",
-        "",
-        "\n",
-        "",
+    // players who dropped out of a group game are struck through
+    for (player, name) in board.players().zip(players.iter_mut()) {
+        if !board.is_in_play(player) {
+            *name = format!("~~{}~~", name);
+        }
+    }
+
+    // the channel sees through the fog only once the game is over
+    let mut board_string = draw_board(board, None);
+
+    if let OngoingMatch::HumanMatch(h) = ongoing_match {
+        if let (GameStatus::Turn(_), Some(deadline)) = (board.game_status(), h.clock.deadline) {
+            board_string.push_str(&clock_description(&h.clock, board, deadline));
+        }
+    }
+
+    let (win_description, tie_description) = match termination {
+        Termination::Normal | Termination::Abandoned | Termination::Cancelled => ("", ""),
+        Termination::DrawAgreed => ("", "Draw agreed"),
+        Termination::Resignation => (" won by resignation", ""),
+        Termination::Timeout => (" won on time", ""),
+    };
+
+    let variant_name = match board.rules().variant {
+        Variant::Standard => "",
+        Variant::PopOut => " PopOut",
+        Variant::FiveInARow => " Five-in-a-Row",
+        Variant::Cylinder => " Cylinder",
+        Variant::FogOfWar => " Fog of War",
+    };
+    let game_name = format!(
+        "Connect{}{} game #{}",
+        board.line_length(),
+        variant_name,
+        ongoing_match.get_id()
     );
+    let in_progress_title = format!("{} in progress", game_name);
+    let game_over_title = format!("{} over!", game_name);
+    let ended_without_result = match termination {
+        Termination::Abandoned => Some(format!("{} abandoned", game_name)),
+        Termination::Cancelled => Some(format!("{} cancelled", game_name)),
+        _ => None,
+    };
 
-    board_string.push_str(":one::two::three::four::five::six::seven:");
-
-    // blue = 6cace9, red = ce4147
-    let embed_building_closure = |embed_builder: EmbedBuilder, b: &Board| match b.game_status() {
-        GameStatus::Turn(Player::Red) => embed_builder
-            .title("Connect4 game in progress")
-            .fields(|fields_builder: EmbedFieldsBuilder| {
-                fields_builder
-                    .field("Player one", &format!("**{}**", &player1), true)
-                    .field("Player two", &player2, true)
-                    .field("The board", &board_string, false)
-            })
-            .color(0xce4147),
-        GameStatus::Turn(Player::Blue) => embed_builder
-            .title("Connect4 game in progress")
-            .fields(|fields_builder: EmbedFieldsBuilder| {
-                fields_builder
-                    .field("Player one", &player1, true)
-                    .field("Player two", &format!("**{}**", &player2).to_string(), true)
-                    .field("The board", &board_string, false)
-            })
-            .color(0x6cace9),
-        GameStatus::GameOver(GameResult::Winner(Player::Red)) => embed_builder
-            .title("Connect4 game over!")
-            .fields(|fields_builder: EmbedFieldsBuilder| {
-                fields_builder
-                    .field("Winner", &player1, false)
-                    .field("Player one", &player1, true)
-                    .field("Player two", &player2, true)
-                    .field("The board", &board_string, false)
-            })
-            .color(0x00cc99),
-        GameStatus::GameOver(GameResult::Winner(Player::Blue)) => embed_builder
-            .title("Connect4 game over!")
-            .fields(|fields_builder: EmbedFieldsBuilder| {
-                fields_builder
-                    .field("Winner", &player2, false)
-                    .field("Player one", &player1, true)
-                    .field("Player two", &player2, true)
-                    .field("The board", &board_string, false)
-            })
-            .color(0x00cc99),
-        GameStatus::GameOver(GameResult::Tie) => embed_builder
-            .title("Connect4 game over!")
-            .fields(|fields_builder: EmbedFieldsBuilder| {
-                fields_builder
-                    .field("Tied game", "", false)
-                    .field("Player one", &player1, true)
-                    .field("Player two", &player2, true)
-                    .field("The board", &board_string, false)
-            })
-            .color(0xffff99),
+    let embed = match (ended_without_result, board.game_status()) {
+        (Some(title), _) => Embed::new(&title, 0x99aab5),
+        (None, GameStatus::Turn(player)) => {
+            let name = &mut players[player as usize];
+            *name = format!("**{}**", name);
+            Embed::new(&in_progress_title, PLAYER_COLORS[player as usize])
+        }
+        (None, GameStatus::GameOver(GameResult::Winner(player))) => {
+            Embed::new(&game_over_title, 0x00cc99).field(
+                "Winner",
+                &format!("{}{}", players[player as usize], win_description),
+                false,
+            )
+        }
+        (None, GameStatus::GameOver(GameResult::Tie)) => {
+            Embed::new(&game_over_title, 0xffff99).field("Tied game", tie_description, false)
+        }
     };
+    let embed = match players.as_slice() {
+        [player1, player2] => {
+            embed
+                .field("Player one", player1, true)
+                .field("Player two", player2, true)
+        }
+        _ => embed.field("Players", &players.join("\n"), false),
+    }
+    .field("The board", &board_string, false);
 
-    let message = match ongoing_match.get_message_id() {
-        Some(message_id) => discord
-            .edit_embed(channel_id, MessageId(message_id), |e| {
-                embed_building_closure(e, &board)
-            })
+    let message_id = match ongoing_match.get_message_id() {
+        Some(message_id) => transport
+            .edit_embed(channel_id, MessageId(message_id), &embed)
             .expect("failed to send embed"),
-        None => discord
-            .send_embed(channel_id, "", |e| embed_building_closure(e, &board))
+        None => transport
+            .send_embed(channel_id, &embed)
             .expect("failed to send embed"),
     };
 
+    // in the fog, the player to move is sent what they can see
+    if let (Variant::FogOfWar, GameStatus::Turn(player)) =
+        (board.rules().variant, board.game_status())
+    {
+        let player_id = match ongoing_match {
+            OngoingMatch::HumanMatch(h) => h.player_id(player),
+            OngoingMatch::ComputerMatch(c) => {
+                Some(c.player_id).filter(|_| c.player_is_red == (player == Player::Red))
+            }
+        };
+        if let Some(player_id) = player_id {
+            let view = Embed::new(&format!("Your view of {}", game_name), embed.color).field(
+                "The board",
+                &draw_board(board, Some(player)),
+                false,
+            );
+            if let Err(e) = transport.send_private_embed(UserId(player_id), &view) {
+                println!("got error: {:?}", e);
+            }
+        }
+    }
+
     if prompt_player {
         match last_move {
             None => {
-                for (i, emoji) in COLUMN_EMOJI.iter().enumerate() {
-                    if ongoing_match.get_board().is_move_legal(i as u8) {
-                        println!("attempting to react with {}", emoji);
-                        match discord.add_reaction(
-                            channel_id,
-                            message.id,
-                            ReactionEmoji::Unicode(emoji.to_string()),
-                        ) {
-                            Err(e) => println!("got error: {:?}", e),
-                            _ => {}
-                        };
+                for m in board.legal_moves() {
+                    let emoji = move_emoji(m);
+                    println!("attempting to react with {}", emoji);
+                    if let Err(e) = transport.add_reaction(channel_id, message_id, emoji) {
+                        println!("got error: {:?}", e);
                     }
                 }
             }
             Some(m) => {
-                let my_id = discord.get_current_user().expect("failed to find self").id;
-                let reaction_of_interest =
-                    ReactionEmoji::Unicode(COLUMN_EMOJI[m as usize].to_string());
-                let reactions = discord
-                    .get_reactions(
-                        channel_id,
-                        message.id,
-                        reaction_of_interest.clone(),
-                        None,
-                        None,
-                    )
+                let my_id = transport.current_user().expect("failed to find self");
+                let reaction_of_interest = move_emoji(m);
+                let reactions = transport
+                    .reacting_users(channel_id, message_id, reaction_of_interest)
                     .expect("Failed to retrieve reactions");
                 for u in reactions {
-                    if my_id.0 != u.id.0 {
-                        discord
-                            .delete_reaction(
-                                channel_id,
-                                message.id,
-                                Some(u.id),
-                                reaction_of_interest.clone(),
-                            )
+                    if my_id.0 != u.0 {
+                        transport
+                            .remove_reaction(channel_id, message_id, Some(u), reaction_of_interest)
                             .expect("failed to delete reaction");
                     }
                 }
-                if !ongoing_match.get_board().is_move_legal(m as u8) {
-                    discord
-                        .delete_reaction(channel_id, message.id, None, reaction_of_interest)
+                if board.rules().variant == Variant::PopOut {
+                    // a pop opens up its column again and changes whose
+                    // pieces can be popped, so every move is brought up to date
+                    for column in 0..board.size().width {
+                        for m in [column, board::pop_move(column)] {
+                            let emoji = move_emoji(m);
+                            let result = if board.is_move_legal(m) {
+                                transport.add_reaction(channel_id, message_id, emoji)
+                            } else {
+                                transport.remove_reaction(channel_id, message_id, None, emoji)
+                            };
+                            if let Err(e) = result {
+                                println!("got error: {:?}", e);
+                            }
+                        }
+                    }
+                } else if !board.is_move_legal(m) {
+                    transport
+                        .remove_reaction(channel_id, message_id, None, reaction_of_interest)
                         .expect("failed to add reaction");
                 }
             }
         };
 
-        persistency::register_interaction(conn, message.id.0, &ongoing_match);
+        persistency::register_interaction(conn, message_id.0, &ongoing_match);
+    }
+
+    if let GameStatus::GameOver(_) = board.game_status() {
+        // the match may not be archived yet, in which case there's nothing to offer
+        match persistency::register_rematch_offer(conn, message_id.0, ongoing_match.get_id()) {
+            Ok(()) => {
+                if let Err(e) = transport.add_reaction(channel_id, message_id, REMATCH_EMOJI) {
+                    println!("got error: {:?}", e);
+                }
+            }
+            Err(e) => println!("could not register rematch offer: {:?}", e),
+        }
     }
 }
 
-fn show_help(conn: &mut Connection, discord: &Discord, channel_id: ChannelId) {
-    discord
+// The board as the viewer sees it, with the column labels under it. The pieces
+// of the winning line are drawn as squares, and rows that go around the
+// cylinder end with arrows.
+fn draw_board(board: &Board, viewer: Option<Player>) -> String {
+    let line_end = match board.rules().variant {
+        Variant::Cylinder => ":left_right_arrow:\n",
+        _ => "\n",
+    };
+    let glyphs = Glyphs {
+        red: ":red_circle:",
+        blue: ":blue_circle:",
+        green: ":green_circle:",
+        yellow: ":yellow_circle:",
+        empty: ":white_circle:",
+        blocked: ":black_circle:",
+        hidden: ":fog:",
+        winning_red: ":red_square:",
+        winning_blue: ":blue_square:",
+        winning_green: ":green_square:",
+        winning_yellow: ":yellow_square:",
+    };
+    let mut board_string = board.display_with_winning_line(viewer, &glyphs, "", "", line_end, "");
+
+    board_string.push_str(&COLUMN_LABELS[..board.size().width as usize].concat());
+    if board.rules().variant == Variant::PopOut {
+        board_string.push('\n');
+        board_string.push_str(&POP_LABELS[..board.size().width as usize].concat());
+    }
+    board_string
+}
+
+fn move_emoji(m: u8) -> &'static str {
+    let column = board::move_column(m) as usize;
+    if board::is_pop(m) {
+        POP_EMOJI[column]
+    } else {
+        COLUMN_EMOJI[column]
+    }
+}
+
+// Blitz and rapid games show how much time each player has left
+fn clock_description(clock: &Clock, board: &Board, deadline: i64) -> String {
+    match clock.time_control {
+        TimeControl::Blitz | TimeControl::Rapid => {
+            let times: Vec<String> = board
+                .players()
+                .map(|player| {
+                    let time_left = clock.time_left(player);
+                    format!(
+                        "{} {}:{:02}",
+                        PLAYER_PIECES[player as usize],
+                        time_left / 60,
+                        time_left % 60
+                    )
+                })
+                .collect();
+            format!(
+                "\n:stopwatch: {}, move due <t:{}:R>",
+                times.join(" "),
+                deadline
+            )
+        }
+        _ => format!("\n:stopwatch: move due <t:{}:R>", deadline),
+    }
+}
+
+fn mentions(user_ids: &[UserId]) -> String {
+    let mentions: Vec<String> = user_ids.iter().map(|id| format!("<@{}>", id.0)).collect();
+    mentions.join(", ")
+}
+
+fn show_offer(
+    conn: &mut Connection,
+    transport: &dyn ChatTransport,
+    channel_id: ChannelId,
+    match_id: MatchId,
+    offering_player: &UserId,
+    offered_players: &[UserId],
+    kind: InteractionKind,
+    text: &str,
+) {
+    let emoji = match kind {
+        InteractionKind::AcceptDraw => DRAW_EMOJI,
+        InteractionKind::ApproveTakeback => TAKEBACK_EMOJI,
+        InteractionKind::PlayMove | InteractionKind::Rematch => {
+            panic!("moves and rematches are prompted by show_game")
+        }
+    };
+
+    let message_id = transport
+        .send_message(channel_id, text)
+        .expect("failed to send message");
+
+    if let Err(e) = transport.add_reaction(channel_id, message_id, emoji) {
+        println!("got error: {:?}", e);
+    }
+
+    let offered_player_ids: Vec<u64> = offered_players.iter().map(|id| id.0).collect();
+    persistency::register_offer(
+        conn,
+        message_id.0,
+        match_id.0,
+        offering_player.0,
+        &offered_player_ids,
+        kind,
+    )
+    .expect("DB error when registering offer");
+}
+
+fn remove_column_reactions(
+    transport: &dyn ChatTransport,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) {
+    for emoji in COLUMN_EMOJI.iter().chain(POP_EMOJI.iter()) {
+        if let Err(e) = transport.remove_reaction(channel_id, message_id, None, emoji) {
+            println!("got error: {:?}", e);
+        }
+    }
+}
+
+fn show_help(conn: &mut Connection, transport: &dyn ChatTransport, channel_id: ChannelId) {
+    transport
         .send_message(channel_id,
         "To play a match, try typing ```!c4 challenge @[tag-the-user-you-want-to play]```
-        You can also play against me with ```!c4 challenge @Connect4Bot```
-        If you keep seeing this message, it might be that you are already playing a game in this channel. Go finish that one first!You can only play one match at a time per channel.",
-         "", false)
+        You can also play against me with ```!c4 challenge @Connect4Bot``` Add level 1 to 5 to pick how well I play, like ```!c4 challenge @Connect4Bot level 3```
+        For a timed game, add blitz, rapid or correspondence after the player you challenge. To play on a bigger or smaller board, add its width and height, like ```!c4 challenge @[player] 9x7``` Boards go from 4x4 to 9x8. To win with 3 to 6 in a row instead of four, add connect and the number, like ```!c4 challenge @[player] 9x7 connect 5``` Add popout to let players pop one of their own pieces out of the bottom row instead of dropping one, with the lettered reactions or ```!c4 pop 4``` Add fiveinarow to connect 5 on a 9x6 board whose side columns start filled, or cylinder to let lines run off one side of the board and carry on from the other. Add blockers 3 to start with neutral pieces in random columns, or blocked 2,6 to pick them. Add from and some moves, like from 4453, to start after them, or handicap 1 to 3 to give the player you challenge, or yourself against me, pieces to start with. Add fog to only see the slots around your own pieces, sent to you privately, until the game ends. Mention two or three players to play with up to four at once, in any variant but popout and fiveinarow.
+        To give up the game, type ```!c4 resign``` To see your wins and losses, type ```!c4 stats```
+        Moderators can end a stuck game with ```!c4 admin cancel @[player]``` Games with no moves for a week are ended automatically.
+        To offer your opponent a draw, type ```!c4 draw``` To take back your last move, type ```!c4 undo``` Offers lapse after the next move. When a game is over, react with 🔁 to play a rematch.
+        You can play several games at once, but only one with each opponent. With more than one going, add the game number to your command, like ```!c4 play 4 #12``` In games of more than two, draws and takebacks need everyone still playing, and whoever resigns drops out while the others play on.")
         .expect("failed to send message");
 }
 
 fn show_error(
     conn: &mut Connection,
-    discord: &Discord,
+    transport: &dyn ChatTransport,
     channel_id: ChannelId,
     user_id: &UserId,
     user_error: &UserError,
 ) {
-    discord
-        .send_message(channel_id, "Help message", "", false)
+    let text = match user_error {
+        UserError::SeveralMatches(match_ids) => {
+            let numbers: Vec<String> = match_ids.iter().map(|id| format!("#{}", id.0)).collect();
+            format!(
+                "<@{}>, you are playing games {} in this channel. \
+                Add the number of the game to your command, like `!c4 play 4 {}`.",
+                user_id.0,
+                numbers.join(", "),
+                numbers.first().map(String::as_str).unwrap_or("#1")
+            )
+        }
+        UserError::NoSuchMatch => format!(
+            "<@{}>, you are not playing a game with that number in this channel.",
+            user_id.0
+        ),
+        UserError::CorruptedMatch(match_id) => format!(
+            "<@{}>, game #{} can't be played, its board got damaged. \
+            A moderator can remove it with `!c4 admin cancel @player #{}`.",
+            user_id.0, match_id.0, match_id.0
+        ),
+        UserError::NoRematchOffered => {
+            format!("<@{}>, that rematch isn't on offer to you.", user_id.0)
+        }
+        UserError::InvalidOpening(error) => format!(
+            "<@{}>, the game can't start from those moves: {}.",
+            user_id.0, error
+        ),
+        UserError::FinishedOpening => format!(
+            "<@{}>, those moves end the game before it starts.",
+            user_id.0
+        ),
+        UserError::NotAModerator => format!(
+            "<@{}>, only members who can manage messages can do that.",
+            user_id.0
+        ),
+        UserError::PlayerAlreadyPlaying => format!(
+            "<@{}>, a player in that game is already in one like it in this channel.",
+            user_id.0
+        ),
+        UserError::PlayerNotPlaying => format!(
+            "<@{}>, you are not playing a game in this channel.",
+            user_id.0
+        ),
+        UserError::NotYourTurn => format!("<@{}>, it's not your turn.", user_id.0),
+        UserError::IllegalMove => format!("<@{}>, that move can't be played.", user_id.0),
+        UserError::NothingToTakeBack => format!("<@{}>, you have no move to take back.", user_id.0),
+    };
+    transport
+        .send_message(channel_id, &text)
         .expect("failed to send message");
 }
 
@@ -654,8 +2102,7 @@ fn check_player_turn_vs_bot(
 
 fn check_player_turn_vs_human(human_match: &persistency::HumanMatch, player_id: UserId) -> bool {
     match human_match.board.game_status() {
-        GameStatus::Turn(Player::Red) => player_id.0 == human_match.red_player_id,
-        GameStatus::Turn(Player::Blue) => player_id.0 == human_match.blue_player_id,
+        GameStatus::Turn(player) => human_match.player_id(player) == Some(player_id.0),
         _ => false,
     }
 }
@@ -673,6 +2120,34 @@ fn decide_random_order(play_order: PlayOrder) -> PlayOrder {
     }
 }
 
-fn search_depth_at_level(ai_level: u8) -> u32 {
-    32768
+// Makes the given engine play for the bot at the given level, for the rest of
+// the process. Only one external engine can be registered.
+pub fn register_external_engine(ai_level: u8, engine: ExternalEngine) -> Result<(), ()> {
+    EXTERNAL_ENGINE.set((ai_level, engine)).map_err(|_| ())
+}
+
+// If an external engine fails, the built-in one at the same level takes over.
+// External engines only get standard games from the empty board, which their
+// protocol assumes.
+fn engine_at_level(ai_level: u8, board: &Board) -> Box<dyn Engine> {
+    match EXTERNAL_ENGINE.get() {
+        Some((level, engine))
+            if *level == ai_level
+                && board.rules() == Rules::STANDARD
+                && board.blocked() == 0
+                && board.preset() == 0 =>
+        {
+            Box::new(engine.clone())
+        }
+        _ => Box::new(built_in_engine(ai_level)),
+    }
+}
+
+// Levels above the ones players can ask for belong to an external engine, and
+// the built-in one stands in for it at its own highest level
+fn built_in_engine(ai_level: u8) -> MonteCarlo {
+    MonteCarlo::at_level(ai_level.min(MAX_CHAT_LEVEL))
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,1140 @@
+use super::*;
+use crate::transport::fake::FakeTransport;
+use std::fs;
+
+const BOT: UserId = UserId(1);
+const GUILD: ServerId = ServerId(100);
+const CHANNEL: ChannelId = ChannelId(200);
+
+fn say(
+    conn: &mut Connection,
+    transport: &FakeTransport,
+    author: u64,
+    content: &str,
+    mentions: &[u64],
+) {
+    let mentions: Vec<UserId> = mentions.iter().map(|id| UserId(*id)).collect();
+    let request = authorize_request(
+        transport,
+        GUILD,
+        parse_command(content, GUILD, CHANNEL, UserId(author), &mentions, &BOT),
+    );
+    let responses = process_request(conn, &request);
+    communicate_responses(conn, transport, CHANNEL, &responses);
+}
+
+fn react(
+    conn: &mut Connection,
+    transport: &FakeTransport,
+    user: u64,
+    message_id: MessageId,
+    emoji: &str,
+) {
+    transport.react(message_id, UserId(user), emoji);
+    let request = parse_reaction_emoji(UserId(user), message_id, emoji);
+    let responses = process_request(conn, &request);
+    communicate_responses(conn, transport, CHANNEL, &responses);
+}
+
+#[test]
+fn a_game_played_to_the_end() {
+    let _ = fs::remove_file("protocol_test1.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test1.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 game #1 in progress");
+    assert_eq!(game.embed().fields[0].value, "**:red_circle: player12**");
+    for emoji in COLUMN_EMOJI[..7].iter() {
+        assert!(game.has_reaction(emoji, BOT));
+    }
+    assert!(!game.has_reaction(COLUMN_EMOJI[7], BOT));
+
+    // red plays by reacting and blue by typing, until red connects four in
+    // the first column
+    for _ in 0..3 {
+        react(&mut conn, &transport, 12, game.id, COLUMN_EMOJI[0]);
+        say(&mut conn, &transport, 21, "!c4 play 2", &[]);
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #1 in progress");
+    assert!(!game.has_reaction(COLUMN_EMOJI[0], UserId(12)));
+    assert!(game.has_reaction(COLUMN_EMOJI[0], BOT));
+
+    // it's not blue's turn, so the game stays as it was
+    say(&mut conn, &transport, 21, "!c4 play 2", &[]);
+    assert_eq!(transport.message(game.id), game);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@21>, it's not your turn."
+    );
+
+    react(&mut conn, &transport, 12, game.id, COLUMN_EMOJI[0]);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #1 over!");
+    assert_eq!(game.embed().fields[0].value, ":red_circle: player12");
+    assert_eq!(
+        game.embed().fields[3].value.matches(":red_square:").count(),
+        4
+    );
+    assert!(game.has_reaction(REMATCH_EMOJI, BOT));
+
+    // both players saw a single board, updated in place
+    let embeds = transport
+        .messages()
+        .iter()
+        .filter(|m| m.channel_id == CHANNEL)
+        .filter(|m| m.reactions.iter().any(|(e, _)| e == REMATCH_EMOJI))
+        .count();
+    assert_eq!(embeds, 1);
+
+    say(&mut conn, &transport, 12, "!c4 stats", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12> has 1 wins, 0 losses and 0 ties on this server."
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test1.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn draw_offered_and_accepted() {
+    let _ = fs::remove_file("protocol_test2.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test2.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    let game = transport.last_message();
+    // out of turn, so the reaction is left on the board
+    react(&mut conn, &transport, 21, game.id, COLUMN_EMOJI[3]);
+
+    say(&mut conn, &transport, 12, "!c4 draw", &[]);
+    let offer = transport.last_message();
+    assert!(offer.text().starts_with("<@21>, <@12> offers a draw."));
+    assert!(offer.has_reaction(DRAW_EMOJI, BOT));
+
+    // only the offered player can accept
+    react(&mut conn, &transport, 12, offer.id, DRAW_EMOJI);
+    assert_eq!(
+        transport.message(game.id).embed().title,
+        "Connect4 game #1 in progress"
+    );
+
+    react(&mut conn, &transport, 21, offer.id, DRAW_EMOJI);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #1 over!");
+    assert_eq!(game.embed().fields[0].name, "Tied game");
+    assert_eq!(game.embed().fields[0].value, "Draw agreed");
+    assert!(!game.has_reaction(COLUMN_EMOJI[3], BOT));
+    // the bot can only take its own reactions off
+    assert!(game.has_reaction(COLUMN_EMOJI[3], UserId(21)));
+
+    drop(conn);
+    fs::remove_file("protocol_test2.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn takebacks_offered_and_approved() {
+    let _ = fs::remove_file("protocol_test16.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test16.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    let game = transport.last_message();
+    say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+
+    // on blue's turn, red only takes back its own move
+    say(&mut conn, &transport, 12, "!c4 undo", &[]);
+    let offer = transport.last_message();
+    assert_eq!(
+        offer.text(),
+        "<@21>, <@12> would like to take back their last move. React with ↩️ to allow it, \
+        or play a move to refuse."
+    );
+    react(&mut conn, &transport, 21, offer.id, TAKEBACK_EMOJI);
+    assert_eq!(
+        transport.message(game.id).embed().fields[0].value,
+        "**:red_circle: player12**"
+    );
+
+    // on its own turn, red asks for blue's answer back as well
+    say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+    say(&mut conn, &transport, 21, "!c4 play 3", &[]);
+    say(&mut conn, &transport, 12, "!c4 undo", &[]);
+    let offer = transport.last_message();
+    assert_eq!(
+        offer.text(),
+        "<@21>, <@12> would like to take back their last move and your answer to it. \
+        React with ↩️ to allow it. The request lapses once they play a move."
+    );
+    react(&mut conn, &transport, 21, offer.id, TAKEBACK_EMOJI);
+    say(&mut conn, &transport, 21, "!c4 play 1", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@21>, it's not your turn."
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test16.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn moderators_cancel_games() {
+    let _ = fs::remove_file("protocol_test3.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test3.sqlite").expect("Failed to create database");
+    let mut transport = FakeTransport::new(BOT, GUILD);
+    transport.add_moderator(UserId(50));
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    let game = transport.last_message();
+
+    say(
+        &mut conn,
+        &transport,
+        60,
+        "!c4 admin cancel @player12",
+        &[12],
+    );
+    assert_eq!(
+        transport.last_message().text(),
+        "<@60>, only members who can manage messages can do that."
+    );
+
+    say(
+        &mut conn,
+        &transport,
+        50,
+        "!c4 admin cancel @player12",
+        &[12],
+    );
+    assert_eq!(
+        transport.message(game.id).embed().title,
+        "Connect4 game #1 cancelled"
+    );
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12>, <@21>, your Connect4 game was cancelled by a moderator."
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test3.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn games_are_picked_by_number() {
+    let _ = fs::remove_file("protocol_test4.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test4.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    let first_game = transport.last_message();
+    say(&mut conn, &transport, 12, "!c4 challenge @player22", &[22]);
+    let second_game = transport.last_message();
+
+    say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12>, you are playing games #1, #2 in this channel. \
+        Add the number of the game to your command, like `!c4 play 4 #1`."
+    );
+
+    say(&mut conn, &transport, 12, "!c4 play 4 #2", &[]);
+    assert_eq!(transport.message(first_game.id), first_game);
+    assert_eq!(
+        transport.message(second_game.id).embed().fields[1].value,
+        "**:blue_circle: player22**"
+    );
+
+    // player 21 only has one game, so needs no number
+    say(&mut conn, &transport, 21, "!c4 resign", &[]);
+    let first_game = transport.message(first_game.id);
+    assert_eq!(first_game.embed().title, "Connect4 game #1 over!");
+    assert_eq!(
+        first_game.embed().fields[0].value,
+        ":red_circle: player12 won by resignation"
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test4.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn external_engines_play_for_the_bot() {
+    let _ = fs::remove_file("protocol_test5.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test5.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    // an engine that always plays in the first column
+    let engine = ExternalEngine::new(
+        "sh",
+        &[
+            "-c".to_string(),
+            "while read moves; do echo 1; done".to_string(),
+        ],
+    );
+    register_external_engine(10, engine).expect("no other engine was registered");
+
+    // players can only ask the built-in bot for the cheaper levels
+    assert_eq!(parse_ai_level("!c4 challenge @bot level 3"), 3);
+    assert_eq!(parse_ai_level("!c4 challenge @bot level 8"), MAX_CHAT_LEVEL);
+    assert_eq!(parse_ai_level("!c4 challenge @bot level 10"), 10);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @Connect4Bot level 10",
+        &[1],
+    );
+    let ongoing_match = persistency::retrieve_match_by_player(&conn, CHANNEL.0, 12, None)
+        .expect("the game should have started");
+    if ongoing_match.get_move_history().is_empty() {
+        say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+    }
+
+    let ongoing_match = persistency::retrieve_match_by_player(&conn, CHANNEL.0, 12, None)
+        .expect("the game should go on");
+    match ongoing_match {
+        OngoingMatch::ComputerMatch(c) => {
+            assert_eq!(c.ai_level, 10);
+            assert!(c.move_history.contains(&0));
+        }
+        OngoingMatch::HumanMatch(_) => panic!("expected a game against the bot"),
+    }
+
+    drop(conn);
+    fs::remove_file("protocol_test5.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn corrupted_games_are_reported() {
+    let _ = fs::remove_file("protocol_test6.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test6.sqlite").expect("Failed to create database");
+    let mut transport = FakeTransport::new(BOT, GUILD);
+    transport.add_moderator(UserId(50));
+
+    say(&mut conn, &transport, 12, "!c4 challenge @player21", &[21]);
+    conn.execute(
+        "UPDATE matches SET red_pieces = 1, blue_pieces = 1;",
+        rusqlite::NO_PARAMS,
+    )
+    .expect("failed to corrupt the board");
+
+    say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12>, game #1 can't be played, its board got damaged. \
+        A moderator can remove it with `!c4 admin cancel @player #1`."
+    );
+
+    say(
+        &mut conn,
+        &transport,
+        50,
+        "!c4 admin cancel @player12",
+        &[12],
+    );
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12>, your Connect4 game was cancelled by a moderator."
+    );
+    // the players are free to meet again
+    say(&mut conn, &transport, 21, "!c4 challenge @player12", &[12]);
+    assert!(transport
+        .last_message()
+        .embed()
+        .title
+        .ends_with("in progress"));
+
+    drop(conn);
+    fs::remove_file("protocol_test6.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn games_on_other_board_sizes() {
+    let _ = fs::remove_file("protocol_test7.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test7.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 9x7",
+        &[21],
+    );
+    let game = transport.last_message();
+    for emoji in COLUMN_EMOJI.iter() {
+        assert!(game.has_reaction(emoji, BOT));
+    }
+    assert!(game.embed().fields[2]
+        .value
+        .ends_with(":seven::eight::nine:"));
+
+    // the ninth column only exists on the wide board
+    say(&mut conn, &transport, 12, "!c4 play 9", &[]);
+    let ongoing_match = persistency::retrieve_match_by_player(&conn, CHANNEL.0, 12, None)
+        .expect("the game should go on");
+    assert_eq!(
+        ongoing_match.get_board().size(),
+        BoardSize {
+            width: 9,
+            height: 7
+        }
+    );
+    assert_eq!(ongoing_match.get_move_history(), &[8]);
+
+    // the bot plays on small boards too
+    say(
+        &mut conn,
+        &transport,
+        13,
+        "!c4 challenge @Connect4Bot level 1 6x5",
+        &[1],
+    );
+    say(&mut conn, &transport, 13, "!c4 play 1", &[]);
+    let ongoing_match = persistency::retrieve_match_by_player(&conn, CHANNEL.0, 13, None)
+        .expect("the game should go on");
+    assert_eq!(
+        ongoing_match.get_board().size(),
+        BoardSize {
+            width: 6,
+            height: 5
+        }
+    );
+    // whoever went first, the bot has answered the player's move
+    assert!(ongoing_match.get_move_history().len() >= 2);
+    assert!(ongoing_match
+        .get_move_history()
+        .iter()
+        .all(|&column| column < 6));
+
+    drop(conn);
+    fs::remove_file("protocol_test7.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn games_with_longer_lines() {
+    let _ = fs::remove_file("protocol_test8.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test8.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 9x7 connect 5",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect5 game #1 in progress");
+
+    // four in a row along the bottom doesn't end the game, five does
+    for (i, column) in [1, 1, 2, 2, 3, 3, 4, 4].iter().enumerate() {
+        let player = if i % 2 == 0 { 12 } else { 21 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    assert_eq!(
+        transport.last_message().embed().title,
+        "Connect5 game #1 in progress"
+    );
+    say(&mut conn, &transport, 12, "!c4 play 5", &[]);
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect5 game #1 over!");
+    assert!(game.embed().fields[3]
+        .value
+        .starts_with(&":white_circle:".repeat(9)));
+    assert_eq!(
+        game.embed().fields[3].value.matches(":red_square:").count(),
+        5
+    );
+
+    // lines that can't fit on the board are left at four
+    assert_eq!(
+        parse_command(
+            "!c4 challenge @player21 4x4 connect 6",
+            GUILD,
+            CHANNEL,
+            UserId(12),
+            &[UserId(21)],
+            &BOT
+        ),
+        Request::Challenge(
+            GUILD,
+            CHANNEL,
+            UserId(12),
+            UserId(21),
+            PlayOrder::GoFirst,
+            TimeControl::Untimed,
+            Rules {
+                size: BoardSize::new(4, 4).unwrap(),
+                ..Rules::STANDARD
+            },
+            Blockers::None,
+            Opening::None
+        )
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test8.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn popout_games() {
+    let _ = fs::remove_file("protocol_test9.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test9.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 popout",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 PopOut game #1 in progress");
+    assert!(game.embed().fields[2]
+        .value
+        .ends_with(&POP_LABELS[..7].concat()));
+    assert!(!game.has_reaction(POP_EMOJI[0], BOT));
+
+    // only the player to move can pop, and only their own pieces
+    say(&mut conn, &transport, 12, "!c4 play 1", &[]);
+    say(&mut conn, &transport, 21, "!c4 play 2", &[]);
+    let game = transport.message(game.id);
+    assert!(game.has_reaction(POP_EMOJI[0], BOT));
+    assert!(!game.has_reaction(POP_EMOJI[1], BOT));
+    say(&mut conn, &transport, 12, "!c4 play 2", &[]);
+    let game = transport.message(game.id);
+    assert!(!game.has_reaction(POP_EMOJI[0], BOT));
+    assert!(game.has_reaction(POP_EMOJI[1], BOT));
+    say(&mut conn, &transport, 21, "!c4 pop 1", &[]);
+    assert_eq!(transport.message(game.id), game);
+
+    for (i, column) in [3, 3, 4, 4, 1, 1, 7].iter().enumerate() {
+        let player = if i % 2 == 0 { 21 } else { 12 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+
+    // popping red's piece out of the first column brings down lines for both
+    // players, and red wins for popping
+    react(&mut conn, &transport, 12, game.id, POP_EMOJI[0]);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 PopOut game #1 over!");
+    assert_eq!(game.embed().fields[0].value, ":red_circle: player12");
+    assert_eq!(
+        game.embed().fields[3].value.matches(":red_square:").count(),
+        4
+    );
+    assert!(!game.has_reaction(POP_EMOJI[1], BOT));
+
+    drop(conn);
+    fs::remove_file("protocol_test9.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn five_in_a_row_games() {
+    let _ = fs::remove_file("protocol_test10.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test10.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 fiveinarow",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(
+        game.embed().title,
+        "Connect5 Five-in-a-Row game #1 in progress"
+    );
+    // the side columns start full
+    assert!(!game.has_reaction(COLUMN_EMOJI[0], BOT));
+    assert!(game.has_reaction(COLUMN_EMOJI[1], BOT));
+    assert!(!game.has_reaction(COLUMN_EMOJI[8], BOT));
+
+    // the variant's size and line length give way to the ones asked for
+    assert_eq!(
+        parse_rules("!c4 challenge @player21 popout 8x7 connect 5"),
+        Rules {
+            size: BoardSize::new(8, 7).unwrap(),
+            line_length: 5,
+            variant: Variant::PopOut,
+            players: 2
+        }
+    );
+    assert_eq!(
+        parse_rules("!c4 challenge @player21 fiveinarow 4x4"),
+        Rules {
+            size: BoardSize::new(4, 4).unwrap(),
+            line_length: 4,
+            variant: Variant::FiveInARow,
+            players: 2
+        }
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test10.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn cylinder_games() {
+    let _ = fs::remove_file("protocol_test11.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test11.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 cylinder",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 Cylinder game #1 in progress");
+    assert_eq!(
+        game.embed().fields[2]
+            .value
+            .matches(":left_right_arrow:")
+            .count(),
+        6
+    );
+
+    // red connects four from the right side around to the left
+    for (i, column) in [6, 6, 7, 7, 1, 1, 2].iter().enumerate() {
+        let player = if i % 2 == 0 { 12 } else { 21 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 Cylinder game #1 over!");
+    assert_eq!(
+        game.embed().fields[3].value.matches(":red_square:").count(),
+        4
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test11.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn games_with_blockers() {
+    let _ = fs::remove_file("protocol_test12.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test12.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    assert_eq!(
+        parse_blockers("!c4 challenge @player21 blockers 12"),
+        Blockers::Random(Board::MAX_BLOCKERS)
+    );
+    assert_eq!(
+        parse_blockers("!c4 challenge @player21 blocked 2,x,0,6"),
+        Blockers::InColumns(vec![1, 5])
+    );
+    assert_eq!(
+        parse_blockers("!c4 challenge @player21 blockers none"),
+        Blockers::None
+    );
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 blocked 4,4,9",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(
+        game.embed().fields[2]
+            .value
+            .matches(":black_circle:")
+            .count(),
+        2
+    );
+
+    // pieces land on the blockers, and the column fills up sooner
+    for (i, column) in [4, 4, 4, 4].iter().enumerate() {
+        let player = if i % 2 == 0 { 12 } else { 21 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    let game = transport.message(game.id);
+    assert!(!game.has_reaction(COLUMN_EMOJI[3], BOT));
+
+    // a rematch starts from the same blockers
+    say(&mut conn, &transport, 12, "!c4 resign", &[]);
+    let game = transport.message(game.id);
+    assert!(game.has_reaction(REMATCH_EMOJI, BOT));
+    react(&mut conn, &transport, 21, game.id, REMATCH_EMOJI);
+    let rematch = transport.last_message();
+    assert_ne!(rematch.id, game.id);
+    assert_eq!(
+        rematch.embed().fields[2]
+            .value
+            .matches(":black_circle:")
+            .count(),
+        2
+    );
+
+    // the bot plays around random blockers
+    say(
+        &mut conn,
+        &transport,
+        13,
+        "!c4 challenge @bot level 1 blockers 3",
+        &[BOT.0],
+    );
+    let game = transport.last_message();
+    assert_eq!(
+        game.embed().fields[2]
+            .value
+            .matches(":black_circle:")
+            .count(),
+        3
+    );
+
+    // a tie on a blocked board can be rematched, though red filled the board
+    say(
+        &mut conn,
+        &transport,
+        14,
+        "!c4 challenge @player41 blocked 4",
+        &[41],
+    );
+    let game = transport.last_message();
+    for (i, column) in "54361611437144646653723565123357221277752"
+        .chars()
+        .enumerate()
+    {
+        let player = if i % 2 == 0 { 14 } else { 41 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #4 over!");
+    assert!(game.has_reaction(REMATCH_EMOJI, BOT));
+    react(&mut conn, &transport, 15, game.id, REMATCH_EMOJI);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@15>, that rematch isn't on offer to you."
+    );
+    react(&mut conn, &transport, 41, game.id, REMATCH_EMOJI);
+    let rematch = transport.last_message();
+    assert_ne!(rematch.id, game.id);
+    assert_eq!(rematch.embed().title, "Connect4 game #5 in progress");
+
+    drop(conn);
+    fs::remove_file("protocol_test12.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn fog_of_war_games() {
+    let _ = fs::remove_file("protocol_test13.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test13.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 fog",
+        &[21],
+    );
+    let game = transport
+        .messages()
+        .into_iter()
+        .find(|m| m.channel_id == CHANNEL)
+        .expect("the game was shown");
+    assert_eq!(
+        game.embed().title,
+        "Connect4 Fog of War game #1 in progress"
+    );
+    assert_eq!(transport.private_messages(UserId(12)).len(), 1);
+    assert!(transport.private_messages(UserId(21)).is_empty());
+
+    // the channel sees nothing, and each player sees around their own pieces
+    for (i, column) in [1, 7, 1].iter().enumerate() {
+        let player = if i % 2 == 0 { 12 } else { 21 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().fields[2].value.matches(":fog:").count(), 42);
+    let view = transport
+        .private_messages(UserId(21))
+        .last()
+        .expect("blue was sent a view")
+        .clone();
+    assert_eq!(
+        view.embed().title,
+        "Your view of Connect4 Fog of War game #1"
+    );
+    assert_eq!(view.embed().fields[0].value.matches(":fog:").count(), 38);
+    assert!(!view.embed().fields[0].value.contains(":red_circle:"));
+
+    // the board is revealed when the game ends
+    for (i, column) in [7, 1, 7, 1].iter().enumerate() {
+        let player = if i % 2 == 0 { 21 } else { 12 };
+        say(
+            &mut conn,
+            &transport,
+            player,
+            &format!("!c4 play {}", column),
+            &[],
+        );
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 Fog of War game #1 over!");
+    let board = &game.embed().fields[3].value;
+    assert!(!board.contains(":fog:"));
+    assert_eq!(board.matches(":red_square:").count(), 4);
+    assert_eq!(board.matches(":blue_circle:").count(), 3);
+
+    drop(conn);
+    fs::remove_file("protocol_test13.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn group_games() {
+    let _ = fs::remove_file("protocol_test14.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test14.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    // four others make too many players
+    assert_eq!(
+        parse_command(
+            "!c4 challenge @a @b @c @d",
+            GUILD,
+            CHANNEL,
+            UserId(12),
+            &[UserId(21), UserId(31), UserId(41), UserId(51)],
+            &BOT,
+        ),
+        Request::Help(HelpTopic::Challenge)
+    );
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 @player31",
+        &[21, 31],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 game #1 in progress");
+    assert_eq!(
+        game.embed().fields[0].value,
+        "**:red_circle: player12**\n:blue_circle: player21\n:green_circle: player31"
+    );
+    for emoji in COLUMN_EMOJI.iter() {
+        assert!(game.has_reaction(emoji, BOT));
+    }
+
+    // the third player has to wait for the other two
+    say(&mut conn, &transport, 31, "!c4 play 3", &[]);
+    assert_eq!(transport.message(game.id).embed(), game.embed());
+
+    // the first player connects four in the first column, playing by
+    // reacting, while the others play by typing
+    for _ in 0..3 {
+        react(&mut conn, &transport, 12, game.id, COLUMN_EMOJI[0]);
+        say(&mut conn, &transport, 21, "!c4 play 2", &[]);
+        say(&mut conn, &transport, 31, "!c4 play 3", &[]);
+    }
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().color, 0xce4147);
+    assert!(!game.has_reaction(COLUMN_EMOJI[0], UserId(12)));
+    react(&mut conn, &transport, 12, game.id, COLUMN_EMOJI[0]);
+
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #1 over!");
+    assert_eq!(game.embed().fields[0].value, ":red_circle: player12");
+    let board = &game.embed().fields[2].value;
+    assert_eq!(board.matches(":red_square:").count(), 4);
+    assert_eq!(board.matches(":green_circle:").count(), 3);
+
+    // once it's over, the players are free to start another, numbered like
+    // any other game
+    say(&mut conn, &transport, 21, "!c4 play 2", &[]);
+    assert_eq!(transport.message(game.id).embed(), game.embed());
+    say(
+        &mut conn,
+        &transport,
+        21,
+        "!c4 challenge @player12 @player31 @player41",
+        &[12, 31, 41],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 game #2 in progress");
+    assert_eq!(game.embed().fields[1].value.lines().count(), 9);
+    say(&mut conn, &transport, 21, "!c4 challenge @player51", &[51]);
+    let other_game = transport.last_message();
+    assert_eq!(other_game.embed().title, "Connect4 game #3 in progress");
+    say(&mut conn, &transport, 21, "!c4 play 4", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@21>, you are playing games #2, #3 in this channel. \
+        Add the number of the game to your command, like `!c4 play 4 #2`."
+    );
+    say(&mut conn, &transport, 21, "!c4 play 4 #2", &[]);
+    say(&mut conn, &transport, 12, "!c4 play 4", &[]);
+
+    // a takeback needs everyone else's approval
+    say(&mut conn, &transport, 12, "!c4 undo", &[]);
+    let offer = transport.last_message();
+    assert_eq!(
+        offer.text(),
+        "<@21>, <@31>, <@41>, <@12> would like to take back their last move. \
+        React with ↩️ to allow it, or play a move to refuse."
+    );
+    react(&mut conn, &transport, 21, offer.id, TAKEBACK_EMOJI);
+    react(&mut conn, &transport, 31, offer.id, TAKEBACK_EMOJI);
+    let board = transport.message(game.id).embed().fields[1].value.clone();
+    assert_eq!(board.matches(":blue_circle:").count(), 1);
+    react(&mut conn, &transport, 41, offer.id, TAKEBACK_EMOJI);
+    let game = transport.message(game.id);
+    assert_eq!(
+        game.embed().fields[1]
+            .value
+            .matches(":blue_circle:")
+            .count(),
+        0
+    );
+    assert_eq!(
+        game.embed().fields[0].value,
+        ":red_circle: player21\n**:blue_circle: player12**\n:green_circle: player31\n\
+        :yellow_circle: player41"
+    );
+    say(&mut conn, &transport, 12, "!c4 play 5", &[]);
+
+    // and so does a draw, which lapses with the next move
+    say(&mut conn, &transport, 31, "!c4 draw", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@21>, <@12>, <@41>, <@31> offers a draw. React with 🤝 or type `!c4 draw` to \
+        accept, or play a move to decline."
+    );
+    say(&mut conn, &transport, 31, "!c4 play 6", &[]);
+    say(&mut conn, &transport, 41, "!c4 draw", &[]);
+    react(
+        &mut conn,
+        &transport,
+        21,
+        transport.last_message().id,
+        DRAW_EMOJI,
+    );
+    say(&mut conn, &transport, 12, "!c4 draw", &[]);
+    let offer = transport.last_message();
+    assert_eq!(
+        offer.text(),
+        "<@31>, <@12> offers a draw. React with 🤝 or type `!c4 draw` to accept, \
+        or play a move to decline."
+    );
+    react(&mut conn, &transport, 31, offer.id, DRAW_EMOJI);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #2 over!");
+    assert_eq!(game.embed().fields[0].name, "Tied game");
+    assert_eq!(game.embed().fields[0].value, "Draw agreed");
+
+    // a player who resigns drops out, and the others play on without them
+    say(
+        &mut conn,
+        &transport,
+        31,
+        "!c4 challenge @player41 @player12",
+        &[41, 12],
+    );
+    let game = transport.last_message();
+    say(&mut conn, &transport, 41, "!c4 resign", &[]);
+    assert_eq!(
+        transport.message(game.id).embed().fields[0].value,
+        "**:red_circle: player31**\n~~:blue_circle: player41~~\n:green_circle: player12"
+    );
+    say(&mut conn, &transport, 31, "!c4 play 1", &[]);
+    assert_eq!(
+        transport.message(game.id).embed().fields[0].value,
+        ":red_circle: player31\n~~:blue_circle: player41~~\n**:green_circle: player12**"
+    );
+    // until only one is left, who wins
+    say(&mut conn, &transport, 12, "!c4 resign", &[]);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().title, "Connect4 game #4 over!");
+    assert_eq!(
+        game.embed().fields[0].value,
+        ":red_circle: player31 won by resignation"
+    );
+
+    // group games count in the stats, and dropping out of one loses it
+    say(&mut conn, &transport, 12, "!c4 stats", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@12> has 1 wins, 1 losses and 1 ties on this server."
+    );
+    say(&mut conn, &transport, 41, "!c4 stats", &[]);
+    assert_eq!(
+        transport.last_message().text(),
+        "<@41> has 0 wins, 1 losses and 1 ties on this server."
+    );
+
+    // in a rematch everyone moves up a seat
+    react(&mut conn, &transport, 41, game.id, REMATCH_EMOJI);
+    let game = transport.last_message();
+    assert_eq!(game.embed().title, "Connect4 game #5 in progress");
+    assert_eq!(
+        game.embed().fields[0].value,
+        "**:red_circle: player41**\n:blue_circle: player12\n:green_circle: player31"
+    );
+
+    drop(conn);
+    fs::remove_file("protocol_test14.sqlite").expect("failed to remove temp database");
+}
+
+#[test]
+fn games_with_an_opening_or_handicap() {
+    let _ = fs::remove_file("protocol_test15.sqlite");
+    let mut conn =
+        persistency::initialize("protocol_test15.sqlite").expect("Failed to create database");
+    let transport = FakeTransport::new(BOT, GUILD);
+
+    assert_eq!(
+        parse_opening("!c4 challenge @player21 from 4453"),
+        Opening::Moves("4453".to_string())
+    );
+    assert_eq!(
+        parse_opening("!c4 challenge @player21 handicap 7"),
+        Opening::Handicap(Board::MAX_HANDICAP)
+    );
+    assert_eq!(
+        parse_opening("!c4 challenge @player21 handicap 0"),
+        Opening::None
+    );
+
+    say(
+        &mut conn,
+        &transport,
+        12,
+        "!c4 challenge @player21 from 445",
+        &[21],
+    );
+    let game = transport.last_message();
+    assert_eq!(game.embed().fields[1].value, "**:blue_circle: player21**");
+    let board = &game.embed().fields[2].value;
+    assert_eq!(board.matches(":red_circle:").count(), 2);
+    assert_eq!(board.matches(":blue_circle:").count(), 1);
+
+    // the game carries on from the opening
+    say(&mut conn, &transport, 12, "!c4 play 1", &[]);
+    assert_eq!(transport.message(game.id).embed(), game.embed());
+    say(&mut conn, &transport, 21, "!c4 play 1", &[]);
+    let game = transport.message(game.id);
+    assert_eq!(game.embed().fields[0].value, "**:red_circle: player12**");
+
+    // a rematch starts from the opening again, with the same colours
+    say(&mut conn, &transport, 12, "!c4 resign", &[]);
+    react(&mut conn, &transport, 21, game.id, REMATCH_EMOJI);
+    let rematch = transport.last_message();
+    assert_ne!(rematch.id, game.id);
+    assert_eq!(
+        rematch.embed().fields[1].value,
+        "**:blue_circle: player21**"
+    );
+    let board = &rematch.embed().fields[2].value;
+    assert_eq!(board.matches(":red_circle:").count(), 2);
+    assert_eq!(board.matches(":blue_circle:").count(), 1);
+
+    say(
+        &mut conn,
+        &transport,
+        31,
+        "!c4 challenge @player41 from 4444444",
+        &[41],
+    );
+    assert_eq!(
+        transport.last_message().text(),
+        "<@31>, the game can't start from those moves: move 7 is in column 4, which is full."
+    );
+    say(
+        &mut conn,
+        &transport,
+        31,
+        "!c4 challenge @player41 from 1212121",
+        &[41],
+    );
+    assert_eq!(
+        transport.last_message().text(),
+        "<@31>, those moves end the game before it starts."
+    );
+
+    // against the bot, the player gets the handicap and the bot moves first
+    say(
+        &mut conn,
+        &transport,
+        13,
+        "!c4 challenge @bot level 1 handicap 2",
+        &[BOT.0],
+    );
+    let game = transport.last_message();
+    let board = &game.embed().fields[2].value;
+    assert_eq!(
+        board.matches(":red_circle:").count() + board.matches(":blue_circle:").count(),
+        3
+    );
+    assert!(game.embed().fields[..2]
+        .iter()
+        .any(|field| field.value.starts_with("**") && field.value.ends_with("player13**")));
+
+    drop(conn);
+    fs::remove_file("protocol_test15.sqlite").expect("failed to remove temp database");
+}
@@ -0,0 +1,29 @@
+use crate::connect4::clock::unix_time;
+use crate::connect4::persistency;
+use crate::protocol;
+
+use discord::Discord;
+
+use std::thread;
+use std::time::Duration;
+
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// Periodically warns players who are running out of time, ends the games of
+// those who ran out and clears out abandoned games. Runs forever, so it's meant to have a thread of its own.
+pub fn run_clock_scheduler(discord: Discord, db_name: &str) {
+    let mut conn = persistency::initialize(db_name).expect("failed to initialize database");
+
+    loop {
+        let now = unix_time();
+        for (channel_id, response) in protocol::check_clocks(&conn, now) {
+            println!("Clock check replying with {:?}", response);
+            protocol::communicate_response(&mut conn, &discord, channel_id, &response);
+        }
+        for (channel_id, response) in protocol::sweep_abandoned_matches(&conn, now) {
+            println!("Abandonment sweep replying with {:?}", response);
+            protocol::communicate_response(&mut conn, &discord, channel_id, &response);
+        }
+        thread::sleep(CLOCK_CHECK_INTERVAL);
+    }
+}
@@ -0,0 +1,226 @@
+use discord::builders::{EmbedBuilder, EmbedFieldsBuilder};
+use discord::model::{Channel, ChannelId, MessageId, Permissions, ReactionEmoji, ServerId, UserId};
+use discord::Discord;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Discord(discord::Error),
+    NotFound,
+}
+
+impl From<discord::Error> for Error {
+    fn from(e: discord::Error) -> Error {
+        Error::Discord(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Discord(e) => write!(f, "discord error: {:?}", e),
+            Error::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Embed {
+    pub title: String,
+    pub color: u64,
+    pub fields: Vec<EmbedField>,
+}
+
+impl Embed {
+    pub fn new(title: &str, color: u64) -> Embed {
+        Embed {
+            title: title.to_string(),
+            color,
+            fields: vec![],
+        }
+    }
+
+    pub fn field(mut self, name: &str, value: &str, inline: bool) -> Embed {
+        self.fields.push(EmbedField {
+            name: name.to_string(),
+            value: value.to_string(),
+            inline,
+        });
+        self
+    }
+
+    fn build(&self, embed_builder: EmbedBuilder) -> EmbedBuilder {
+        embed_builder.title(&self.title).color(self.color).fields(
+            |fields_builder: EmbedFieldsBuilder| {
+                self.fields
+                    .iter()
+                    .fold(fields_builder, |fields_builder, field| {
+                        fields_builder.field(&field.name, &field.value, field.inline)
+                    })
+            },
+        )
+    }
+}
+
+// Everything the bot needs from the chat service it runs on. Emoji are plain
+// unicode strings.
+pub trait ChatTransport {
+    fn current_user(&self) -> Result<UserId>;
+
+    fn guild_of_channel(&self, channel_id: ChannelId) -> Result<ServerId>;
+
+    fn display_name(&self, server_id: ServerId, user_id: UserId) -> Result<String>;
+
+    // Moderators are the users allowed to manage messages in the guild
+    fn is_moderator(&self, server_id: ServerId, user_id: UserId) -> Result<bool>;
+
+    fn send_message(&self, channel_id: ChannelId, text: &str) -> Result<MessageId>;
+
+    fn send_embed(&self, channel_id: ChannelId, embed: &Embed) -> Result<MessageId>;
+
+    // Sends the embed in a direct message, which only the user can see
+    fn send_private_embed(&self, user_id: UserId, embed: &Embed) -> Result<MessageId>;
+
+    fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: &Embed,
+    ) -> Result<MessageId>;
+
+    fn add_reaction(&self, channel_id: ChannelId, message_id: MessageId, emoji: &str)
+        -> Result<()>;
+
+    // Removes the reaction of the given user, or the bot's own if there is none
+    fn remove_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: Option<UserId>,
+        emoji: &str,
+    ) -> Result<()>;
+
+    fn reacting_users(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>>;
+}
+
+impl ChatTransport for Discord {
+    fn current_user(&self) -> Result<UserId> {
+        Ok(self.get_current_user()?.id)
+    }
+
+    fn guild_of_channel(&self, channel_id: ChannelId) -> Result<ServerId> {
+        match self.get_channel(channel_id)? {
+            Channel::Public(public_channel) => Ok(public_channel.server_id),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn display_name(&self, server_id: ServerId, user_id: UserId) -> Result<String> {
+        Ok(self
+            .get_member(server_id, user_id)?
+            .display_name()
+            .to_string())
+    }
+
+    fn is_moderator(&self, server_id: ServerId, user_id: UserId) -> Result<bool> {
+        let server = self.get_server(server_id)?;
+        if server.owner_id == user_id {
+            return Ok(true);
+        }
+        let member = self.get_member(server_id, user_id)?;
+
+        // the @everyone role shares its id with the server
+        let permissions = server
+            .roles
+            .iter()
+            .filter(|role| role.id.0 == server_id.0 || member.roles.contains(&role.id))
+            .fold(Permissions::empty(), |acc, role| acc | role.permissions);
+
+        Ok(permissions.intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_MESSAGES))
+    }
+
+    fn send_message(&self, channel_id: ChannelId, text: &str) -> Result<MessageId> {
+        Ok(Discord::send_message(self, channel_id, text, "", false)?.id)
+    }
+
+    fn send_embed(&self, channel_id: ChannelId, embed: &Embed) -> Result<MessageId> {
+        Ok(Discord::send_embed(self, channel_id, "", |e| embed.build(e))?.id)
+    }
+
+    fn send_private_embed(&self, user_id: UserId, embed: &Embed) -> Result<MessageId> {
+        let channel = self.create_private_channel(user_id)?;
+        Ok(Discord::send_embed(self, channel.id, "", |e| embed.build(e))?.id)
+    }
+
+    fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: &Embed,
+    ) -> Result<MessageId> {
+        Ok(Discord::edit_embed(self, channel_id, message_id, |e| embed.build(e))?.id)
+    }
+
+    fn add_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<()> {
+        Ok(Discord::add_reaction(
+            self,
+            channel_id,
+            message_id,
+            ReactionEmoji::Unicode(emoji.to_string()),
+        )?)
+    }
+
+    fn remove_reaction(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: Option<UserId>,
+        emoji: &str,
+    ) -> Result<()> {
+        Ok(self.delete_reaction(
+            channel_id,
+            message_id,
+            user_id,
+            ReactionEmoji::Unicode(emoji.to_string()),
+        )?)
+    }
+
+    fn reacting_users(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>> {
+        let users = self.get_reactions(
+            channel_id,
+            message_id,
+            ReactionEmoji::Unicode(emoji.to_string()),
+            None,
+            None,
+        )?;
+        Ok(users.iter().map(|u| u.id).collect())
+    }
+}
+
+#[cfg(test)]
+pub mod fake;
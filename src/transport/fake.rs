@@ -0,0 +1,228 @@
+use super::{ChatTransport, Embed, Error, Result};
+
+use discord::model::{ChannelId, MessageId, ServerId, UserId};
+
+use std::cell::RefCell;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Content {
+    Text(String),
+    Embed(Embed),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FakeMessage {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub content: Content,
+    pub reactions: Vec<(String, UserId)>,
+    // The user a direct message went to
+    pub recipient: Option<UserId>,
+}
+
+impl FakeMessage {
+    pub fn text(&self) -> &str {
+        match &self.content {
+            Content::Text(text) => text,
+            Content::Embed(_) => panic!("expected a text message, found an embed"),
+        }
+    }
+
+    pub fn embed(&self) -> &Embed {
+        match &self.content {
+            Content::Embed(embed) => embed,
+            Content::Text(_) => panic!("expected an embed, found a text message"),
+        }
+    }
+
+    pub fn has_reaction(&self, emoji: &str, user_id: UserId) -> bool {
+        self.reactions
+            .iter()
+            .any(|(e, u)| e == emoji && *u == user_id)
+    }
+}
+
+// A chat that lives in memory, with every channel in the same guild. It keeps
+// the messages the bot sends so that tests can check them.
+pub struct FakeTransport {
+    bot_id: UserId,
+    guild_id: ServerId,
+    moderators: Vec<UserId>,
+    messages: RefCell<Vec<FakeMessage>>,
+}
+
+impl FakeTransport {
+    pub fn new(bot_id: UserId, guild_id: ServerId) -> FakeTransport {
+        FakeTransport {
+            bot_id,
+            guild_id,
+            moderators: vec![],
+            messages: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn add_moderator(&mut self, user_id: UserId) {
+        self.moderators.push(user_id);
+    }
+
+    pub fn messages(&self) -> Vec<FakeMessage> {
+        self.messages.borrow().clone()
+    }
+
+    pub fn message(&self, message_id: MessageId) -> FakeMessage {
+        self.messages
+            .borrow()
+            .iter()
+            .find(|m| m.id == message_id)
+            .expect("no message with that id")
+            .clone()
+    }
+
+    pub fn private_messages(&self, user_id: UserId) -> Vec<FakeMessage> {
+        self.messages
+            .borrow()
+            .iter()
+            .filter(|m| m.recipient == Some(user_id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn last_message(&self) -> FakeMessage {
+        self.messages
+            .borrow()
+            .last()
+            .expect("no messages were sent")
+            .clone()
+    }
+
+    // Adds the reaction of a user other than the bot
+    pub fn react(&self, message_id: MessageId, user_id: UserId, emoji: &str) {
+        self.with_message(message_id, |m| {
+            m.reactions.push((emoji.to_string(), user_id));
+        })
+        .expect("reacted to a message that doesn't exist");
+    }
+
+    fn with_message<T>(
+        &self,
+        message_id: MessageId,
+        f: impl FnOnce(&mut FakeMessage) -> T,
+    ) -> Result<T> {
+        let mut messages = self.messages.borrow_mut();
+        match messages.iter_mut().find(|m| m.id == message_id) {
+            None => Err(Error::NotFound),
+            Some(message) => Ok(f(message)),
+        }
+    }
+
+    fn push(&self, channel_id: ChannelId, content: Content) -> MessageId {
+        self.push_to(channel_id, None, content)
+    }
+
+    fn push_to(
+        &self,
+        channel_id: ChannelId,
+        recipient: Option<UserId>,
+        content: Content,
+    ) -> MessageId {
+        let mut messages = self.messages.borrow_mut();
+        let id = MessageId(1000 + messages.len() as u64);
+        messages.push(FakeMessage {
+            id,
+            channel_id,
+            content,
+            reactions: vec![],
+            recipient,
+        });
+        id
+    }
+}
+
+impl ChatTransport for FakeTransport {
+    fn current_user(&self) -> Result<UserId> {
+        Ok(self.bot_id)
+    }
+
+    fn guild_of_channel(&self, _channel_id: ChannelId) -> Result<ServerId> {
+        Ok(self.guild_id)
+    }
+
+    fn display_name(&self, _server_id: ServerId, user_id: UserId) -> Result<String> {
+        Ok(format!("player{}", user_id.0))
+    }
+
+    fn is_moderator(&self, _server_id: ServerId, user_id: UserId) -> Result<bool> {
+        Ok(self.moderators.contains(&user_id))
+    }
+
+    fn send_message(&self, channel_id: ChannelId, text: &str) -> Result<MessageId> {
+        Ok(self.push(channel_id, Content::Text(text.to_string())))
+    }
+
+    fn send_embed(&self, channel_id: ChannelId, embed: &Embed) -> Result<MessageId> {
+        Ok(self.push(channel_id, Content::Embed(embed.clone())))
+    }
+
+    // Direct messages get a channel of their own, numbered after the user
+    fn send_private_embed(&self, user_id: UserId, embed: &Embed) -> Result<MessageId> {
+        Ok(self.push_to(
+            ChannelId(user_id.0),
+            Some(user_id),
+            Content::Embed(embed.clone()),
+        ))
+    }
+
+    fn edit_embed(
+        &self,
+        _channel_id: ChannelId,
+        message_id: MessageId,
+        embed: &Embed,
+    ) -> Result<MessageId> {
+        self.with_message(message_id, |m| {
+            m.content = Content::Embed(embed.clone());
+            m.id
+        })
+    }
+
+    fn add_reaction(
+        &self,
+        _channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<()> {
+        let bot_id = self.bot_id;
+        self.with_message(message_id, |m| {
+            if !m.has_reaction(emoji, bot_id) {
+                m.reactions.push((emoji.to_string(), bot_id));
+            }
+        })
+    }
+
+    fn remove_reaction(
+        &self,
+        _channel_id: ChannelId,
+        message_id: MessageId,
+        user_id: Option<UserId>,
+        emoji: &str,
+    ) -> Result<()> {
+        let user_id = user_id.unwrap_or(self.bot_id);
+        self.with_message(message_id, |m| {
+            m.reactions.retain(|(e, u)| e != emoji || *u != user_id);
+        })
+    }
+
+    fn reacting_users(
+        &self,
+        _channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>> {
+        self.with_message(message_id, |m| {
+            m.reactions
+                .iter()
+                .filter(|(e, _)| e == emoji)
+                .map(|(_, u)| *u)
+                .collect()
+        })
+    }
+}